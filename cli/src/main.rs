@@ -0,0 +1,194 @@
+mod output;
+
+use std::path::PathBuf;
+use std::process;
+
+use lib::raytracer::*;
+use lib::loader;
+use lib::sampling::{Filter, Sampler};
+use lib::tonemap::{Encoding, Operator};
+
+use output::Format;
+
+const USAGE: &str = "\
+Usage: cli [OPTIONS]
+
+Renders a scene without opening a window and writes the image to disk.
+
+Options:
+    -w, --width <N>        Image width in pixels (default: 800)
+    -h, --height <N>       Image height in pixels (default: 600)
+    -s, --scene <FILE>     Scene description to render (default: the
+                           built-in demo scene)
+    -o, --output <PATH>    Output file, format is picked by the .png, .ppm
+                           or .pfm (floating point) extension. May be given
+                           several times
+                           (default: out.png)
+    -i, --integrator <I>   Rendering algorithm: whitted or path (default:
+                           from the scene, else whitted)
+        --max-depth <N>    Bounces of path tracing (default: from the
+                           scene, else 8)
+    -n, --samples <N>      Rays traced per pixel (default: from the scene,
+                           else 1)
+        --sampler <S>      Placement of the rays: grid, jittered, halton or
+                           sobol (default: from the scene, else grid)
+        --filter <F>       Reconstruction filter: box, tent, gaussian or
+                           mitchell (default: from the scene, else box)
+    -t, --tone-map <OP>    Tone mapping operator: clip, reinhard, aces or
+                           filmic (default: from the scene, else clip)
+    -e, --exposure <STOPS> Exposure adjustment in stops (default: from the
+                           scene, else 0)
+        --encoding <ENC>   Output transfer function: srgb or linear
+                           (default: from the scene, else srgb)
+        --help             Print this message";
+
+struct Args {
+    width: u32,
+    height: u32,
+    scene: Option<PathBuf>,
+    outputs: Vec<PathBuf>,
+    integrator: Option<Integrator>,
+    max_depth: Option<u32>,
+    samples: Option<u32>,
+    sampler: Option<Sampler>,
+    filter: Option<Filter>,
+    operator: Option<Operator>,
+    exposure: Option<f64>,
+    encoding: Option<Encoding>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+	width: 800,
+	height: 600,
+	scene: None,
+	outputs: vec![],
+	integrator: None,
+	max_depth: None,
+	samples: None,
+	sampler: None,
+	filter: None,
+	operator: None,
+	exposure: None,
+	encoding: None,
+    };
+
+    let mut it = std::env::args().skip(1);
+    while let Some(arg) = it.next() {
+	let mut value = || it.next().ok_or(format!("Missing value for {}", arg));
+	match arg.as_str() {
+	    "-w" | "--width" => args.width = parse_size(&value()?)?,
+	    "-h" | "--height" => args.height = parse_size(&value()?)?,
+	    "-s" | "--scene" => args.scene = Some(PathBuf::from(value()?)),
+	    "-o" | "--output" => args.outputs.push(PathBuf::from(value()?)),
+	    "-i" | "--integrator" => args.integrator = Some(value()?.parse()?),
+	    "--max-depth" => args.max_depth = Some(parse_depth(&value()?)?),
+	    "-n" | "--samples" => args.samples = Some(parse_count(&value()?)?),
+	    "--sampler" => args.sampler = Some(value()?.parse()?),
+	    "--filter" => args.filter = Some(value()?.parse()?),
+	    "-t" | "--tone-map" => args.operator = Some(value()?.parse()?),
+	    "-e" | "--exposure" => args.exposure = Some(parse_exposure(&value()?)?),
+	    "--encoding" => args.encoding = Some(value()?.parse()?),
+	    "--help" => {
+		println!("{}", USAGE);
+		process::exit(0);
+	    },
+	    _ => return Err(format!("Unknown argument: {}", arg)),
+	}
+    }
+
+    if args.outputs.is_empty() {
+	args.outputs.push(PathBuf::from("out.png"));
+    }
+    Ok(args)
+}
+
+fn parse_size(s: &str) -> Result<u32, String> {
+    match s.parse() {
+	Ok(0) | Err(_) => Err(format!("Invalid size: {}", s)),
+	Ok(n) => Ok(n),
+    }
+}
+
+fn parse_depth(s: &str) -> Result<u32, String> {
+    s.parse().map_err(|_| format!("Invalid depth: {}", s))
+}
+
+fn parse_count(s: &str) -> Result<u32, String> {
+    match s.parse() {
+	Ok(0) | Err(_) => Err(format!("Invalid sample count: {}", s)),
+	Ok(n) => Ok(n),
+    }
+}
+
+fn parse_exposure(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+	Ok(n) if n.is_finite() => Ok(n),
+	_ => Err(format!("Invalid exposure: {}", s)),
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let outputs = args.outputs.into_iter()
+	.map(|path| match Format::from_path(&path) {
+	    Some(format) => Ok((path, format)),
+	    None => Err(format!("Cannot infer image format of {}, expected .png, .ppm or .pfm", path.display())),
+	})
+	.collect::<Result<Vec<_>, _>>()?;
+
+    let scene = match &args.scene {
+	Some(path) => loader::load_file(path, args.width, args.height)
+	    .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?,
+	None => loader::load(include_str!("../../scenes/demo.toml"), args.width, args.height)
+	    .map_err(|e| format!("Failed to load the demo scene: {}", e))?,
+    };
+    let mut raytracer = Raytracer::new(scene);
+    let scene = &mut raytracer.scene;
+    if let Some(integrator) = args.integrator {
+	scene.integrator = integrator;
+    }
+    if let Some(depth) = args.max_depth {
+	match &mut scene.integrator {
+	    Integrator::Path { max_depth } => *max_depth = depth,
+	    Integrator::Whitted => return Err("--max-depth only applies to path tracing".to_owned()),
+	}
+    }
+    let sampling = &mut raytracer.scene.sampling;
+    if let Some(samples) = args.samples {
+	sampling.samples = samples;
+    }
+    if let Some(sampler) = args.sampler {
+	sampling.sampler = sampler;
+    }
+    if let Some(filter) = args.filter {
+	sampling.filter = filter;
+    }
+    let tone_map = &mut raytracer.scene.tone_map;
+    if let Some(operator) = args.operator {
+	tone_map.operator = operator;
+    }
+    if let Some(exposure) = args.exposure {
+	tone_map.exposure = exposure;
+    }
+    if let Some(encoding) = args.encoding {
+	tone_map.encoding = encoding;
+    }
+    let canvas = raytracer.render();
+
+    for (path, format) in outputs {
+	output::write(canvas, &path, format)
+	    .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|e| {
+	eprintln!("{}\n\n{}", e, USAGE);
+	process::exit(2);
+    });
+    if let Err(e) = run(args) {
+	eprintln!("{}", e);
+	process::exit(1);
+    }
+}
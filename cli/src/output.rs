@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use lib::raytracer::{Canvas, Pixel};
+use lib::object::Color;
+
+pub enum Format {
+    Png,
+    Ppm,
+    Pfm,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Format> {
+	match path.extension()?.to_str()?.to_lowercase().as_str() {
+	    "png" => Some(Format::Png),
+	    "ppm" => Some(Format::Ppm),
+	    "pfm" => Some(Format::Pfm),
+	    _ => None,
+	}
+    }
+}
+
+pub fn write(canvas: &Canvas, path: &Path, format: Format) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    match format {
+	Format::Png => write_png(canvas, &mut out)?,
+	Format::Ppm => write_ppm(canvas, &mut out)?,
+	Format::Pfm => write_pfm(canvas, &mut out)?,
+    }
+    out.flush()
+}
+
+/// Binary (P6) portable pixmap.
+pub fn write_ppm<W: Write>(canvas: &Canvas, out: &mut W) -> io::Result<()> {
+    let (width, height) = canvas.size();
+    write!(out, "P6\n{} {}\n255\n", width, height)?;
+    let data: Vec<u8> = canvas.pixels().flat_map(|Pixel(r, g, b)| vec![r, g, b]).collect();
+    out.write_all(&data)
+}
+
+/// Portable float map keeping the unclipped linear radiance.
+pub fn write_pfm<W: Write>(canvas: &Canvas, out: &mut W) -> io::Result<()> {
+    let (width, height) = canvas.size();
+    // Negative scale marks little-endian data
+    write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+    let colors: Vec<&Color> = canvas.iter().collect();
+    // Rows go bottom to top
+    for row in colors.chunks(width as usize).rev() {
+	for c in row {
+	    for channel in [c.r, c.g, c.b].iter() {
+		out.write_all(&(*channel as f32).to_le_bytes())?;
+	    }
+	}
+    }
+    Ok(())
+}
+
+pub fn write_png<W: Write>(canvas: &Canvas, out: &mut W) -> io::Result<()> {
+    let (width, height) = canvas.size();
+    let mut encoder = png::Encoder::new(out, width, height);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data: Vec<u8> = canvas.pixels().flat_map(|Pixel(r, g, b)| vec![r, g, b]).collect();
+    encoder.write_header()
+	.and_then(|mut writer| writer.write_image_data(&data))
+	.map_err(io::Error::other)
+}
@@ -0,0 +1,61 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use lib::raytracer::*;
+use lib::object::*;
+use lib::math::Vec3;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+
+/// Deterministic values in `[0, 1)`.
+fn lcg(state: &mut u64) -> f64 {
+    *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+    (*state >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Cloud of small random triangles in front of the camera.
+fn triangles(count: usize) -> Scene {
+    let mut scene = Scene::new(WIDTH, HEIGHT);
+    let mut state = 1;
+    let material = Material::new(Color::new(0xa0, 0xa0, 0xa0), 0.);
+    for _ in 0..count {
+	let mut point = || Vec3(lcg(&mut state) * 4. - 2., lcg(&mut state) * 3. - 1., lcg(&mut state) * 4. + 1.);
+	let a = point();
+	let offset = Vec3(lcg(&mut state) - 0.5, lcg(&mut state) - 0.5, lcg(&mut state) - 0.5) * 0.1;
+	let b = a + offset;
+	let c = a + Vec3(offset.1, offset.2, offset.0);
+	scene.add(Polygon::new(a, b, c, material.clone()));
+    }
+    scene.add_light(PointLight::new(Vec3(0., 3., 0.), 1.));
+    scene.add_light(AmbientLight::new(0.1));
+    scene
+}
+
+fn render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render");
+    group.sample_size(10);
+    for count in [1_000, 10_000, 100_000].iter() {
+	let mut raytracer = Raytracer::new(triangles(*count));
+	raytracer.scene.build();
+	group.bench_with_input(BenchmarkId::new("triangles", count), count, |b, _| {
+	    b.iter(|| {
+		raytracer.render();
+	    })
+	});
+    }
+    group.finish();
+}
+
+fn build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+    for count in [1_000, 10_000, 100_000].iter() {
+	group.bench_with_input(BenchmarkId::new("triangles", count), count, |b, count| {
+	    b.iter_batched(|| triangles(*count), |mut scene| scene.build(), criterion::BatchSize::LargeInput)
+	});
+    }
+    group.finish();
+}
+
+criterion_group!(benches, render, build);
+criterion_main!(benches);
@@ -0,0 +1,184 @@
+//! Radiance RGBE (`.hdr`) images.
+//!
+//! Both flat and run-length encoded scanlines are read, as long as the
+//! image is stored top to bottom and left to right (`-Y <height> +X
+//! <width>`), which is what every common tool writes. XYZE images and
+//! OpenEXR files are not supported.
+
+use std::fs;
+use std::path::Path;
+
+use super::image::{Error, Image};
+use super::object::Color;
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Image, Error> {
+    let path = path.as_ref();
+    let data = fs::read(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+    parse(&data, path)
+}
+
+/// Parses file contents, `file` is only used in error messages.
+pub fn parse(data: &[u8], file: &Path) -> Result<Image, Error> {
+    let error = |message: &str| Error::Format(file.to_owned(), message.to_owned());
+
+    let mut reader = Reader { data, pos: 0 };
+    let magic = reader.line().ok_or_else(|| error("missing header"))?;
+    if magic != "#?RADIANCE" && magic != "#?RGBE" {
+	return Err(error("not a Radiance HDR file"));
+    }
+    let mut exposure = 1.;
+    loop {
+	let line = reader.line().ok_or_else(|| error("unexpected end of header"))?;
+	if line.is_empty() {
+	    break;
+	}
+	if let Some(format) = line.strip_prefix("FORMAT=") {
+	    if format != "32-bit_rle_rgbe" {
+		return Err(Error::Format(file.to_owned(), format!("unsupported pixel format `{}`", format)));
+	    }
+	} else if let Some(value) = line.strip_prefix("EXPOSURE=") {
+	    // Pixels were multiplied by the exposure when written
+	    exposure *= value.trim().parse::<f64>().map_err(|_| error("invalid exposure"))?;
+	}
+    }
+
+    let resolution = reader.line().ok_or_else(|| error("missing resolution"))?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+	["-Y", h, "+X", w] => match (h.parse::<usize>(), w.parse::<usize>()) {
+	    (Ok(h), Ok(w)) if h > 0 && w > 0 => (h, w),
+	    _ => return Err(Error::Format(file.to_owned(), format!("invalid resolution `{}`", resolution))),
+	},
+	_ => return Err(Error::Format(file.to_owned(), format!("unsupported orientation `{}`", resolution))),
+    };
+
+    // Runs of run-length encoded lines hold at most 127 pixels in 2 bytes
+    // per channel, more pixels than that cannot be in the data
+    let size = match width.checked_mul(height) {
+	Some(size) if size / 16 <= reader.remaining() => size,
+	_ => return Err(Error::Format(file.to_owned(), format!("resolution `{}` too large for the pixel data", resolution))),
+    };
+    let mut pixels = Vec::with_capacity(size);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+	reader.scanline(&mut scanline).ok_or_else(|| error("truncated pixel data"))?;
+	pixels.extend(scanline.iter().map(|&rgbe| decode(rgbe).div(exposure)));
+    }
+    Ok(Image::new(width, height, pixels))
+}
+
+/// Shared exponent encoding of a pixel.
+fn decode([r, g, b, e]: [u8; 4]) -> Color {
+    if e == 0 {
+	return Color::default();
+    }
+    let f = 2f64.powi(e as i32 - 136);
+    Color::rgb(r as f64 * f, g as f64 * f, b as f64 * f)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Option<u8> {
+	let b = *self.data.get(self.pos)?;
+	self.pos += 1;
+	Some(b)
+    }
+
+    fn remaining(&self) -> usize {
+	self.data.len().saturating_sub(self.pos)
+    }
+
+    fn line(&mut self) -> Option<String> {
+	let rest = self.data.get(self.pos..)?;
+	let end = rest.iter().position(|&b| b == b'\n')?;
+	self.pos += end + 1;
+	Some(String::from_utf8_lossy(&rest[..end]).trim_end_matches('\r').to_owned())
+    }
+
+    fn scanline(&mut self, line: &mut [[u8; 4]]) -> Option<()> {
+	let start = [self.byte()?, self.byte()?, self.byte()?, self.byte()?];
+	// Run-length encoded lines start with 2, 2 and their width
+	let rle = start[0] == 2 && start[1] == 2 && start[2] & 0x80 == 0 && (8..0x8000).contains(&line.len());
+	if !rle {
+	    line[0] = start;
+	    for pixel in line[1..].iter_mut() {
+		*pixel = [self.byte()?, self.byte()?, self.byte()?, self.byte()?];
+	    }
+	    return Some(());
+	}
+	if ((start[2] as usize) << 8 | start[3] as usize) != line.len() {
+	    return None;
+	}
+	// Channels follow one another, each in runs and literal spans
+	for channel in 0..4 {
+	    let mut x = 0;
+	    while x < line.len() {
+		let count = self.byte()? as usize;
+		if count > 128 {
+		    let count = count - 128;
+		    let value = self.byte()?;
+		    for pixel in line.get_mut(x..x + count)? {
+			pixel[channel] = value;
+		    }
+		    x += count;
+		} else {
+		    if count == 0 {
+			return None;
+		    }
+		    for pixel in line.get_mut(x..x + count)? {
+			pixel[channel] = self.byte()?;
+		    }
+		    x += count;
+		}
+	    }
+	}
+	Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(resolution: &str) -> Vec<u8> {
+	format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution).into_bytes()
+    }
+
+    #[test]
+    fn parse_flat() {
+	let mut data = header("-Y 1 +X 2");
+	data.extend([128, 64, 0, 129, 0, 0, 0, 0]);
+	let image = parse(&data, Path::new("test.hdr")).unwrap();
+	assert_eq!((image.width(), image.height()), (2, 1));
+	assert_eq!(image.get(0, 0), Color::rgb(1., 0.5, 0.));
+	assert_eq!(image.get(1, 0), Color::default());
+    }
+
+    #[test]
+    fn parse_rle() {
+	let mut data = header("-Y 1 +X 8");
+	data.extend([2, 2, 0, 8]);
+	// Red: a run of eight, green: eight literals, blue and exponent runs
+	data.extend([136, 128]);
+	data.extend([8, 0, 16, 32, 48, 64, 80, 96, 112]);
+	data.extend([136, 0]);
+	data.extend([136, 129]);
+	let image = parse(&data, Path::new("test.hdr")).unwrap();
+	assert_eq!(image.get(0, 0), Color::rgb(1., 0., 0.));
+	assert_eq!(image.get(7, 0), Color::rgb(1., 112. / 128., 0.));
+    }
+
+    #[test]
+    fn parse_errors() {
+	let error = |data: &[u8]| parse(data, Path::new("test.hdr")).unwrap_err().to_string();
+	assert_eq!(error(b"P3\n"), "test.hdr: not a Radiance HDR file");
+	assert_eq!(error(b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n"), "test.hdr: unsupported pixel format `32-bit_rle_xyze`");
+	assert_eq!(error(&header("+Y 1 +X 2")), "test.hdr: unsupported orientation `+Y 1 +X 2`");
+	assert_eq!(error(&header("-Y 1 +X 2")), "test.hdr: truncated pixel data");
+	assert_eq!(error(&header("-Y 100000 +X 100000")), "test.hdr: resolution `-Y 100000 +X 100000` too large for the pixel data");
+	assert_eq!(error(&header("-Y 4294967296 +X 4294967296")), "test.hdr: resolution `-Y 4294967296 +X 4294967296` too large for the pixel data");
+    }
+}
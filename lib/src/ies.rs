@@ -0,0 +1,239 @@
+//! IES LM-63 photometric profiles.
+//!
+//! Only type C photometry, the one used for architectural luminaires, is
+//! supported. Candela values are scaled so the brightest direction gets
+//! `1.`, a profile then shapes a light without changing its intensity.
+//! Tilt data is skipped.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    Parse { file: PathBuf, line: usize, message: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    Error::Io(file, e) => write!(f, "{}: {}", file.display(), e),
+	    Error::Parse { file, line, message } => write!(f, "{}:{}: {}", file.display(), line + 1, message),
+	}
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Distribution of light around a luminaire. Vertical angles are measured
+/// from the nadir, the axis the luminaire points along, horizontal ones
+/// around it.
+#[derive(Debug)]
+pub struct Profile {
+    /// Ascending angles in degrees.
+    vertical: Vec<f64>,
+    horizontal: Vec<f64>,
+    /// Relative intensity for every horizontal angle, then every vertical
+    /// one.
+    values: Vec<Vec<f64>>,
+}
+
+impl Profile {
+    /// Relative intensity in `[0, 1]` towards the given angles in radians.
+    pub fn intensity(&self, vertical: f64, horizontal: f64) -> f64 {
+	let v = vertical.to_degrees();
+	let h = self.fold(horizontal.to_degrees().rem_euclid(360.));
+	let (v0, v1, tv) = match lerp_index(&self.vertical, v) {
+	    Some(i) => i,
+	    None => return 0.,
+	};
+	let (h0, h1, th) = lerp_index(&self.horizontal, h).unwrap_or((0, 0, 0.));
+	let at = |h: usize| self.values[h][v0] * (1. - tv) + self.values[h][v1] * tv;
+	at(h0) * (1. - th) + at(h1) * th
+    }
+
+    /// Maps a horizontal angle onto the measured range using the
+    /// symmetry implied by its last angle.
+    fn fold(&self, h: f64) -> f64 {
+	let last = self.horizontal.last().copied().unwrap_or(0.);
+	if last == 0. {
+	    0.
+	} else if last == 90. {
+	    let h = if h > 180. { 360. - h } else { h };
+	    if h > 90. { 180. - h } else { h }
+	} else if last == 180. && h > 180. {
+	    360. - h
+	} else {
+	    h
+	}
+    }
+}
+
+/// Neighbouring indices of `x` in the ascending `angles` and the
+/// interpolation factor between them, `None` outside of the range.
+fn lerp_index(angles: &[f64], x: f64) -> Option<(usize, usize, f64)> {
+    let first = *angles.first()?;
+    let last = *angles.last()?;
+    if x < first || x > last {
+	return None;
+    }
+    let i = angles.partition_point(|&a| a <= x);
+    if i == 0 || i == angles.len() {
+	let i = i.saturating_sub(1);
+	return Some((i, i, 0.));
+    }
+    let (a, b) = (angles[i - 1], angles[i]);
+    Some((i - 1, i, (x - a) / (b - a)))
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Profile, Error> {
+    let path = path.as_ref();
+    let src = fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+    parse(&src, path)
+}
+
+/// Parses profile source, `file` is only used in error messages.
+pub fn parse(src: &str, file: &Path) -> Result<Profile, Error> {
+    let error = |line: usize, message: String| Error::Parse { file: file.to_owned(), line, message };
+
+    let lines: Vec<&str> = src.lines().collect();
+    let tilt_line = lines.iter().position(|l| l.trim_start().starts_with("TILT="))
+	.ok_or_else(|| error(lines.len().saturating_sub(1), "missing `TILT=` line".to_owned()))?;
+    let tilt = lines[tilt_line].trim_start()["TILT=".len()..].trim();
+
+    // Numbers may be split between lines arbitrarily
+    let mut numbers = Numbers {
+	file,
+	tokens: lines.iter().enumerate().skip(tilt_line + 1)
+	    .flat_map(|(line, text)| text.split(|c: char| c.is_whitespace() || c == ',')
+		      .filter(|t| !t.is_empty())
+		      .map(move |t| (line, t)))
+	    .collect(),
+	pos: 0,
+	line: tilt_line,
+    };
+
+    match tilt {
+	"NONE" => {},
+	"INCLUDE" => {
+	    numbers.next("lamp to luminaire geometry")?;
+	    let pairs = numbers.count("number of tilt angles")?;
+	    for _ in 0..2 * pairs {
+		numbers.next("tilt value")?;
+	    }
+	},
+	_ => return Err(error(tilt_line, format!("tilt data in a separate file `{}` is not supported", tilt))),
+    }
+
+    for what in ["number of lamps", "lumens per lamp", "candela multiplier"] {
+	numbers.next(what)?;
+    }
+    let vertical_count = numbers.count("number of vertical angles")?;
+    let horizontal_count = numbers.count("number of horizontal angles")?;
+    if numbers.next("photometric type")? != 1. {
+	return Err(numbers.error("only type C photometry is supported".to_owned()));
+    }
+    for what in ["units type", "width", "length", "height", "ballast factor", "future use", "input watts"] {
+	numbers.next(what)?;
+    }
+
+    let vertical = numbers.angles(vertical_count, "vertical angle")?;
+    let horizontal = numbers.angles(horizontal_count, "horizontal angle")?;
+    let mut values = (0..horizontal_count)
+	.map(|_| (0..vertical_count).map(|_| numbers.next("candela value")).collect::<Result<Vec<_>, _>>())
+	.collect::<Result<Vec<_>, _>>()?;
+
+    let max = values.iter().flatten().cloned().fold(0., f64::max);
+    if max > 0. {
+	values.iter_mut().flatten().for_each(|v| *v /= max);
+    }
+    Ok(Profile { vertical, horizontal, values })
+}
+
+/// Whitespace or comma separated numbers with the lines they are on.
+struct Numbers<'a> {
+    file: &'a Path,
+    tokens: Vec<(usize, &'a str)>,
+    pos: usize,
+    /// Line of the last number read.
+    line: usize,
+}
+
+impl Numbers<'_> {
+    fn error(&self, message: String) -> Error {
+	Error::Parse { file: self.file.to_owned(), line: self.line, message }
+    }
+
+    fn next(&mut self, what: &str) -> Result<f64, Error> {
+	match self.tokens.get(self.pos) {
+	    Some(&(line, token)) => {
+		self.pos += 1;
+		self.line = line;
+		token.parse().map_err(|_| self.error(format!("invalid {} `{}`", what, token)))
+	    },
+	    None => Err(self.error(format!("unexpected end of file, expected {}", what))),
+	}
+    }
+
+    fn count(&mut self, what: &str) -> Result<usize, Error> {
+	let n = self.next(what)?;
+	if n < 0. || n.fract() != 0. {
+	    return Err(self.error(format!("invalid {} `{}`", what, n)));
+	}
+	Ok(n as usize)
+    }
+
+    fn angles(&mut self, count: usize, what: &str) -> Result<Vec<f64>, Error> {
+	if count == 0 {
+	    return Err(self.error(format!("expected at least one {}", what)));
+	}
+	let angles = (0..count).map(|_| self.next(what)).collect::<Result<Vec<_>, _>>()?;
+	if angles.windows(2).any(|w| w[0] >= w[1]) {
+	    return Err(self.error(format!("{}s are not ascending", what)));
+	}
+	Ok(angles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILE: &str = "IESNA:LM-63-2002
+[TEST] downlight
+TILT=NONE
+1 1000 1 3 2 1 2 0 0 0
+1 1 100
+0 45 90
+0 90
+200 100 0
+200 50 0
+";
+
+    #[test]
+    fn parse_profile() {
+	let profile = parse(PROFILE, Path::new("test.ies")).unwrap();
+	let deg = |d: f64| d.to_radians();
+	assert!((profile.intensity(0., 0.) - 1.).abs() < 1e-9);
+	assert!((profile.intensity(deg(45.), 0.) - 0.5).abs() < 1e-9);
+	assert!((profile.intensity(deg(45.), deg(90.)) - 0.25).abs() < 1e-9);
+	assert!((profile.intensity(deg(45.), deg(45.)) - 0.375).abs() < 1e-9);
+	// Quadrant symmetry
+	assert!((profile.intensity(deg(45.), deg(270.)) - 0.25).abs() < 1e-9);
+	assert!((profile.intensity(deg(45.), deg(180.)) - 0.5).abs() < 1e-9);
+	assert_eq!(profile.intensity(deg(120.), 0.), 0.);
+    }
+
+    #[test]
+    fn parse_errors() {
+	let error = |src: &str| parse(src, Path::new("test.ies")).unwrap_err().to_string();
+	assert_eq!(error("IESNA:LM-63-2002\n"), "test.ies:1: missing `TILT=` line");
+	assert_eq!(error("TILT=NONE\n1 1000 1 3 2 3 2 0 0 0\n"), "test.ies:2: only type C photometry is supported");
+	assert_eq!(error("TILT=NONE\n1 1000 1 3 2 1 2 0 0 0\n1 1 100\n0 45 90\n0 90\n200 100\n"),
+		   "test.ies:6: unexpected end of file, expected candela value");
+	assert_eq!(error("TILT=NONE\n1 1000 1 3 2 1 2 0 0 0\n1 1 100\n0 90 45\n"),
+		   "test.ies:4: vertical angles are not ascending");
+    }
+}
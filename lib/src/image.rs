@@ -0,0 +1,149 @@
+//! Images used as textures and environment maps.
+//!
+//! PNG and JPEG files hold 8-bit values, Radiance HDR files
+//! linear radiance. The format is told by the contents of a file rather
+//! than its extension.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::hdr;
+use super::object::Color;
+use super::tonemap::Encoding;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    Format(PathBuf, String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    Error::Io(file, e) => write!(f, "{}: {}", file.display(), e),
+	    Error::Format(file, message) => write!(f, "{}: {}", file.display(), message),
+	}
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Linear values of every pixel, row by row from the top.
+#[derive(Debug)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    /// Image from `width · height` pixels given row by row.
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+	assert!(width > 0 && height > 0 && pixels.len() == width * height, "Image size does not match its pixels");
+	Self { width, height, pixels }
+    }
+
+    /// Image from 8-bit RGB values with the given transfer function.
+    pub fn from_rgb8(width: usize, height: usize, data: &[u8], encoding: Encoding) -> Self {
+	let decode = |v: u8| {
+	    let x = v as f64 / 255.;
+	    match encoding {
+		Encoding::Linear => x,
+		Encoding::Srgb if x <= 0.04045 => x / 12.92,
+		Encoding::Srgb => ((x + 0.055) / 1.055).powf(2.4),
+	    }
+	};
+	// Decoding every pixel on its own would repeat the same 256 values
+	let table: Vec<f64> = (0..=255).map(decode).collect();
+	let pixels = data.chunks_exact(3)
+	    .map(|p| Color::rgb(table[p[0] as usize], table[p[1] as usize], table[p[2] as usize]))
+	    .collect();
+	Self::new(width, height, pixels)
+    }
+
+    pub fn width(&self) -> usize {
+	self.width
+    }
+
+    pub fn height(&self) -> usize {
+	self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+	self.pixels[y * self.width + x]
+    }
+}
+
+/// Loads an image. `encoding` is the transfer function of 8-bit formats,
+/// `Srgb` for colors and `Linear` for data such as normal maps.
+pub fn load<P: AsRef<Path>>(path: P, encoding: Encoding) -> Result<Image, Error> {
+    let path = path.as_ref();
+    let data = fs::read(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+    if data.starts_with(b"\x89PNG") {
+	parse_png(&data, path, encoding)
+    } else if data.starts_with(&[0xff, 0xd8]) {
+	parse_jpeg(&data, path, encoding)
+    } else if data.starts_with(b"#?") {
+	hdr::parse(&data, path)
+    } else {
+	Err(Error::Format(path.to_owned(), "unknown image format, expected PNG, JPEG or Radiance HDR".to_owned()))
+    }
+}
+
+fn parse_png(data: &[u8], file: &Path, encoding: Encoding) -> Result<Image, Error> {
+    let error = |e: png::DecodingError| Error::Format(file.to_owned(), e.to_string());
+    // Palettes are expanded and 16-bit channels cut to 8 bits by default
+    let (info, mut reader) = png::Decoder::new(data).read_info().map_err(error)?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf).map_err(error)?;
+    let channels = match info.color_type {
+	png::ColorType::Grayscale => 1,
+	png::ColorType::GrayscaleAlpha => 2,
+	png::ColorType::RGB => 3,
+	png::ColorType::RGBA => 4,
+	png::ColorType::Indexed => return Err(Error::Format(file.to_owned(), "unexpected palette image".to_owned())),
+    };
+    let (width, height) = (info.width as usize, info.height as usize);
+    // Alpha is dropped
+    let rgb: Vec<u8> = buf.chunks(info.line_size)
+	.flat_map(|line| line[..width * channels].chunks_exact(channels))
+	.flat_map(|p| if channels < 3 { [p[0]; 3] } else { [p[0], p[1], p[2]] })
+	.collect();
+    Ok(Image::from_rgb8(width, height, &rgb, encoding))
+}
+
+fn parse_jpeg(data: &[u8], file: &Path, encoding: Encoding) -> Result<Image, Error> {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let buf = decoder.decode().map_err(|e| Error::Format(file.to_owned(), e.to_string()))?;
+    let info = decoder.info().expect("decoded JPEG has no header");
+    let rgb: Vec<u8> = match info.pixel_format {
+	jpeg_decoder::PixelFormat::L8 => buf.iter().flat_map(|&v| [v; 3]).collect(),
+	jpeg_decoder::PixelFormat::RGB24 => buf,
+	jpeg_decoder::PixelFormat::CMYK32 => return Err(Error::Format(file.to_owned(), "unexpected CMYK image".to_owned())),
+    };
+    Ok(Image::from_rgb8(info.width as usize, info.height as usize, &rgb, encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_formats() {
+	let png = parse_png(include_bytes!("../../scenes/textures/grid.png"), Path::new("grid.png"), Encoding::Linear).unwrap();
+	assert_eq!((png.width(), png.height()), (256, 128));
+	assert_eq!(png.get(128, 5), Color::new(220, 40, 40));
+
+	let jpeg = parse_jpeg(include_bytes!("../../scenes/textures/tiles.jpg"), Path::new("tiles.jpg"), Encoding::Srgb).unwrap();
+	assert_eq!((jpeg.width(), jpeg.height()), (128, 128));
+	// Compression only keeps colors close, sRGB 140 is about 0.26 linear
+	let tile = jpeg.get(16, 16);
+	assert!((tile.b - 0.26).abs() < 0.02 && tile.r < 0.05, "{:?}", tile);
+
+	assert_eq!(
+	    parse_png(b"\x89PNG\r\n\x1a\n", Path::new("a.png"), Encoding::Srgb).unwrap_err().to_string(),
+	    "a.png: unexpected EOF");
+    }
+}
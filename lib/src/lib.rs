@@ -1,3 +1,14 @@
 pub mod raytracer;
 pub mod object;
 pub mod math;
+pub mod loader;
+pub mod obj;
+pub mod ies;
+pub mod hdr;
+pub mod image;
+pub mod texture;
+pub mod noise;
+pub mod medium;
+pub mod sky;
+pub mod sampling;
+pub mod tonemap;
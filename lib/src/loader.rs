@@ -0,0 +1,1356 @@
+//! Declarative scene description in TOML.
+//!
+//! ```toml
+//! [camera]
+//! position = [0, 0.5, -1]
+//! direction = [0, 0, 1]
+//!
+//! [materials.floor]
+//! color = [80, 80, 80]
+//! reflection = 0.5
+//!
+//! [[plane]]
+//! normal = [0, -1, 0]
+//! offset = 0
+//! material = "floor"
+//!
+//! [[sphere]]
+//! center = [0, 0, 0.5]
+//! radius = 0.5
+//! material = { color = [0, 0, 255], shine = 100, reflection = 0.6 }
+//!
+//! [[point_light]]
+//! position = [0, 0.2, 2]
+//! intensity = 1.0
+//! ```
+//!
+//! Objects are given as arrays of tables, one per kind: `sphere`,
+//! `plane`, `polygon`, `mesh`. Lights likewise: `point_light`,
+//! `direct_light`, `ambient_light` and the area lights `rect_light`,
+//! `disk_light`, `sphere_light` casting soft shadows. The `intensity`
+//! of area lights is the radiance of their surface, that of point and
+//! spot lights is their radiant intensity in W/sr, it falls off with
+//! the squared distance unless `attenuation` gives the constant, linear
+//! and quadratic terms of the falloff. An optional `range` fades their
+//! light out. A `spot_light` shines from `position` along `direction`
+//! into a cone given by `inner_angle` and `outer_angle` in degrees, its
+//! light can be shaped by an IES `profile` file resolved relative to
+//! the scene file. An `environment_light` surrounds the scene with an
+//! equirectangular Radiance HDR image from `file`, turned around the
+//! vertical by `rotation` degrees, and shows it as the background.
+//!
+//! The optional `[sky]` table surrounds the scene with a clear daylight
+//! sky and the sun at `sun_elevation` degrees above the horizon and
+//! `sun_azimuth` degrees from `+z` towards `+x`. The haze is set by
+//! `turbidity` between 2 and 10, the ground below the horizon reflects
+//! `ground_albedo` of the light and `sun_size` is the angular diameter of
+//! the sun in degrees. `intensity` scales sky and sun alike.
+//!
+//! The optional `[fog]` table fills the space between objects with a
+//! medium dimming light by `absorption` and `scattering` per unit of
+//! length. Scattered light is tinted by `color` and its direction follows
+//! the Henyey-Greenstein phase function with an `anisotropy` from `-1`
+//! (back) to `1` (forward). A material `medium` of the same keys fills a
+//! closed object instead, whose surface only bounds it. Either varies by
+//! a `density` table: a grid of `size = [nx, ny, nz]` points from corner
+//! `min` to `max` holding `values` along `x` first, or filled with
+//! fractal `noise` of that many features per unit and `octaves`. Rays
+//! leaving the scene, such as those towards lights at infinite distance,
+//! only pass through fog inside the box around all objects but planes.
+//!
+//! A `material` is either the name of an entry of `[materials]` or an
+//! inline table. Surfaces have a `color`, a `roughness` from `0` for
+//! polished to `1` for matte (the default) and are `metallic` from `0`
+//! for dielectrics to `1` for metals, which reflect in their own color.
+//! The older `shine` gives the roughness matching a Phong exponent
+//! instead. A mirror image is mixed in by `reflection` and blurred by
+//! `reflection_roughness` from `0` (the default) to `1`, Whitted shading
+//! averages `reflection_samples` rays over the blur. A material may be
+//! see-through with `transparency` and an index of refraction `ior`.
+//! Objects glow with an `emission` color times `emission_strength` from
+//! the front of their surface and light the scene like area lights.
+//! Translucent materials such as skin, wax or marble take a `subsurface`
+//! table: light enters them and scatters inside, traveling the
+//! `mean_free_path = [r, g, b]` between collisions per channel with an
+//! optional `anisotropy`, until it leaves about `color` of itself. Only
+//! path tracing follows it, Whitted shading shows the material diffuse.
+//! A `texture` table maps a PNG, JPEG or Radiance HDR image `file` onto
+//! the surface, multiplied by `color` which defaults to white. It
+//! tiles with `wrap` mode `repeat`, `mirror` or `clamp`, is filtered with
+//! `interpolation` `bilinear` or `nearest` and repeats `scale = [u, v]`
+//! times per unit of texture coordinates. Spheres are mapped by longitude
+//! and latitude, planes by distance along the plane, polygons span the
+//! unit square's lower half and meshes use their own coordinates.
+//!
+//! Instead of a `file` a texture may be a procedural `pattern`:
+//! `checker`, `noise`, `fbm`, `turbulence`, `marble`, `wood` or
+//! `voronoi`, blending between two `colors` (black and white by default)
+//! with `scale` features per unit. Noise patterns add up `octaves` layers
+//! of detail. Patterns are carved from space with `mapping = "solid"` or
+//! laid out by texture coordinates with `"uv"`. A `roughness_texture`,
+//! `metallic_texture` or `reflection_texture` multiplies the value of its
+//! key by its brightness, their images hold linear values.
+//!
+//! Surface detail bends the shading normal, either by a tangent space
+//! `normal_map` image or by the slopes of a `bump_map` whose brightness
+//! is the height, scaled by `bump_strength`. Slopes are per unit of
+//! texture coordinates, so stretching the texture keeps bumps as steep.
+//! Both are texture tables whose images hold linear values.
+//!
+//! A `mesh` is imported from a Wavefront OBJ `file`, resolved relative to
+//! the scene file, and placed with optional `scale` and `offset`. Its own
+//! materials are used unless `material` is given.
+//!
+//! The `[camera]` looks from `position` along `direction` at a screen
+//! `distance` ahead, magnified by `zoom`. It is a pinhole unless its lens
+//! has an `aperture` radius, then only things `focus_distance` ahead are
+//! sharp. Out of focus highlights take the shape of the aperture, a disk
+//! or with `aperture_blades` a regular polygon turned by
+//! `aperture_rotation` degrees.
+//!
+//! Other cameras set a `projection`: `orthographic` with parallel rays
+//! from a screen `view_size` units across (2 by default), `fisheye`
+//! seeing `fov` degrees (180 by default) across a circle filling the
+//! image with an `equidistant` or `equisolid` `mapping`, or
+//! `equirectangular` seeing all around as a 360° panorama. Only the
+//! default `perspective` projection has a lens.
+//!
+//! The optional `[integrator]` table picks the rendering algorithm by
+//! `type`: `whitted` ray tracing or `path` tracing, which takes the
+//! number of bounces as `max_depth`.
+//!
+//! The optional `[sampling]` table sets the number of `samples` traced
+//! per pixel, the `sampler` placing them (`grid`, `jittered`, `halton` or
+//! `sobol`) and the reconstruction `filter` (`box`, `tent`, `gaussian` or
+//! `mitchell`).
+//!
+//! The optional `[tone_map]` table picks how radiance is shown: an
+//! `operator` (`clip`, `reinhard`, `aces` or `filmic`), an `exposure` in
+//! stops and an output `encoding` (`srgb`, the default, or `linear`).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+use toml::Spanned;
+
+use super::math::{Aabb, Bokeh, Camera, CameraTransform, Fisheye, Projection, Vec3};
+use super::medium::{DensityGrid, Medium};
+use super::noise;
+use super::object::*;
+use super::raytracer::{Integrator, Scene};
+use super::obj;
+use super::ies;
+use super::image::{self, Image};
+use super::sky::Sky;
+use super::texture::{ImageTexture, Procedural, Texture};
+use super::sampling::Sampling;
+use super::tonemap::{Encoding, ToneMap};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Invalid { line: usize, col: usize, message: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    Error::Io(e) => write!(f, "{}", e),
+	    Error::Parse(e) => write!(f, "{}", e),
+	    Error::Invalid { line, col, message } => write!(f, "{} at line {} column {}", message, line + 1, col + 1),
+	}
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self { Error::Io(e) }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self { Error::Parse(e) }
+}
+
+pub fn load_file<P: AsRef<Path>>(path: P, width: u32, height: u32) -> Result<Scene, Error> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    load_relative(&fs::read_to_string(path)?, dir, width, height)
+}
+
+/// Loads a scene, files it refers to are looked up in the current
+/// directory.
+pub fn load(src: &str, width: u32, height: u32) -> Result<Scene, Error> {
+    load_relative(src, Path::new(""), width, height)
+}
+
+/// Loads a scene, files it refers to are looked up in `dir`.
+pub fn load_relative(src: &str, dir: &Path, width: u32, height: u32) -> Result<Scene, Error> {
+    let desc: SceneDesc = toml::from_str(src)?;
+    let src = Source(src);
+    let mut scene = Scene::new(width, height);
+
+    if let Some(camera) = &desc.camera {
+	camera.apply(&src, &mut scene.camera)?;
+    }
+    if let Some(integrator) = &desc.integrator {
+	scene.integrator = integrator.integrator(&src)?;
+    }
+    if let Some(sampling) = &desc.sampling {
+	sampling.apply(&src, &mut scene.sampling)?;
+    }
+    if let Some(tone_map) = &desc.tone_map {
+	tone_map.apply(&src, &mut scene.tone_map)?;
+    }
+
+    // Materials sharing an image share its pixels
+    let mut images = HashMap::new();
+    let mut material = |key: &str, m: &Spanned<MaterialRef>| -> Result<Material, Error> {
+	match m.get_ref() {
+	    MaterialRef::Inline(material) => material.material(&src, key, dir, &mut images),
+	    MaterialRef::Named(name) => match desc.materials.get(name) {
+		Some(material) => material.material(&src, &format!("materials.{}", name), dir, &mut images),
+		None => Err(src.error(m, format!("unknown material `{}` for key `{}`", name, key))),
+	    },
+	}
+    };
+
+    // Objects are added in the order they appear in the file, which
+    // decides between coincident surfaces. Tables carry no position,
+    // so entries are ordered by one of their values
+    let mut objects: Vec<(usize, Box<dyn Object + Send + Sync>)> = vec![];
+    for s in desc.sphere.iter() {
+	objects.push((s.center.start(), Box::new(Sphere::new(
+	    (*s.center.get_ref()).into(),
+	    src.positive("sphere.radius", &s.radius)?,
+	    material("sphere.material", &s.material)?))));
+    }
+    for p in desc.plane.iter() {
+	objects.push((p.normal.start(), Box::new(Plane::new(
+	    src.direction("plane.normal", &p.normal)?,
+	    p.offset,
+	    material("plane.material", &p.material)?))));
+    }
+    for p in desc.polygon.iter() {
+	let [a, b, c] = *p.vertices.get_ref();
+	objects.push((p.vertices.start(), Box::new(Polygon::new(
+	    a.into(), b.into(), c.into(),
+	    material("polygon.material", &p.material)?))));
+    }
+    for m in desc.mesh.iter() {
+	let mut mesh = obj::load(dir.join(m.file.get_ref()))
+	    .map_err(|e| src.error(&m.file, format!("failed to load mesh: {}", e)))?;
+	if let Some(material_ref) = &m.material {
+	    mesh = mesh.with_material(material("mesh.material", material_ref)?);
+	}
+	let scale = match &m.scale {
+	    Some(scale) => src.positive("mesh.scale", scale)?,
+	    None => 1.,
+	};
+	let offset = m.offset.map_or(Vec3(0., 0., 0.), Vec3::from);
+	objects.push((m.file.start(), Box::new(mesh.transform(scale, offset))));
+    }
+    objects.sort_by_key(|(start, _)| *start);
+    for (_, obj) in objects {
+	scene.add_boxed(obj);
+    }
+
+    for l in desc.point_light.iter() {
+	let intensity = src.non_negative("point_light.intensity", &l.intensity)?;
+	let mut light = match l.color {
+	    Some(color) => PointLight::new_color(l.position.into(), intensity, color.into()),
+	    None => PointLight::new(l.position.into(), intensity),
+	};
+	light.set_attenuation(src.attenuation("point_light", &l.attenuation, &l.range)?);
+	scene.add_light(light);
+    }
+    for l in desc.direct_light.iter() {
+	let direction = src.direction("direct_light.direction", &l.direction)?;
+	let intensity = src.non_negative("direct_light.intensity", &l.intensity)?;
+	scene.add_light(match l.color {
+	    Some(color) => DirectLight::new_color(direction, intensity, color.into()),
+	    None => DirectLight::new(direction, intensity),
+	});
+    }
+    let mut profiles: HashMap<&str, Arc<ies::Profile>> = HashMap::new();
+    for l in desc.spot_light.iter() {
+	let direction = src.direction("spot_light.direction", &l.direction)?;
+	let intensity = src.non_negative("spot_light.intensity", &l.intensity)?;
+	let outer = src.check("spot_light.outer_angle", &l.outer_angle, (0. ..=180.).contains(l.outer_angle.get_ref()), "an angle between 0 and 180")?;
+	let inner = match &l.inner_angle {
+	    Some(inner) => src.check("spot_light.inner_angle", inner, (0. ..=outer).contains(inner.get_ref()), "an angle between 0 and `outer_angle`")?,
+	    None => outer,
+	};
+	let mut light = match l.color {
+	    Some(color) => SpotLight::new_color(l.position.into(), direction, inner.to_radians(), outer.to_radians(), intensity, color.into()),
+	    None => SpotLight::new(l.position.into(), direction, inner.to_radians(), outer.to_radians(), intensity),
+	};
+	light.set_attenuation(src.attenuation("spot_light", &l.attenuation, &l.range)?);
+	if let Some(file) = &l.profile {
+	    let profile = match profiles.get(file.get_ref().as_str()) {
+		Some(profile) => profile.clone(),
+		None => {
+		    let profile = Arc::new(ies::load(dir.join(file.get_ref()))
+			.map_err(|e| src.error(file, format!("failed to load profile: {}", e)))?);
+		    profiles.insert(file.get_ref(), profile.clone());
+		    profile
+		},
+	    };
+	    light.set_profile(profile);
+	}
+	scene.add_light(light);
+    }
+    for l in desc.rect_light.iter() {
+	let intensity = src.non_negative("rect_light.intensity", &l.intensity)?;
+	let (u, v) = (src.direction("rect_light.u", &l.u)?, src.direction("rect_light.v", &l.v)?);
+	if u.cross(v).len() < f64::EPSILON {
+	    return Err(src.error(&l.v, "expected a vector not parallel to `rect_light.u` for key `rect_light.v`".to_owned()));
+	}
+	let mut light = match l.color {
+	    Some(color) => RectLight::new_color(l.center.into(), u, v, intensity, color.into()),
+	    None => RectLight::new(l.center.into(), u, v, intensity),
+	};
+	if let Some(samples) = &l.samples {
+	    light.set_samples(src.count("rect_light.samples", samples)?);
+	}
+	scene.add_light(light);
+    }
+    for l in desc.disk_light.iter() {
+	let intensity = src.non_negative("disk_light.intensity", &l.intensity)?;
+	let normal = src.direction("disk_light.normal", &l.normal)?;
+	let radius = src.positive("disk_light.radius", &l.radius)?;
+	let mut light = match l.color {
+	    Some(color) => DiskLight::new_color(l.center.into(), normal, radius, intensity, color.into()),
+	    None => DiskLight::new(l.center.into(), normal, radius, intensity),
+	};
+	if let Some(samples) = &l.samples {
+	    light.set_samples(src.count("disk_light.samples", samples)?);
+	}
+	scene.add_light(light);
+    }
+    for l in desc.sphere_light.iter() {
+	let intensity = src.non_negative("sphere_light.intensity", &l.intensity)?;
+	let radius = src.positive("sphere_light.radius", &l.radius)?;
+	let mut light = match l.color {
+	    Some(color) => SphereLight::new_color(l.center.into(), radius, intensity, color.into()),
+	    None => SphereLight::new(l.center.into(), radius, intensity),
+	};
+	if let Some(samples) = &l.samples {
+	    light.set_samples(src.count("sphere_light.samples", samples)?);
+	}
+	scene.add_light(light);
+    }
+    for l in desc.environment_light.iter() {
+	let intensity = src.non_negative("environment_light.intensity", &l.intensity)?;
+	let image = image::load(dir.join(l.file.get_ref()), Encoding::Srgb)
+	    .map_err(|e| src.error(&l.file, format!("failed to load environment: {}", e)))?;
+	let mut light = EnvironmentLight::new(Arc::new(image), intensity);
+	if let Some(rotation) = l.rotation {
+	    light.set_rotation(rotation.to_radians());
+	}
+	if let Some(samples) = &l.samples {
+	    light.set_samples(src.count("environment_light.samples", samples)?);
+	}
+	scene.add_light(light);
+    }
+    if let Some(fog) = &desc.fog {
+	scene.fog = Some(fog.medium(&src, "fog")?);
+    }
+    if let Some(sky) = &desc.sky {
+	let (environment, sun) = sky.lights(&src)?;
+	scene.add_light(environment);
+	scene.add_light(sun);
+    }
+    for l in desc.ambient_light.iter() {
+	let intensity = src.non_negative("ambient_light.intensity", &l.intensity)?;
+	scene.add_light(match l.color {
+	    Some(color) => AmbientLight::new_color(intensity, color.into()),
+	    None => AmbientLight::new(intensity),
+	});
+    }
+
+    Ok(scene)
+}
+
+/// Scene file text, used to point semantic errors at the offending value.
+struct Source<'a>(&'a str);
+
+impl Source<'_> {
+    fn error<T>(&self, at: &Spanned<T>, message: String) -> Error {
+	let before = &self.0[..at.start()];
+	let line = before.matches('\n').count();
+	let col = before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+	Error::Invalid { line, col, message }
+    }
+
+    fn check(&self, key: &str, v: &Spanned<f64>, ok: bool, expected: &str) -> Result<f64, Error> {
+	if ok {
+	    Ok(*v.get_ref())
+	} else {
+	    Err(self.error(v, format!("expected {}, got {} for key `{}`", expected, v.get_ref(), key)))
+	}
+    }
+
+    fn positive(&self, key: &str, v: &Spanned<f64>) -> Result<f64, Error> {
+	self.check(key, v, *v.get_ref() > 0., "a positive number")
+    }
+
+    fn non_negative(&self, key: &str, v: &Spanned<f64>) -> Result<f64, Error> {
+	self.check(key, v, *v.get_ref() >= 0., "a non-negative number")
+    }
+
+    fn attenuation(&self, key: &str, terms: &Option<Spanned<[f64; 3]>>, range: &Option<Spanned<f64>>) -> Result<Attenuation, Error> {
+	let mut attenuation = match terms {
+	    Some(terms) => {
+		let [constant, linear, quadratic] = *terms.get_ref();
+		if terms.get_ref().iter().any(|&t| t < 0.) || constant + linear + quadratic <= 0. {
+		    return Err(self.error(terms, format!("expected non-negative terms, not all zero, for key `{}.attenuation`", key)));
+		}
+		Attenuation::new(constant, linear, quadratic)
+	    },
+	    None => Attenuation::inverse_square(),
+	};
+	if let Some(range) = range {
+	    attenuation.range = Some(self.positive(&format!("{}.range", key), range)?);
+	}
+	Ok(attenuation)
+    }
+
+    fn count(&self, key: &str, v: &Spanned<u32>) -> Result<u32, Error> {
+	if *v.get_ref() == 0 {
+	    return Err(self.error(v, format!("expected a positive number, got 0 for key `{}`", key)));
+	}
+	Ok(*v.get_ref())
+    }
+
+    fn fraction(&self, key: &str, v: &Spanned<f64>) -> Result<f64, Error> {
+	self.check(key, v, (0. ..=1.).contains(v.get_ref()), "a number between 0 and 1")
+    }
+
+    fn direction(&self, key: &str, v: &Spanned<Vector>) -> Result<Vec3, Error> {
+	let dir: Vec3 = (*v.get_ref()).into();
+	if dir.len() < f64::EPSILON {
+	    return Err(self.error(v, format!("expected a non-zero vector for key `{}`", key)));
+	}
+	Ok(dir)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    camera: Option<CameraDesc>,
+    integrator: Option<IntegratorDesc>,
+    sampling: Option<SamplingDesc>,
+    tone_map: Option<ToneMapDesc>,
+    sky: Option<SkyDesc>,
+    fog: Option<MediumDesc>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDesc>,
+    #[serde(default)]
+    sphere: Vec<SphereDesc>,
+    #[serde(default)]
+    plane: Vec<PlaneDesc>,
+    #[serde(default)]
+    polygon: Vec<PolygonDesc>,
+    #[serde(default)]
+    mesh: Vec<MeshDesc>,
+    #[serde(default)]
+    point_light: Vec<PointLightDesc>,
+    #[serde(default)]
+    direct_light: Vec<DirectLightDesc>,
+    #[serde(default)]
+    ambient_light: Vec<AmbientLightDesc>,
+    #[serde(default)]
+    spot_light: Vec<SpotLightDesc>,
+    #[serde(default)]
+    rect_light: Vec<RectLightDesc>,
+    #[serde(default)]
+    disk_light: Vec<DiskLightDesc>,
+    #[serde(default)]
+    sphere_light: Vec<SphereLightDesc>,
+    #[serde(default)]
+    environment_light: Vec<EnvironmentLightDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    position: Option<Vector>,
+    direction: Option<Spanned<Vector>>,
+    distance: Option<Spanned<f64>>,
+    zoom: Option<Spanned<f64>>,
+    aperture: Option<Spanned<f64>>,
+    focus_distance: Option<Spanned<f64>>,
+    aperture_blades: Option<Spanned<u32>>,
+    aperture_rotation: Option<f64>,
+    projection: Option<Spanned<String>>,
+    mapping: Option<Spanned<String>>,
+    fov: Option<Spanned<f64>>,
+    view_size: Option<Spanned<f64>>,
+}
+
+impl CameraDesc {
+    fn apply(&self, src: &Source, camera: &mut Camera) -> Result<(), Error> {
+	if let Some(position) = self.position {
+	    camera.set_position(position.into());
+	}
+	if let Some(direction) = &self.direction {
+	    camera.set_direction(src.direction("camera.direction", direction)?);
+	}
+	if let Some(distance) = &self.distance {
+	    camera.transform(CameraTransform::ScaleDistance(src.positive("camera.distance", distance)?));
+	}
+	if let Some(zoom) = &self.zoom {
+	    camera.transform(CameraTransform::ScaleScreen(1. / src.positive("camera.zoom", zoom)?));
+	}
+	let projection = self.projection(src)?;
+	let only = |at: &Spanned<f64>, key: &str, kind: &str| Err(src.error(at, format!("key `camera.{}` only applies to {} cameras", key, kind)));
+	match (&projection, &self.view_size) {
+	    (Projection::Orthographic { .. }, _) | (_, None) => (),
+	    (_, Some(size)) => return only(size, "view_size", "orthographic"),
+	}
+	match (&projection, &self.fov, &self.mapping) {
+	    (Projection::Fisheye { .. }, _, _) | (_, None, None) => (),
+	    (_, Some(fov), _) => return only(fov, "fov", "fisheye"),
+	    (_, None, Some(mapping)) => return Err(src.error(mapping, "key `camera.mapping` only applies to fisheye cameras".to_owned())),
+	}
+	match (&projection, &self.aperture) {
+	    (Projection::Perspective, _) | (_, None) => (),
+	    (_, Some(aperture)) => return only(aperture, "aperture", "perspective"),
+	}
+	camera.set_projection(projection);
+	if let Some(aperture) = &self.aperture {
+	    let focus_distance = match &self.focus_distance {
+		Some(distance) => src.positive("camera.focus_distance", distance)?,
+		None => camera.focus_distance(),
+	    };
+	    camera.set_lens(src.non_negative("camera.aperture", aperture)?, focus_distance);
+	} else if let Some(distance) = &self.focus_distance {
+	    return Err(src.error(distance, "key `camera.focus_distance` only applies to lenses with an `aperture`".to_owned()));
+	}
+	match &self.aperture_blades {
+	    Some(blades) if *blades.get_ref() < 3 => {
+		return Err(src.error(blades, format!("expected at least 3 blades, got {} for key `camera.aperture_blades`", blades.get_ref())));
+	    },
+	    Some(blades) => camera.set_bokeh(Bokeh::Polygon {
+		blades: *blades.get_ref(),
+		rotation: self.aperture_rotation.unwrap_or(0.).to_radians(),
+	    }),
+	    None => (),
+	}
+	Ok(())
+    }
+
+    fn projection(&self, src: &Source) -> Result<Projection, Error> {
+	let kind = match &self.projection {
+	    Some(kind) => kind,
+	    None => return Ok(Projection::Perspective),
+	};
+	match kind.get_ref().as_str() {
+	    "perspective" => Ok(Projection::Perspective),
+	    "orthographic" => Ok(Projection::Orthographic {
+		size: match &self.view_size {
+		    Some(size) => src.positive("camera.view_size", size)?,
+		    None => 2.,
+		},
+	    }),
+	    "fisheye" => {
+		let mapping = match self.mapping.as_ref().map(|m| (m, m.get_ref().as_str())) {
+		    None | Some((_, "equidistant")) => Fisheye::Equidistant,
+		    Some((_, "equisolid")) => Fisheye::Equisolid,
+		    Some((m, other)) => return Err(src.error(m, format!("unknown fisheye mapping `{}`, expected one of `equidistant`, `equisolid` for key `camera.mapping`", other))),
+		};
+		let fov = match &self.fov {
+		    Some(fov) => src.check("camera.fov", fov, *fov.get_ref() > 0. && *fov.get_ref() <= 360., "an angle above 0 up to 360")?,
+		    None => 180.,
+		};
+		Ok(Projection::Fisheye { mapping, fov: fov.to_radians() })
+	    },
+	    "equirectangular" => Ok(Projection::Equirectangular),
+	    other => Err(src.error(kind, format!("unknown projection `{}`, expected one of `perspective`, `orthographic`, `fisheye`, `equirectangular` for key `camera.projection`", other))),
+	}
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IntegratorDesc {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    max_depth: Option<Spanned<u32>>,
+}
+
+impl IntegratorDesc {
+    fn integrator(&self, src: &Source) -> Result<Integrator, Error> {
+	let integrator = self.kind.get_ref().parse()
+	    .map_err(|e| src.error(&self.kind, format!("{} for key `integrator.type`", e)))?;
+	match (integrator, &self.max_depth) {
+	    (Integrator::Path { .. }, Some(max_depth)) => Ok(Integrator::Path { max_depth: *max_depth.get_ref() }),
+	    (Integrator::Whitted, Some(max_depth)) => Err(src.error(max_depth, "key `integrator.max_depth` only applies to path tracing".to_owned())),
+	    (integrator, None) => Ok(integrator),
+	}
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SamplingDesc {
+    samples: Option<Spanned<u32>>,
+    sampler: Option<Spanned<String>>,
+    filter: Option<Spanned<String>>,
+}
+
+impl SamplingDesc {
+    fn apply(&self, src: &Source, sampling: &mut Sampling) -> Result<(), Error> {
+	if let Some(samples) = &self.samples {
+	    sampling.samples = src.count("sampling.samples", samples)?;
+	}
+	if let Some(sampler) = &self.sampler {
+	    sampling.sampler = sampler.get_ref().parse()
+		.map_err(|e| src.error(sampler, format!("{} for key `sampling.sampler`", e)))?;
+	}
+	if let Some(filter) = &self.filter {
+	    sampling.filter = filter.get_ref().parse()
+		.map_err(|e| src.error(filter, format!("{} for key `sampling.filter`", e)))?;
+	}
+	Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ToneMapDesc {
+    operator: Option<Spanned<String>>,
+    exposure: Option<f64>,
+    encoding: Option<Spanned<String>>,
+}
+
+impl ToneMapDesc {
+    fn apply(&self, src: &Source, tone_map: &mut ToneMap) -> Result<(), Error> {
+	if let Some(operator) = &self.operator {
+	    tone_map.operator = operator.get_ref().parse()
+		.map_err(|e| src.error(operator, format!("{} for key `tone_map.operator`", e)))?;
+	}
+	if let Some(exposure) = self.exposure {
+	    tone_map.exposure = exposure;
+	}
+	if let Some(encoding) = &self.encoding {
+	    tone_map.encoding = encoding.get_ref().parse()
+		.map_err(|e| src.error(encoding, format!("{} for key `tone_map.encoding`", e)))?;
+	}
+	Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    color: Option<ColorDesc>,
+    texture: Option<Spanned<TextureDesc>>,
+    shine: Option<Spanned<i32>>,
+    roughness: Option<Spanned<f64>>,
+    roughness_texture: Option<Spanned<TextureDesc>>,
+    metallic: Option<Spanned<f64>>,
+    metallic_texture: Option<Spanned<TextureDesc>>,
+    reflection: Option<Spanned<f64>>,
+    reflection_texture: Option<Spanned<TextureDesc>>,
+    reflection_roughness: Option<Spanned<f64>>,
+    reflection_samples: Option<Spanned<u32>>,
+    normal_map: Option<Spanned<TextureDesc>>,
+    bump_map: Option<Spanned<TextureDesc>>,
+    bump_strength: Option<Spanned<f64>>,
+    transparency: Option<Spanned<f64>>,
+    ior: Option<Spanned<f64>>,
+    emission: Option<ColorDesc>,
+    emission_strength: Option<Spanned<f64>>,
+    medium: Option<MediumDesc>,
+    subsurface: Option<Spanned<SubsurfaceDesc>>,
+}
+
+impl MaterialDesc {
+    /// Textures are looked up in `dir`, images already loaded are taken
+    /// from `images`.
+    fn material(&self, src: &Source, key: &str, dir: &Path, images: &mut HashMap<(String, Encoding), Arc<Image>>) -> Result<Material, Error> {
+	let reflection = match &self.reflection {
+	    Some(r) => src.fraction(&format!("{}.reflection", key), r)?,
+	    None => 0.,
+	};
+	let color = self.color.map_or(Color::white(), Color::from);
+	let mut material = match (&self.shine, &self.roughness) {
+	    (Some(_), Some(roughness)) => return Err(src.error(roughness, format!("key `{}.roughness` conflicts with `{}.shine`", key, key))),
+	    (Some(shine), None) => Material::new_shine(color, *shine.get_ref(), reflection),
+	    (None, Some(roughness)) => Material { roughness: src.fraction(&format!("{}.roughness", key), roughness)?, ..Material::new(color, reflection) },
+	    (None, None) => Material::new(color, reflection),
+	};
+	if let Some(metallic) = &self.metallic {
+	    material.metallic = src.fraction(&format!("{}.metallic", key), metallic)?;
+	}
+	if let Some(roughness) = &self.reflection_roughness {
+	    material.reflection_roughness = src.fraction(&format!("{}.reflection_roughness", key), roughness)?;
+	}
+	if let Some(samples) = &self.reflection_samples {
+	    material.reflection_samples = src.count(&format!("{}.reflection_samples", key), samples)?;
+	}
+	if let Some(texture) = &self.texture {
+	    material.texture = Some(texture.get_ref().texture(src, &format!("{}.texture", key), texture, Encoding::Srgb, dir, images)?);
+	}
+	if let Some(texture) = &self.roughness_texture {
+	    material.roughness_texture = Some(texture.get_ref().texture(src, &format!("{}.roughness_texture", key), texture, Encoding::Linear, dir, images)?);
+	}
+	if let Some(texture) = &self.metallic_texture {
+	    material.metallic_texture = Some(texture.get_ref().texture(src, &format!("{}.metallic_texture", key), texture, Encoding::Linear, dir, images)?);
+	}
+	if let Some(texture) = &self.reflection_texture {
+	    material.reflection_texture = Some(texture.get_ref().texture(src, &format!("{}.reflection_texture", key), texture, Encoding::Srgb, dir, images)?);
+	}
+	// Normals and heights are stored as plain numbers
+	material.relief = match (&self.normal_map, &self.bump_map, &self.bump_strength) {
+	    (Some(_), Some(bump), _) => return Err(src.error(bump, format!("key `{}.bump_map` conflicts with `{}.normal_map`", key, key))),
+	    (_, None, Some(strength)) => return Err(src.error(strength, format!("key `{}.bump_strength` only applies to bump maps", key))),
+	    (Some(normal), None, None) => Some(Relief::Normal(normal.get_ref().texture(src, &format!("{}.normal_map", key), normal, Encoding::Linear, dir, images)?)),
+	    (None, Some(bump), strength) => {
+		let strength = match strength {
+		    Some(strength) => src.non_negative(&format!("{}.bump_strength", key), strength)?,
+		    None => 1.,
+		};
+		Some(Relief::Bump(bump.get_ref().texture(src, &format!("{}.bump_map", key), bump, Encoding::Linear, dir, images)?, strength))
+	    },
+	    (None, None, None) => None,
+	};
+	if let Some(transparency) = &self.transparency {
+	    material.transparency = src.fraction(&format!("{}.transparency", key), transparency)?;
+	}
+	if let Some(ior) = &self.ior {
+	    material.ior = src.positive(&format!("{}.ior", key), ior)?;
+	}
+	material.emission = match (self.emission, &self.emission_strength) {
+	    (None, Some(strength)) => return Err(src.error(strength, format!("key `{}.emission_strength` only applies to glowing materials", key))),
+	    (Some(emission), Some(strength)) => Color::from(emission).mul_float(src.non_negative(&format!("{}.emission_strength", key), strength)?),
+	    (Some(emission), None) => Color::from(emission),
+	    (None, None) => Color::default(),
+	};
+	if let Some(medium) = &self.medium {
+	    material.medium = Some(Arc::new(medium.medium(src, &format!("{}.medium", key))?));
+	}
+	if let Some(subsurface) = &self.subsurface {
+	    if self.medium.is_some() {
+		return Err(src.error(subsurface, format!("key `{}.subsurface` conflicts with `{}.medium`", key, key)));
+	    }
+	    if self.transparency.is_some() {
+		return Err(src.error(subsurface, format!("key `{}.subsurface` conflicts with `{}.transparency`", key, key)));
+	    }
+	    material.subsurface = Some(subsurface.get_ref().subsurface(src, &format!("{}.subsurface", key))?);
+	}
+	Ok(material)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureDesc {
+    file: Option<Spanned<String>>,
+    wrap: Option<Spanned<String>>,
+    interpolation: Option<Spanned<String>>,
+    pattern: Option<Spanned<String>>,
+    colors: Option<Spanned<[ColorDesc; 2]>>,
+    octaves: Option<Spanned<u32>>,
+    mapping: Option<Spanned<String>>,
+    scale: Option<Spanned<ScaleDesc>>,
+}
+
+/// One factor for every axis or one per texture coordinate.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f64),
+    Axes([f64; 2]),
+}
+
+impl TextureDesc {
+    /// Images are decoded with `encoding`, see `MaterialDesc::material`
+    /// for `dir` and `images`.
+    fn texture(&self, src: &Source, key: &str, at: &Spanned<TextureDesc>, encoding: Encoding, dir: &Path, images: &mut HashMap<(String, Encoding), Arc<Image>>) -> Result<Arc<dyn Texture + Send + Sync>, Error> {
+	let only = |value: Option<&Spanned<String>>, name: &str, kind: &str| match value {
+	    Some(value) => Err(src.error(value, format!("key `{}.{}` only applies to {} textures", key, name, kind))),
+	    None => Ok(()),
+	};
+	let scale = match &self.scale {
+	    Some(scale) => {
+		let (u, v) = match *scale.get_ref() {
+		    ScaleDesc::Uniform(s) => (s, s),
+		    ScaleDesc::Axes([u, v]) => (u, v),
+		};
+		if u <= 0. || v <= 0. {
+		    return Err(src.error(scale, format!("expected positive scales, got {} and {} for key `{}.scale`", u, v, key)));
+		}
+		Some((scale, u, v))
+	    },
+	    None => None,
+	};
+
+	match (&self.file, &self.pattern) {
+	    (Some(file), None) => {
+		only(self.mapping.as_ref(), "mapping", "procedural")?;
+		if let Some(colors) = &self.colors {
+		    return Err(src.error(colors, format!("key `{}.colors` only applies to procedural textures", key)));
+		}
+		if let Some(octaves) = &self.octaves {
+		    return Err(src.error(octaves, format!("key `{}.octaves` only applies to procedural textures", key)));
+		}
+		let cache_key = (file.get_ref().clone(), encoding);
+		let image = match images.get(&cache_key) {
+		    Some(image) => image.clone(),
+		    None => {
+			let image = Arc::new(image::load(dir.join(file.get_ref()), encoding)
+			    .map_err(|e| src.error(file, format!("failed to load texture: {}", e)))?);
+			images.insert(cache_key, image.clone());
+			image
+		    },
+		};
+		let mut texture = ImageTexture::new(image);
+		if let Some(wrap) = &self.wrap {
+		    texture.wrap = wrap.get_ref().parse()
+			.map_err(|e| src.error(wrap, format!("{} for key `{}.wrap`", e, key)))?;
+		}
+		if let Some(interpolation) = &self.interpolation {
+		    texture.interpolation = interpolation.get_ref().parse()
+			.map_err(|e| src.error(interpolation, format!("{} for key `{}.interpolation`", e, key)))?;
+		}
+		if let Some((_, u, v)) = scale {
+		    texture.scale = (u, v);
+		}
+		Ok(Arc::new(texture))
+	    },
+	    (None, Some(pattern)) => {
+		only(self.wrap.as_ref(), "wrap", "image")?;
+		only(self.interpolation.as_ref(), "interpolation", "image")?;
+		let pattern = pattern.get_ref().parse()
+		    .map_err(|e| src.error(pattern, format!("{} for key `{}.pattern`", e, key)))?;
+		let colors = match &self.colors {
+		    Some(colors) => colors.get_ref().map(Color::from),
+		    None => [Color::default(), Color::white()],
+		};
+		let mut texture = Procedural::new(pattern, colors);
+		if let Some(octaves) = &self.octaves {
+		    texture.octaves = src.count(&format!("{}.octaves", key), octaves)?;
+		}
+		if let Some(mapping) = &self.mapping {
+		    texture.mapping = mapping.get_ref().parse()
+			.map_err(|e| src.error(mapping, format!("{} for key `{}.mapping`", e, key)))?;
+		}
+		if let Some((at, u, v)) = scale {
+		    if u != v {
+			return Err(src.error(at, format!("expected a single scale for key `{}.scale` of a procedural texture", key)));
+		    }
+		    texture.scale = u;
+		}
+		Ok(Arc::new(texture))
+	    },
+	    (Some(_), Some(pattern)) => Err(src.error(pattern, format!("key `{}.pattern` conflicts with `{}.file`", key, key))),
+	    (None, None) => Err(src.error(at, format!("expected a `file` or a `pattern` for key `{}`", key))),
+	}
+    }
+}
+
+enum MaterialRef {
+    Named(String),
+    Inline(Box<MaterialDesc>),
+}
+
+impl<'de> Deserialize<'de> for MaterialRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+	struct MaterialRefVisitor;
+
+	impl<'de> Visitor<'de> for MaterialRefVisitor {
+	    type Value = MaterialRef;
+
+	    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("a material name or a material table")
+	    }
+
+	    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+		Ok(MaterialRef::Named(v.to_owned()))
+	    }
+
+	    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+		MaterialDesc::deserialize(de::value::MapAccessDeserializer::new(map)).map(|m| MaterialRef::Inline(Box::new(m)))
+	    }
+	}
+
+	deserializer.deserialize_any(MaterialRefVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereDesc {
+    center: Spanned<Vector>,
+    radius: Spanned<f64>,
+    material: Spanned<MaterialRef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlaneDesc {
+    normal: Spanned<Vector>,
+    #[serde(default)]
+    offset: f64,
+    material: Spanned<MaterialRef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolygonDesc {
+    vertices: Spanned<[Vector; 3]>,
+    material: Spanned<MaterialRef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    file: Spanned<String>,
+    scale: Option<Spanned<f64>>,
+    offset: Option<Vector>,
+    material: Option<Spanned<MaterialRef>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PointLightDesc {
+    position: Vector,
+    intensity: Spanned<f64>,
+    color: Option<ColorDesc>,
+    attenuation: Option<Spanned<[f64; 3]>>,
+    range: Option<Spanned<f64>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DirectLightDesc {
+    direction: Spanned<Vector>,
+    intensity: Spanned<f64>,
+    color: Option<ColorDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AmbientLightDesc {
+    intensity: Spanned<f64>,
+    color: Option<ColorDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpotLightDesc {
+    position: Vector,
+    direction: Spanned<Vector>,
+    inner_angle: Option<Spanned<f64>>,
+    outer_angle: Spanned<f64>,
+    intensity: Spanned<f64>,
+    color: Option<ColorDesc>,
+    attenuation: Option<Spanned<[f64; 3]>>,
+    range: Option<Spanned<f64>>,
+    profile: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RectLightDesc {
+    center: Vector,
+    u: Spanned<Vector>,
+    v: Spanned<Vector>,
+    intensity: Spanned<f64>,
+    color: Option<ColorDesc>,
+    samples: Option<Spanned<u32>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DiskLightDesc {
+    center: Vector,
+    normal: Spanned<Vector>,
+    radius: Spanned<f64>,
+    intensity: Spanned<f64>,
+    color: Option<ColorDesc>,
+    samples: Option<Spanned<u32>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SphereLightDesc {
+    center: Vector,
+    radius: Spanned<f64>,
+    intensity: Spanned<f64>,
+    color: Option<ColorDesc>,
+    samples: Option<Spanned<u32>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EnvironmentLightDesc {
+    file: Spanned<String>,
+    intensity: Spanned<f64>,
+    rotation: Option<f64>,
+    samples: Option<Spanned<u32>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SkyDesc {
+    sun_elevation: Spanned<f64>,
+    sun_azimuth: Option<f64>,
+    turbidity: Option<Spanned<f64>>,
+    ground_albedo: Option<Spanned<f64>>,
+    sun_size: Option<Spanned<f64>>,
+    intensity: Option<Spanned<f64>>,
+}
+
+impl SkyDesc {
+    fn lights(&self, src: &Source) -> Result<(EnvironmentLight, SunLight), Error> {
+	let elevation = src.check("sky.sun_elevation", &self.sun_elevation, (0. ..=90.).contains(self.sun_elevation.get_ref()), "an angle between 0 and 90")?;
+	let mut sky = Sky::new(elevation.to_radians(), self.sun_azimuth.unwrap_or(0.).to_radians());
+	if let Some(turbidity) = &self.turbidity {
+	    sky.turbidity = src.check("sky.turbidity", turbidity, (2. ..=10.).contains(turbidity.get_ref()), "a number between 2 and 10")?;
+	}
+	if let Some(albedo) = &self.ground_albedo {
+	    sky.ground_albedo = src.fraction("sky.ground_albedo", albedo)?;
+	}
+	if let Some(size) = &self.sun_size {
+	    sky.sun_size = src.positive("sky.sun_size", size)?;
+	}
+	let intensity = match &self.intensity {
+	    Some(intensity) => src.non_negative("sky.intensity", intensity)?,
+	    None => 1.,
+	};
+	Ok((sky.environment(intensity), sky.sun(intensity)))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MediumDesc {
+    absorption: Option<Spanned<f64>>,
+    scattering: Option<Spanned<f64>>,
+    color: Option<ColorDesc>,
+    anisotropy: Option<Spanned<f64>>,
+    density: Option<Spanned<DensityDesc>>,
+}
+
+impl MediumDesc {
+    fn medium(&self, src: &Source, key: &str) -> Result<Medium, Error> {
+	let coefficient = |name: &str, v: &Option<Spanned<f64>>| match v {
+	    Some(v) => src.non_negative(&format!("{}.{}", key, name), v),
+	    None => Ok(0.),
+	};
+	let mut medium = Medium::new(coefficient("absorption", &self.absorption)?, coefficient("scattering", &self.scattering)?);
+	if let Some(color) = self.color {
+	    medium.color = color.into();
+	}
+	if let Some(g) = &self.anisotropy {
+	    medium.anisotropy = src.check(&format!("{}.anisotropy", key), g, g.get_ref().abs() < 1., "a number between -1 and 1")?;
+	}
+	if let Some(density) = &self.density {
+	    medium.density = Some(Arc::new(density.get_ref().grid(src, &format!("{}.density", key), density)?));
+	}
+	Ok(medium)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubsurfaceDesc {
+    mean_free_path: Spanned<[f64; 3]>,
+    anisotropy: Option<Spanned<f64>>,
+}
+
+impl SubsurfaceDesc {
+    fn subsurface(&self, src: &Source, key: &str) -> Result<Subsurface, Error> {
+	let [r, g, b] = *self.mean_free_path.get_ref();
+	if [r, g, b].iter().any(|&d| d <= 0.) {
+	    return Err(src.error(&self.mean_free_path, format!("expected positive distances for key `{}.mean_free_path`", key)));
+	}
+	let mut subsurface = Subsurface::new(Color::rgb(r, g, b));
+	if let Some(g) = &self.anisotropy {
+	    subsurface.anisotropy = src.check(&format!("{}.anisotropy", key), g, g.get_ref().abs() < 1., "a number between -1 and 1")?;
+	}
+	Ok(subsurface)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DensityDesc {
+    min: Vector,
+    max: Vector,
+    size: Spanned<[usize; 3]>,
+    values: Option<Spanned<Vec<f64>>>,
+    noise: Option<Spanned<f64>>,
+    octaves: Option<Spanned<u32>>,
+}
+
+impl DensityDesc {
+    /// `at` spans the whole table, for errors about missing keys.
+    fn grid(&self, src: &Source, key: &str, at: &Spanned<DensityDesc>) -> Result<DensityGrid, Error> {
+	let (min, max): (Vec3, Vec3) = (self.min.into(), self.max.into());
+	if (0..3).any(|axis| min[axis] >= max[axis]) {
+	    return Err(src.error(at, format!("expected `min` below `max` on every axis for key `{}`", key)));
+	}
+	let size = *self.size.get_ref();
+	if size.iter().any(|&s| s < 2) {
+	    return Err(src.error(&self.size, format!("expected at least 2 points per side for key `{}.size`", key)));
+	}
+	let bounds = Aabb::new(min, max);
+	match (&self.values, &self.noise, &self.octaves) {
+	    (Some(_), Some(noise), _) => Err(src.error(noise, format!("key `{}.noise` conflicts with `{}.values`", key, key))),
+	    (_, None, Some(octaves)) => Err(src.error(octaves, format!("key `{}.octaves` only applies to noise", key))),
+	    (Some(values), None, None) => {
+		let count = size[0] * size[1] * size[2];
+		if values.get_ref().len() != count {
+		    return Err(src.error(values, format!("expected {} values, got {} for key `{}.values`", count, values.get_ref().len(), key)));
+		}
+		Ok(DensityGrid::new(bounds, size, values.get_ref().clone()))
+	    },
+	    (None, Some(scale), octaves) => {
+		let scale = src.positive(&format!("{}.noise", key), scale)?;
+		let octaves = match octaves {
+		    Some(octaves) => src.count(&format!("{}.octaves", key), octaves)?,
+		    None => 1,
+		};
+		Ok(DensityGrid::from_fn(bounds, size, |p| (noise::fbm(p * scale, octaves) + 1.) / 2.))
+	    },
+	    (None, None, _) => Err(src.error(at, format!("expected `values` or `noise` for key `{}`", key))),
+	}
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+struct Vector([f64; 3]);
+
+impl From<Vector> for Vec3 {
+    fn from(Vector([x, y, z]): Vector) -> Self { Vec3(x, y, z) }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+struct ColorDesc([u8; 3]);
+
+impl From<ColorDesc> for Color {
+    fn from(ColorDesc([r, g, b]): ColorDesc) -> Self { Color::new(r, g, b) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::sampling::{Filter, Sampler};
+    use super::super::tonemap::{Encoding, Operator};
+
+    fn error(src: &str) -> String {
+	match load(src, 10, 10) {
+	    Ok(_) => panic!("scene should not load"),
+	    Err(e) => e.to_string(),
+	}
+    }
+
+    #[test]
+    fn load_scene() {
+	let scene = load(r#"
+[camera]
+position = [0, 1, -2]
+direction = [0, 0, 1]
+aperture = 0.05
+focus_distance = 2
+aperture_blades = 6
+aperture_rotation = 15
+
+[materials.red]
+color = [255, 0, 0]
+roughness = 0.3
+metallic = 1
+
+[[sphere]]
+center = [0, 0, 0]
+radius = 1
+material = "red"
+
+[[polygon]]
+vertices = [[0, 0, 0], [1, 0, 0], [0, 0, 1]]
+material = { color = [0, 255, 0], shine = 10, reflection = 0.5 }
+
+[[polygon]]
+vertices = [[0, 2, 0], [0, 2, 1], [1, 2, 0]]
+material = { emission = [255, 240, 200], emission_strength = 4 }
+
+[[plane]]
+normal = [0, -1, 0]
+material = { reflection_roughness = 0.2, reflection_samples = 4, texture = { pattern = "marble", colors = [[40, 40, 40], [230, 230, 220]], scale = 2, octaves = 6 }, reflection = 0.5, reflection_texture = { pattern = "checker", mapping = "uv" }, bump_map = { pattern = "voronoi", scale = 20 }, bump_strength = 0.02 }
+
+[[sphere]]
+center = [0, 1, 0]
+radius = 0.5
+material = { medium = { scattering = 2, absorption = 0.5, anisotropy = 0.6, density = { min = [-0.5, 0.5, -0.5], max = [0.5, 1.5, 0.5], size = [8, 8, 8], noise = 3, octaves = 4 } } }
+
+[[sphere]]
+center = [1, 1, 0]
+radius = 0.5
+material = { color = [230, 180, 160], roughness = 0.4, subsurface = { mean_free_path = [0.3, 0.1, 0.05] } }
+
+[[point_light]]
+position = [0, 2, 0]
+intensity = 1.0
+
+[[point_light]]
+position = [0, 3, 0]
+intensity = 2.0
+attenuation = [1, 0.5, 0]
+range = 10
+
+[[rect_light]]
+center = [0, 2, 0]
+u = [1, 0, 0]
+v = [0, 0, 1]
+intensity = 0.5
+samples = 4
+
+[[spot_light]]
+position = [0, 2, 0]
+direction = [0, -1, 0]
+inner_angle = 20
+outer_angle = 30
+intensity = 1
+
+[[sphere_light]]
+center = [1, 2, 0]
+radius = 0.1
+intensity = 0.5
+color = [255, 200, 150]
+
+[integrator]
+type = "path"
+max_depth = 5
+
+[sampling]
+samples = 4
+sampler = "sobol"
+filter = "mitchell"
+
+[tone_map]
+operator = "aces"
+exposure = 1.5
+encoding = "linear"
+
+[sky]
+sun_elevation = 45
+sun_azimuth = 120
+turbidity = 4
+
+[fog]
+scattering = 0.05
+color = [200, 210, 255]
+"#, 10, 10).unwrap();
+	assert_eq!(scene.integrator, Integrator::Path { max_depth: 5 });
+	assert_eq!(scene.sampling, Sampling::new(4, Sampler::Sobol, Filter::Mitchell));
+	assert_eq!(scene.tone_map.operator, Operator::Aces);
+	assert_eq!(scene.tone_map.encoding, Encoding::Linear);
+	assert_eq!(scene.tone_map.exposure, 1.5);
+	assert_eq!(scene.camera.focus_distance(), 2.);
+	assert_eq!(scene.camera.bokeh(), Bokeh::Polygon { blades: 6, rotation: 15f64.to_radians() });
+	assert_eq!(scene.fog.map(|fog| fog.scattering), Some(0.05));
+	let fisheye = load("[camera]\nprojection = \"fisheye\"\nmapping = \"equisolid\"\nfov = 200\n", 10, 10).unwrap();
+	assert_eq!(fisheye.camera.projection(), Projection::Fisheye { mapping: Fisheye::Equisolid, fov: 200f64.to_radians() });
+    }
+
+    #[test]
+    fn load_errors() {
+	assert_eq!(
+	    error("[[sphere]]\ncenter = [0, 0, 0]\nradius = -1\nmaterial = \"red\"\n"),
+	    "expected a positive number, got -1 for key `sphere.radius` at line 3 column 10");
+	assert_eq!(
+	    error("[[sphere]]\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"red\"\n"),
+	    "unknown material `red` for key `sphere.material` at line 4 column 12");
+	assert_eq!(
+	    error("[[plane]]\nnormal = [0, 0, 0]\nmaterial = { color = [0, 0, 0] }\n"),
+	    "expected a non-zero vector for key `plane.normal` at line 2 column 10");
+	assert_eq!(
+	    error("[[plane]]\nnormal = [0, 1, 0]\nmaterial = { color = [0, 0, 300] }\n"),
+	    "invalid value: integer `300`, expected u8 for key `plane.material` at line 3 column 29");
+	assert_eq!(
+	    error("[[plane]]\nnormal = [0, 1, 0]\nmaterial = { color = [0, 0, 0], shne = 1 }\n"),
+	    "unknown field `shne`, expected one of `color`, `texture`, `shine`, `roughness`, `roughness_texture`, `metallic`, `metallic_texture`, `reflection`, `reflection_texture`, `reflection_roughness`, `reflection_samples`, `normal_map`, `bump_map`, `bump_strength`, `transparency`, `ior`, `emission`, `emission_strength`, `medium`, `subsurface` for key `plane.material` at line 3 column 12");
+	assert_eq!(
+	    error("[tone_map]\noperator = \"linear\"\n"),
+	    "unknown tone mapping operator `linear`, expected one of `clip`, `reinhard`, `aces`, `filmic` for key `tone_map.operator` at line 2 column 12");
+	assert_eq!(
+	    error("[[point_light]]\nposition = [0, 0, 0]\nintensity = 1\nattenuation = [0, 0, 0]\n"),
+	    "expected non-negative terms, not all zero, for key `point_light.attenuation` at line 4 column 15");
+	assert_eq!(
+	    error("[[spot_light]]\nposition = [0, 0, 0]\ndirection = [0, -1, 0]\ninner_angle = 40\nouter_angle = 30\nintensity = 1\n"),
+	    "expected an angle between 0 and `outer_angle`, got 40 for key `spot_light.inner_angle` at line 4 column 15");
+	assert_eq!(
+	    error("[integrator]\ntype = \"whitted\"\nmax_depth = 4\n"),
+	    "key `integrator.max_depth` only applies to path tracing at line 3 column 13");
+	assert_eq!(
+	    error("[[environment_light]]\nfile = \"missing.hdr\"\nintensity = 1\n"),
+	    "failed to load environment: missing.hdr: No such file or directory (os error 2) at line 2 column 8");
+	assert_eq!(
+	    error("[[sphere]]\ncenter = [0, 0, 0]\nradius = 1\nmaterial = { texture = { file = \"missing.png\" } }\n"),
+	    "failed to load texture: missing.png: No such file or directory (os error 2) at line 4 column 33");
+	assert_eq!(
+	    error("[[sphere]]\ncenter = [0, 0, 0]\nradius = 1\nmaterial = { texture = { scale = 2 } }\n"),
+	    "expected a `file` or a `pattern` for key `sphere.material.texture` at line 4 column 24");
+	assert_eq!(
+	    error("[[sphere]]\ncenter = [0, 0, 0]\nradius = 1\nmaterial = { texture = { pattern = \"wood\", wrap = \"clamp\" } }\n"),
+	    "key `sphere.material.texture.wrap` only applies to image textures at line 4 column 51");
+	assert_eq!(
+	    error("[[sphere]]\ncenter = [0, 0, 0]\nradius = 1\nmaterial = { texture = { pattern = \"bricks\" } }\n"),
+	    "unknown pattern `bricks`, expected one of `checker`, `noise`, `fbm`, `turbulence`, `marble`, `wood`, `voronoi` for key `sphere.material.texture.pattern` at line 4 column 36");
+	assert_eq!(
+	    error("[[sphere]]\ncenter = [0, 0, 0]\nradius = 1\nmaterial = { bump_strength = 2 }\n"),
+	    "key `sphere.material.bump_strength` only applies to bump maps at line 4 column 30");
+	assert_eq!(
+	    error("[[sphere]]\ncenter = [0, 0, 0]\nradius = 1\nmaterial = { emission_strength = 2 }\n"),
+	    "key `sphere.material.emission_strength` only applies to glowing materials at line 4 column 34");
+	assert_eq!(
+	    error("[[sphere]]\ncenter = [0, 0, 0]\nradius = 1\nmaterial = { shine = 10, roughness = 0.5 }\n"),
+	    "key `sphere.material.roughness` conflicts with `sphere.material.shine` at line 4 column 40");
+	assert_eq!(
+	    error("[[sphere]]\ncenter = [0, 0, 0]\nradius = 1\nmaterial = { metallic = 2 }\n"),
+	    "expected a number between 0 and 1, got 2 for key `sphere.material.metallic` at line 4 column 25");
+	assert_eq!(
+	    error("[sky]\nsun_elevation = 30\nturbidity = 1\n"),
+	    "expected a number between 2 and 10, got 1 for key `sky.turbidity` at line 3 column 13");
+	assert_eq!(
+	    error("[fog]\nscattering = 1\nanisotropy = -1\n"),
+	    "expected a number between -1 and 1, got -1 for key `fog.anisotropy` at line 3 column 14");
+	assert_eq!(
+	    error("[fog]\nscattering = 1\ndensity = { min = [0, 0, 0], max = [1, 1, 1], size = [2, 2, 2], values = [1, 2] }\n"),
+	    "expected 8 values, got 2 for key `fog.density.values` at line 3 column 74");
+	assert_eq!(
+	    error("[[sphere]]\ncenter = [0, 0, 0]\nradius = 1\nmaterial = { subsurface = { mean_free_path = [1, 0, 1] } }\n"),
+	    "expected positive distances for key `sphere.material.subsurface.mean_free_path` at line 4 column 46");
+	assert_eq!(
+	    error("[camera]\nfocus_distance = 3\n"),
+	    "key `camera.focus_distance` only applies to lenses with an `aperture` at line 2 column 18");
+	assert_eq!(
+	    error("[camera]\naperture = 0.1\naperture_blades = 2\n"),
+	    "expected at least 3 blades, got 2 for key `camera.aperture_blades` at line 3 column 19");
+	assert_eq!(
+	    error("[camera]\nprojection = \"cylindrical\"\n"),
+	    "unknown projection `cylindrical`, expected one of `perspective`, `orthographic`, `fisheye`, `equirectangular` for key `camera.projection` at line 2 column 14");
+	assert_eq!(
+	    error("[camera]\nprojection = \"orthographic\"\nfov = 90\n"),
+	    "key `camera.fov` only applies to fisheye cameras at line 3 column 7");
+	assert_eq!(
+	    error("[camera]\nprojection = \"equirectangular\"\naperture = 1\n"),
+	    "key `camera.aperture` only applies to perspective cameras at line 3 column 12");
+	assert_eq!(
+	    error("[sampling]\nsamples = 0\n"),
+	    "expected a positive number, got 0 for key `sampling.samples` at line 2 column 11");
+    }
+}
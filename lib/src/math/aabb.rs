@@ -0,0 +1,121 @@
+use super::vector::*;
+use super::ray::Ray;
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn new(min: Point3, max: Point3) -> Aabb { Aabb { min, max } }
+
+    /// Box containing nothing, neutral element of `union`.
+    pub fn empty() -> Aabb {
+	Aabb {
+	    min: Vec3(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+	    max: Vec3(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+	}
+    }
+
+    pub fn from_points(points: &[Point3]) -> Aabb {
+	points.iter().fold(Aabb::empty(), |b, p| b.grow(*p))
+    }
+
+    pub fn grow(&self, p: Point3) -> Aabb {
+	Aabb::new(self.min.min(p), self.max.max(p))
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+	Aabb::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    pub fn centroid(&self) -> Point3 {
+	(self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f64 {
+	let d = self.max - self.min;
+	if d.0 < 0. || d.1 < 0. || d.2 < 0. {
+	    return 0.;
+	}
+	2. * (d.0 * d.1 + d.1 * d.2 + d.2 * d.0)
+    }
+
+    /// Axis along which the box is the longest.
+    pub fn longest_axis(&self) -> usize {
+	let d = self.max - self.min;
+	if d.0 >= d.1 && d.0 >= d.2 {
+	    0
+	} else if d.1 >= d.2 {
+	    1
+	} else {
+	    2
+	}
+    }
+
+    /// Distance along the ray at which it enters the box, if it does so
+    /// before `t_max`. `inv_dir` is the component-wise inverse of the ray
+    /// direction.
+    pub fn intersect(&self, ray: &Ray, inv_dir: Vec3, t_max: f64) -> Option<f64> {
+	self.span(ray, inv_dir, t_max).map(|(t0, _)| t0)
+    }
+
+    /// Distances along the ray between which it is inside the box, cut to
+    /// `[0, t_max]`.
+    pub fn span(&self, ray: &Ray, inv_dir: Vec3, t_max: f64) -> Option<(f64, f64)> {
+	let mut t0: f64 = 0.;
+	let mut t1 = t_max;
+	for axis in 0..3 {
+	    let near = (self.min[axis] - ray.point[axis]) * inv_dir[axis];
+	    let far = (self.max[axis] - ray.point[axis]) * inv_dir[axis];
+	    let (near, far) = if near > far { (far, near) } else { (near, far) };
+	    // NaN appears when the ray lies exactly in a slab plane, keep the
+	    // current bounds then
+	    t0 = if near > t0 { near } else { t0 };
+	    t1 = if far < t1 { far } else { t1 };
+	    if t0 > t1 {
+		return None;
+	    }
+	}
+	Some((t0, t1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inv(v: Vec3) -> Vec3 {
+	Vec3(1. / v.0, 1. / v.1, 1. / v.2)
+    }
+
+    #[test]
+    fn aabb_intersect() {
+	let b = Aabb::new(Vec3(-1., -1., -1.), Vec3(1., 1., 1.));
+
+	let ray = Ray::new(Vec3(0., 0., -5.), Vec3(0., 0., 1.));
+	assert_eq!(b.intersect(&ray, inv(ray.direction), f64::INFINITY), Some(4.));
+	assert_eq!(b.intersect(&ray, inv(ray.direction), 3.), None);
+
+	let ray = Ray::new(Vec3(0., 0., 0.), Vec3(1., 0., 0.));
+	assert_eq!(b.intersect(&ray, inv(ray.direction), f64::INFINITY), Some(0.));
+
+	let ray = Ray::new(Vec3(0., 2., -5.), Vec3(0., 0., 1.));
+	assert_eq!(b.intersect(&ray, inv(ray.direction), f64::INFINITY), None);
+
+	let ray = Ray::new(Vec3(0., 0., 5.), Vec3(0., 0., 1.));
+	assert_eq!(b.intersect(&ray, inv(ray.direction), f64::INFINITY), None);
+    }
+
+    #[test]
+    fn aabb_union() {
+	let a = Aabb::from_points(&[Vec3(0., 0., 0.), Vec3(1., 2., 3.)]);
+	let b = Aabb::new(Vec3(-1., 1., 1.), Vec3(0., 1., 4.));
+
+	assert_eq!(a.union(&b), Aabb::new(Vec3(-1., 0., 0.), Vec3(1., 2., 4.)));
+	assert_eq!(Aabb::empty().union(&a), a);
+	assert_eq!(a.surface_area(), 2. * (2. + 6. + 3.));
+    }
+}
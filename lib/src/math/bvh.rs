@@ -0,0 +1,259 @@
+use super::vector::*;
+use super::ray::Ray;
+use super::aabb::Aabb;
+
+const BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f64 = 1.;
+const INTERSECTION_COST: f64 = 1.;
+
+enum NodeKind {
+    Leaf { first: usize, count: usize },
+    /// Left child directly follows the node.
+    Interior { right: usize, axis: usize },
+}
+
+struct Node {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+/// Bounding volume hierarchy over a set of primitives given by their
+/// bounds. Built with the surface area heuristic over binned centroids.
+///
+/// The hierarchy only knows primitive indices, actual intersection tests
+/// are left to the caller.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Bvh {
+	let mut bvh = Bvh {
+	    nodes: Vec::with_capacity(bounds.len() * 2),
+	    indices: (0..bounds.len()).collect(),
+	};
+	if !bounds.is_empty() {
+	    let centroids: Vec<Point3> = bounds.iter().map(|b| b.centroid()).collect();
+	    bvh.build(bounds, &centroids, 0, bounds.len());
+	}
+	bvh
+    }
+
+    pub fn bounds(&self) -> Aabb {
+	self.nodes.first().map(|n| n.bounds).unwrap_or_else(Aabb::empty)
+    }
+
+    fn build(&mut self, bounds: &[Aabb], centroids: &[Point3], first: usize, count: usize) -> usize {
+	let node = self.nodes.len();
+	let items = &self.indices[first..first + count];
+	let node_bounds = items.iter().fold(Aabb::empty(), |b, &i| b.union(&bounds[i]));
+	self.nodes.push(Node { bounds: node_bounds, kind: NodeKind::Leaf { first, count } });
+
+	if count <= 1 {
+	    return node;
+	}
+
+	let centroid_bounds = items.iter().fold(Aabb::empty(), |b, &i| b.grow(centroids[i]));
+	let split = match find_split(bounds, centroids, items, &centroid_bounds) {
+	    Some(split) => split,
+	    None => return node,
+	};
+
+	let leaf_cost = INTERSECTION_COST * count as f64;
+	let split_cost = TRAVERSAL_COST + INTERSECTION_COST * split.cost / node_bounds.surface_area().max(f64::EPSILON);
+	if count <= MAX_LEAF_SIZE && split_cost >= leaf_cost {
+	    return node;
+	}
+
+	let (axis, lo, extent) = (split.axis, centroid_bounds.min[split.axis], centroid_bounds.max[split.axis] - centroid_bounds.min[split.axis]);
+	let mid = partition(&mut self.indices[first..first + count], |i| bin(centroids[i][axis], lo, extent) < split.bin);
+	if mid == 0 || mid == count {
+	    return node;
+	}
+
+	self.build(bounds, centroids, first, mid);
+	let right = self.build(bounds, centroids, first + mid, count - mid);
+	self.nodes[node].kind = NodeKind::Interior { right, axis };
+	node
+    }
+
+    /// Visits primitives the ray may hit, nearest boxes first. `hit` gets
+    /// the primitive index and the current search distance and returns the
+    /// distance of a closer hit, if any.
+    pub fn nearest<F>(&self, ray: &Ray, t_max: f64, mut hit: F)
+    where F: FnMut(usize, f64) -> Option<f64> {
+	self.traverse(ray, t_max, |i, t_max| {
+	    if let Some(t) = hit(i, *t_max) {
+		*t_max = t_max.min(t);
+	    }
+	    false
+	});
+    }
+
+    /// Whether `hit` returns true for any primitive the ray may hit
+    /// before `t_max`. Stops at the first such primitive.
+    pub fn any<F>(&self, ray: &Ray, t_max: f64, mut hit: F) -> bool
+    where F: FnMut(usize) -> bool {
+	let mut found = false;
+	self.traverse(ray, t_max, |i, _| {
+	    found = hit(i);
+	    found
+	});
+	found
+    }
+
+    /// Walks the nodes the ray enters. `visit` may shrink the search
+    /// distance and returns true to stop the walk.
+    fn traverse<F>(&self, ray: &Ray, t_max: f64, mut visit: F)
+    where F: FnMut(usize, &mut f64) -> bool {
+	if self.nodes.is_empty() {
+	    return;
+	}
+
+	let inv_dir = Vec3(1. / ray.direction.0, 1. / ray.direction.1, 1. / ray.direction.2);
+	let mut t_max = t_max;
+	let mut stack = Vec::with_capacity(64);
+	stack.push(0);
+
+	while let Some(node) = stack.pop() {
+	    let node_ref = &self.nodes[node];
+	    if node_ref.bounds.intersect(ray, inv_dir, t_max).is_none() {
+		continue;
+	    }
+	    match node_ref.kind {
+		NodeKind::Leaf { first, count } => {
+		    for &i in &self.indices[first..first + count] {
+			if visit(i, &mut t_max) {
+			    return;
+			}
+		    }
+		},
+		NodeKind::Interior { right, axis } => {
+		    // Visit the child closer along the ray first
+		    if ray.direction[axis] < 0. {
+			stack.push(node + 1);
+			stack.push(right);
+		    } else {
+			stack.push(right);
+			stack.push(node + 1);
+		    }
+		},
+	    }
+	}
+    }
+}
+
+struct Split {
+    axis: usize,
+    bin: usize,
+    /// Sum of children surface areas weighted by their primitive counts.
+    cost: f64,
+}
+
+fn bin(c: f64, lo: f64, extent: f64) -> usize {
+    (((c - lo) / extent * BINS as f64) as usize).min(BINS - 1)
+}
+
+fn find_split(bounds: &[Aabb], centroids: &[Point3], items: &[usize], centroid_bounds: &Aabb) -> Option<Split> {
+    (0..3)
+	.filter_map(|axis| find_axis_split(bounds, centroids, items, centroid_bounds, axis))
+	.min_by(|a, b| a.cost.total_cmp(&b.cost))
+}
+
+fn find_axis_split(bounds: &[Aabb], centroids: &[Point3], items: &[usize], centroid_bounds: &Aabb, axis: usize) -> Option<Split> {
+    let lo = centroid_bounds.min[axis];
+    let extent = centroid_bounds.max[axis] - lo;
+    if extent < f64::EPSILON {
+	return None;
+    }
+
+    let mut bin_bounds = [Aabb::empty(); BINS];
+    let mut bin_counts = [0usize; BINS];
+    for &i in items {
+	let b = bin(centroids[i][axis], lo, extent);
+	bin_bounds[b] = bin_bounds[b].union(&bounds[i]);
+	bin_counts[b] += 1;
+    }
+
+    // Costs of everything to the right of each split, swept from the end
+    let mut right_cost = [0.; BINS];
+    let (mut acc_bounds, mut acc_count) = (Aabb::empty(), 0);
+    for b in (1..BINS).rev() {
+	acc_bounds = acc_bounds.union(&bin_bounds[b]);
+	acc_count += bin_counts[b];
+	right_cost[b] = acc_bounds.surface_area() * acc_count as f64;
+    }
+
+    let mut best = Split { axis, bin: 0, cost: f64::INFINITY };
+    let (mut acc_bounds, mut acc_count) = (Aabb::empty(), 0);
+    for b in 1..BINS {
+	acc_bounds = acc_bounds.union(&bin_bounds[b - 1]);
+	acc_count += bin_counts[b - 1];
+	let cost = acc_bounds.surface_area() * acc_count as f64 + right_cost[b];
+	if cost < best.cost {
+	    best = Split { axis, bin: b, cost };
+	}
+    }
+
+    Some(best).filter(|s| s.bin != 0)
+}
+
+/// Moves elements satisfying `pred` to the front, returns their count.
+fn partition<F: Fn(usize) -> bool>(items: &mut [usize], pred: F) -> usize {
+    let mut mid = 0;
+    for i in 0..items.len() {
+	if pred(items[i]) {
+	    items.swap(i, mid);
+	    mid += 1;
+	}
+    }
+    mid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::ray::{RayIntersect, Sphere};
+
+    /// Deterministic values in `[0, 1)`.
+    fn lcg(state: &mut u64) -> f64 {
+	*state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+	(*state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    #[test]
+    fn bvh_nearest_matches_linear() {
+	let mut state = 42;
+	let spheres: Vec<Sphere> = (0..500)
+	    .map(|_| Sphere(Vec3(lcg(&mut state) * 20. - 10., lcg(&mut state) * 20. - 10., lcg(&mut state) * 20.), lcg(&mut state) * 0.5 + 0.05))
+	    .collect();
+	let bounds: Vec<Aabb> = spheres.iter()
+	    .map(|s| Aabb::new(s.0 - Vec3(s.1, s.1, s.1), s.0 + Vec3(s.1, s.1, s.1)))
+	    .collect();
+	let bvh = Bvh::new(&bounds);
+
+	for _ in 0..200 {
+	    let ray = Ray::new(Vec3(0., 0., -10.), Vec3(lcg(&mut state) - 0.5, lcg(&mut state) - 0.5, 1.));
+	    let dist = |i: usize| spheres[i].intersection(&ray).map(|(p, _, _)| (p - ray.point).len());
+
+	    let linear = (0..spheres.len())
+		.filter_map(|i| dist(i).map(|d| (i, d)))
+		.fold(None, |best: Option<(usize, f64)>, (i, d)| match best {
+		    Some((_, bd)) if bd <= d => best,
+		    _ => Some((i, d)),
+		});
+
+	    let mut nearest = None;
+	    bvh.nearest(&ray, f64::INFINITY, |i, t_max| {
+		let d = dist(i).filter(|d| *d < t_max)?;
+		nearest = Some(i);
+		Some(d)
+	    });
+
+	    assert_eq!(nearest, linear.map(|(i, _)| i));
+	    assert_eq!(bvh.any(&ray, f64::INFINITY, |i| dist(i).is_some()), linear.is_some());
+	}
+    }
+}
@@ -1,3 +1,5 @@
+use std::f64::consts::PI;
+
 use super::vector::*;
 use super::ray::Ray;
 
@@ -10,6 +12,70 @@ pub enum CameraTransform {
     RotateVertical(f64),
 }
 
+/// Maps points of the screen to directions from the camera. Distances
+/// across the screen run from `-0.5` to `0.5` at the edges when not
+/// zoomed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// Rays fan out through the screen `distance` ahead.
+    Perspective,
+    /// Parallel rays start from a screen `size` units across.
+    Orthographic { size: f64 },
+    /// Rays spread by their distance from the center of a circle filling
+    /// the screen, `fov` radians across. Points outside of the circle see
+    /// nothing.
+    Fisheye { mapping: Fisheye, fov: f64 },
+    /// All directions around, longitude across and latitude down the
+    /// screen, as in a 360° panorama.
+    Equirectangular,
+}
+
+/// How far from the center of a fisheye image a direction lands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fisheye {
+    /// In proportion to the angle to the view direction.
+    Equidistant,
+    /// Keeping areas of equal solid angle the same size.
+    Equisolid,
+}
+
+/// Shape of the lens opening, which out of focus highlights take.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bokeh {
+    Circle,
+    /// Regular polygon of `blades` corners, the first one `rotation`
+    /// radians from the right of the lens towards its top.
+    Polygon { blades: u32, rotation: f64 },
+}
+
+impl Bokeh {
+    /// Evenly spread point of the shape inscribed in the unit circle for
+    /// `u` and `v` in `[0, 1)`.
+    pub fn point(self, u: f64, v: f64) -> (f64, f64) {
+	match self {
+	    Bokeh::Circle => {
+		let (r, phi) = (u.sqrt(), 2. * PI * v);
+		(r * phi.cos(), r * phi.sin())
+	    },
+	    Bokeh::Polygon { blades, rotation } => {
+		// Pick one of the triangles fanning out from the center,
+		// then a point inside it
+		let blades = blades.max(3) as f64;
+		let sector = (u * blades).floor().min(blades - 1.);
+		let u = u * blades - sector;
+		let corner = |i: f64| {
+		    let angle = rotation + 2. * PI * i / blades;
+		    (angle.cos(), angle.sin())
+		};
+		let (a, b) = (corner(sector), corner(sector + 1.));
+		let r = u.sqrt();
+		let (s, t) = (r * (1. - v), r * v);
+		(a.0 * s + b.0 * t, a.1 * s + b.1 * t)
+	    },
+	}
+    }
+}
+
 pub struct Camera {
     position: Point3,
     direction: Vec3,
@@ -18,6 +84,14 @@ pub struct Camera {
     screen_y: Vec3,
     screen_width: f64,
     screen_height: f64,
+    /// Radius of the lens, `0.` for a pinhole with everything in focus.
+    aperture: f64,
+    /// Distance along the view direction that is in focus.
+    focus_distance: f64,
+    bokeh: Bokeh,
+    projection: Projection,
+    /// Bumped by every change of the camera.
+    generation: u64,
 }
 
 impl Camera {
@@ -30,12 +104,24 @@ impl Camera {
 	    screen_y: Vec3(0., -1. / height as f64, 0.),
 	    screen_height: height as f64,
 	    screen_width: width as f64,
+	    aperture: 0.,
+	    focus_distance: 1.,
+	    bokeh: Bokeh::Circle,
+	    projection: Projection::Perspective,
+	    generation: 0,
 	}
     }
 
+    /// Counter changing whenever the camera moves, lets renderers tell
+    /// whether earlier images are still valid.
+    pub fn generation(&self) -> u64 {
+	self.generation
+    }
+
     pub fn transform(&mut self, t: CameraTransform) {
 	use CameraTransform::*;
 
+	self.generation += 1;
 	match t {
 	    ScaleScreen(factor) => {
 		self.screen_x = self.screen_x * factor;
@@ -57,28 +143,122 @@ impl Camera {
 	}
     }
 
+    pub fn set_position(&mut self, position: Point3) {
+	self.generation += 1;
+	self.position = position;
+    }
+
+    /// Turns the camera to look along `direction` keeping the screen
+    /// size and the horizon level.
+    pub fn set_direction(&mut self, direction: Vec3) {
+	self.generation += 1;
+	let direction = direction.norm();
+	let mut right = Vec3(0., 1., 0.).cross(direction);
+	if right.len() < f64::EPSILON {
+	    right = self.screen_x * -1.;
+	}
+	let right = right.norm();
+	self.screen_x = right * -self.screen_x.len();
+	self.screen_y = direction.cross(right).norm() * -self.screen_y.len();
+	self.direction = direction;
+    }
+
+    /// Turns the pinhole into a thin lens of radius `aperture` focused
+    /// `focus_distance` ahead.
+    pub fn set_lens(&mut self, aperture: f64, focus_distance: f64) {
+	self.generation += 1;
+	self.aperture = aperture.max(0.);
+	self.focus_distance = focus_distance;
+    }
+
+    pub fn set_bokeh(&mut self, bokeh: Bokeh) {
+	self.generation += 1;
+	self.bokeh = bokeh;
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+	self.generation += 1;
+	self.projection = projection;
+    }
+
+    pub fn projection(&self) -> Projection {
+	self.projection
+    }
+
+    pub fn aperture(&self) -> f64 {
+	self.aperture
+    }
+
+    pub fn focus_distance(&self) -> f64 {
+	self.focus_distance
+    }
+
+    pub fn bokeh(&self) -> Bokeh {
+	self.bokeh
+    }
+
     pub fn rotate(&mut self, ax: f64, ay: f64, az: f64) {
+	self.generation += 1;
 	let screen_center = self.direction * self.distance;
 	self.direction = self.direction.rotate(ax, ay, az);
 	self.screen_x = self.screen_x.rotate_by_point(ax, ay, az, screen_center);
 	self.screen_y = self.screen_y.rotate_by_point(ax, ay, az, screen_center);
     }
 
-    fn screen_coords(&self, (x, y): (u32, u32)) -> Vec3 {
-	let (x, y) = (x as f64 - self.screen_width / 2., y as f64 - self.screen_height / 2.);
+    fn screen_coords(&self, (x, y): (f64, f64)) -> Vec3 {
+	let (x, y) = (x - self.screen_width / 2., y - self.screen_height / 2.);
 	self.screen_x * x + self.screen_y * y
     }
 
-    pub fn get_ray(&self, coords: (u32, u32)) -> Ray {
+    /// Ray through a point of the screen given in pixels from the top
+    /// left corner, pixel `(x, y)` spans `[x, x + 1) × [y, y + 1)`.
+    /// `None` where the projection shows nothing.
+    pub fn get_ray(&self, coords: (f64, f64)) -> Option<Ray> {
 	let screen_coords = self.screen_coords(coords);
-	Ray::new(self.position, self.direction * self.distance + screen_coords)
+	let (right, down) = (self.screen_x.norm(), self.screen_y.norm());
+	match self.projection {
+	    Projection::Perspective => Some(Ray::new(self.position, self.direction * self.distance + screen_coords)),
+	    Projection::Orthographic { size } => Some(Ray::new(self.position + screen_coords * size, self.direction)),
+	    Projection::Fisheye { mapping, fov } => {
+		let r = 2. * screen_coords.len();
+		if r > 1. {
+		    return None;
+		}
+		let theta = match mapping {
+		    Fisheye::Equidistant => r * fov / 2.,
+		    Fisheye::Equisolid => 2. * (r * (fov / 4.).sin()).asin(),
+		};
+		let side = if r > 0. { screen_coords.norm() } else { right };
+		Some(Ray::new(self.position, self.direction * theta.cos() + side * theta.sin()))
+	    },
+	    Projection::Equirectangular => {
+		let longitude = 2. * PI * screen_coords.dot(right);
+		let latitude = -PI * screen_coords.dot(down);
+		let around = self.direction * longitude.cos() + right * longitude.sin();
+		Some(Ray::new(self.position, around * latitude.cos() - down * latitude.sin()))
+	    },
+	}
+    }
+
+    /// Ray through a point of the screen like `get_ray`, starting from
+    /// the point of the lens picked by `lens` in `[0, 1)²`. All rays
+    /// through a point meet again at the focus distance. Only perspective
+    /// cameras have a lens.
+    pub fn get_lens_ray(&self, coords: (f64, f64), lens: (f64, f64)) -> Option<Ray> {
+	let pinhole = self.get_ray(coords)?;
+	if self.aperture <= 0. || self.projection != Projection::Perspective {
+	    return Some(pinhole);
+	}
+	let focus = self.position + pinhole.direction * (self.focus_distance / pinhole.direction.dot(self.direction));
+	let (x, y) = self.bokeh.point(lens.0, lens.1);
+	let origin = self.position + self.screen_x.norm() * (x * self.aperture) + self.screen_y.norm() * (y * self.aperture);
+	Some(Ray::new(origin, focus - origin))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::f64::consts::PI;
 
     #[test]
     fn camera() {
@@ -91,6 +271,76 @@ mod tests {
 	// assert_eq!(camera.get_ray((50, 0)), Ray::new(Vec3(0., 50., 0.), Vec3(0., 50., 1.)));
     }
 
+    #[test]
+    fn camera_lens() {
+	let mut camera = Camera::new(100, 100);
+	camera.set_lens(0.1, 4.);
+	let pinhole = camera.get_ray((30., 70.)).unwrap();
+	let focus = pinhole.point + pinhole.direction * (4. / pinhole.direction.dot(camera.direction));
+	for lens in [(0., 0.), (0.5, 0.25), (0.99, 0.9)] {
+	    let ray = camera.get_lens_ray((30., 70.), lens).unwrap();
+	    assert!((ray.point - camera.position).len() <= 0.1 + 1e-12);
+	    // Rays through the lens meet in the plane of focus
+	    let t = (focus - ray.point).dot(camera.direction) / ray.direction.dot(camera.direction);
+	    assert!((ray.point + ray.direction * t - focus).len() < 1e-9);
+	}
+
+	// Points of a hexagon stay within its edges
+	let hexagon = Bokeh::Polygon { blades: 6, rotation: 0. };
+	let apothem = (PI / 6.).cos();
+	for i in 0..100 {
+	    let (x, y) = hexagon.point(i as f64 / 100., (i * 37 % 100) as f64 / 100.);
+	    for k in 0..6 {
+		let angle = PI / 6. + k as f64 * PI / 3.;
+		assert!(x * angle.cos() + y * angle.sin() <= apothem + 1e-12);
+	    }
+	}
+    }
+
+    #[test]
+    fn camera_projections() {
+	let mut camera = Camera::new(100, 100);
+	let close = |a: Vec3, b: Vec3| (a - b).len() < 1e-9;
+
+	camera.set_projection(Projection::Orthographic { size: 4. });
+	let (a, b) = (camera.get_ray((0., 50.)).unwrap(), camera.get_ray((100., 50.)).unwrap());
+	assert!(close(a.direction, camera.direction) && close(b.direction, camera.direction));
+	assert!(((a.point - b.point).len() - 4.).abs() < 1e-9);
+
+	for mapping in [Fisheye::Equidistant, Fisheye::Equisolid] {
+	    camera.set_projection(Projection::Fisheye { mapping, fov: PI });
+	    assert!(close(camera.get_ray((50., 50.)).unwrap().direction, camera.direction));
+	    // The edge of the circle looks sideways, corners see nothing
+	    assert!(camera.get_ray((100., 50.)).unwrap().direction.dot(camera.direction).abs() < 1e-9);
+	    assert!(camera.get_ray((0., 0.)).is_none());
+	}
+	// Halfway out equisolid fisheyes see less far to the side
+	camera.set_projection(Projection::Fisheye { mapping: Fisheye::Equidistant, fov: PI });
+	let equidistant = camera.get_ray((75., 50.)).unwrap().direction.dot(camera.direction);
+	camera.set_projection(Projection::Fisheye { mapping: Fisheye::Equisolid, fov: PI });
+	assert!(camera.get_ray((75., 50.)).unwrap().direction.dot(camera.direction) > equidistant);
+
+	camera.set_projection(Projection::Equirectangular);
+	assert!(close(camera.get_ray((50., 50.)).unwrap().direction, camera.direction));
+	assert!(close(camera.get_ray((0., 50.)).unwrap().direction, -1. * camera.direction));
+	assert!(close(camera.get_ray((50., 0.)).unwrap().direction, Vec3(0., 1., 0.)));
+	assert!(close(camera.get_ray((25., 50.)).unwrap().direction, camera.screen_x.norm() * -1.));
+    }
+
+    #[test]
+    fn camera_set_direction() {
+	let mut camera = Camera::new(100, 100);
+
+	camera.set_direction(Vec3(0., 0., 1.));
+	assert_eq!(camera.screen_x, Vec3(-0.01, 0., 0.));
+	assert_eq!(camera.screen_y, Vec3(0., -0.01, 0.));
+
+	camera.set_direction(Vec3(1., 0., 0.));
+	assert_eq!(camera.direction, Vec3(1., 0., 0.));
+	assert_eq!(camera.screen_x.cross(camera.screen_y).norm(), camera.direction);
+	assert_eq!(camera.screen_y, Vec3(0., -0.01, 0.));
+    }
+
     // #[test]
     // fn intersection_plane() {
     // 	let plane = Plane(Vec3(0., 1., 0.), 0.);
@@ -1,3 +1,5 @@
+pub mod aabb;
+pub mod bvh;
 pub mod camera;
 pub mod ray;
 pub mod vector;
@@ -6,11 +8,21 @@ pub use vector::Vec3;
 pub use vector::Point3;
 pub use vector::Distance;
 
+pub use aabb::Aabb;
+pub use bvh::Bvh;
+
 pub use camera::CameraTransform;
 pub use camera::Camera;
+pub use camera::Bokeh;
+pub use camera::Fisheye;
+pub use camera::Projection;
 
 pub use ray::Ray;
 pub use ray::Polygon;
 pub use ray::Plane;
 pub use ray::Sphere;
 pub use ray::RayIntersect;
+pub use ray::intersect_triangle;
+pub use ray::reflect;
+pub use ray::refract;
+pub use ray::schlick;
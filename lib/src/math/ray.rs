@@ -1,10 +1,40 @@
 use super::vector::*;
 
-fn reflect(v: Vec3, n: Vec3) -> Vec3 {
+/// Direction of `v` mirrored at a surface with normal `n`.
+pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - 2. * v.dot(n.norm()) * n.norm()
 }
 
-fn fix_point_reflect(p: Point3, v: Vec3, n: Vec3) -> (Point3, Vec3, Vec3) {
+/// Direction of `v` refracted through a surface with normal `n` facing
+/// against it, `eta` is the ratio of indices of refraction of the media the
+/// ray leaves and enters. `None` on total internal reflection.
+pub fn refract(v: Vec3, n: Vec3, eta: f64) -> Option<Vec3> {
+    let (v, n) = (v.norm(), n.norm());
+    let cos_i = -n.dot(v);
+    let sin2_t = eta * eta * (1. - cos_i * cos_i);
+    if sin2_t > 1. {
+	return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(v * eta + n * (eta * cos_i - cos_t))
+}
+
+/// Schlick's approximation of the Fresnel reflectance for a ray with
+/// direction `v` hitting a surface with normal `n` facing against it.
+pub fn schlick(v: Vec3, n: Vec3, n1: f64, n2: f64) -> f64 {
+    let r0 = ((n1 - n2) / (n1 + n2)).powi(2);
+    let mut cos = -n.norm().dot(v.norm());
+    if n1 > n2 {
+	let sin2_t = (n1 / n2).powi(2) * (1. - cos * cos);
+	if sin2_t > 1. {
+	    return 1.;
+	}
+	cos = (1. - sin2_t).sqrt();
+    }
+    r0 + (1. - r0) * (1. - cos).powi(5)
+}
+
+pub(crate) fn fix_point_reflect(p: Point3, v: Vec3, n: Vec3) -> (Point3, Vec3, Vec3) {
     let mut n = n.norm();
     if n.dot(v) > 0. {
 	n = -1. * n;
@@ -27,6 +57,36 @@ fn intersect_plane(ray: &Ray, n: Vec3, d: f64) -> Option<Point3> {
     Some(int)
 }
 
+/// Distance along the ray and barycentric coordinates of `b` and `c` at the
+/// hit point, using the Möller–Trumbore algorithm.
+pub fn intersect_triangle(ray: &Ray, a: Point3, b: Point3, c: Point3) -> Option<(f64, f64, f64)> {
+    let e1 = b - a;
+    let e2 = c - a;
+    let p = ray.direction.cross(e2);
+    let det = e1.dot(p);
+    if det.abs() < f64::EPSILON {
+	return None;
+    }
+
+    let inv_det = 1. / det;
+    let s = ray.point - a;
+    let u = s.dot(p) * inv_det;
+    if !(0. ..=1.).contains(&u) {
+	return None;
+    }
+    let q = s.cross(e1);
+    let v = ray.direction.dot(q) * inv_det;
+    if v < 0. || u + v > 1. {
+	return None;
+    }
+
+    let t = e2.dot(q) * inv_det;
+    if t < 0. {
+	return None;
+    }
+    Some((t, u, v))
+}
+
 pub struct Polygon(pub Point3, pub Point3, pub Point3);
 
 pub trait RayIntersect {
@@ -36,7 +96,7 @@ pub trait RayIntersect {
 impl RayIntersect for Polygon {
     fn intersection(&self, ray: &Ray) -> Option<(Point3, Vec3, Vec3)> {
 	let n = (self.1 - self.0).cross(self.2 - self.0);
-	let d = -1. * n.dot(self.0);
+	let d = -n.dot(self.0);
 	let int = intersect_plane(ray, n, d)?;
 
 	let check = |pb: Point3, pe: Point3| n.dot((pe - pb).cross(int - pb)) >= 0.;
@@ -100,3 +160,31 @@ impl Ray {
 	}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refraction() {
+	let n = Vec3(0., 1., 0.);
+
+	assert_eq!(refract(Vec3(0., -1., 0.), n, 1.5), Some(Vec3(0., -1., 0.)));
+	assert_eq!(refract(Vec3(1., -1., 0.), n, 1.), Some(Vec3(1., -1., 0.).norm()));
+
+	let t = refract(Vec3(1., -1., 0.), n, 1. / 1.5).unwrap();
+	let sin_t = t.0 / t.len();
+	assert!((sin_t - (0.5f64).sqrt() / 1.5).abs() < 1e-12);
+
+	assert_eq!(refract(Vec3(1., -0.1, 0.), n, 1.5), None);
+    }
+
+    #[test]
+    fn fresnel() {
+	let n = Vec3(0., 1., 0.);
+
+	assert!((schlick(Vec3(0., -1., 0.), n, 1., 1.5) - 0.04).abs() < 1e-12);
+	assert_eq!(schlick(Vec3(1., -0.1, 0.), n, 1.5, 1.), 1.);
+	assert!(schlick(Vec3(1., -0.1, 0.), n, 1., 1.5) > 0.5);
+    }
+}
@@ -1,4 +1,4 @@
-use std::ops::{Add, Sub, Mul, Div};
+use std::ops::{Add, Sub, Mul, Div, Index};
 use super::ray::Ray;
 
 #[derive(Clone,Copy,Debug)]
@@ -51,6 +51,14 @@ impl Vec3 {
 	self.div(self.len())
     }
 
+    pub fn min(&self, rhs: Vec3) -> Vec3 {
+	Vec3(self.0.min(rhs.0), self.1.min(rhs.1), self.2.min(rhs.2))
+    }
+
+    pub fn max(&self, rhs: Vec3) -> Vec3 {
+	Vec3(self.0.max(rhs.0), self.1.max(rhs.1), self.2.max(rhs.2))
+    }
+
     pub fn rotate(&self, ax: f64, ay: f64, az: f64) -> Vec3 {
 	let (xcos, ycos, zcos) = (ax.cos(), ay.cos(), az.cos());
 	let (xsin, ysin, zsin) = (ax.sin(), ay.sin(), az.sin());
@@ -107,6 +115,19 @@ impl Div<f64> for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &Self::Output {
+	match axis {
+	    0 => &self.0,
+	    1 => &self.1,
+	    2 => &self.2,
+	    _ => panic!("Vec3 axis out of range: {}", axis),
+	}
+    }
+}
+
 pub trait Distance<Other> {
     fn distance(&self, other: Other) -> f64;
 }
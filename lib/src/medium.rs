@@ -0,0 +1,258 @@
+//! Participating media: fog, smoke and other matter between surfaces that
+//! absorbs and scatters light along the way.
+//!
+//! Media are gray, a single absorption and scattering coefficient holds
+//! for every channel and `color` tints the scattered light. Heterogeneous
+//! media scale both coefficients by a density grid. Distances to
+//! collisions are picked by delta tracking and the light left along a
+//! shadow ray by ratio tracking, both against the densest point of the
+//! grid.
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::math::{Aabb, Point3, Ray, Vec3};
+use super::object::Color;
+use super::sampling::{self, Rng};
+
+/// Densities on a regular grid of points spanning a box, interpolated
+/// between them. Outside the box the density is zero.
+pub struct DensityGrid {
+    bounds: Aabb,
+    size: [usize; 3],
+    values: Vec<f64>,
+    max: f64,
+}
+
+impl DensityGrid {
+    /// `values` run along `x` first, then `y`, then `z`, every side has
+    /// at least two points. Negative values count as zero.
+    pub fn new(bounds: Aabb, size: [usize; 3], values: Vec<f64>) -> Self {
+	assert!(size.iter().all(|&s| s >= 2), "density grid needs two points per side");
+	assert_eq!(values.len(), size[0] * size[1] * size[2], "density grid size does not match its values");
+	let values: Vec<f64> = values.into_iter().map(|v| v.max(0.)).collect();
+	let max = values.iter().cloned().fold(0., f64::max);
+	Self { bounds, size, values, max }
+    }
+
+    /// Grid of `size` points filled by a function of their position.
+    pub fn from_fn<F: Fn(Point3) -> f64>(bounds: Aabb, size: [usize; 3], density: F) -> Self {
+	let extent = bounds.max - bounds.min;
+	let mut values = Vec::with_capacity(size[0] * size[1] * size[2]);
+	for z in 0..size[2] {
+	    for y in 0..size[1] {
+		for x in 0..size[0] {
+		    let t = |i: usize, n: usize| i as f64 / (n - 1).max(1) as f64;
+		    let p = bounds.min + Vec3(extent.0 * t(x, size[0]), extent.1 * t(y, size[1]), extent.2 * t(z, size[2]));
+		    values.push(density(p));
+		}
+	    }
+	}
+	Self::new(bounds, size, values)
+    }
+
+    pub fn bounds(&self) -> Aabb {
+	self.bounds
+    }
+
+    /// Largest density anywhere.
+    pub fn max(&self) -> f64 {
+	self.max
+    }
+
+    /// Trilinear interpolation of the density at `p`.
+    pub fn density(&self, p: Point3) -> f64 {
+	let (min, max) = (self.bounds.min, self.bounds.max);
+	let mut cell = [0; 3];
+	let mut frac = [0.; 3];
+	for axis in 0..3 {
+	    if p[axis] < min[axis] || p[axis] > max[axis] {
+		return 0.;
+	    }
+	    let steps = (self.size[axis] - 1) as f64;
+	    let x = ((p[axis] - min[axis]) / (max[axis] - min[axis]) * steps).clamp(0., steps);
+	    cell[axis] = (x.floor() as usize).min(self.size[axis] - 2);
+	    frac[axis] = x - cell[axis] as f64;
+	}
+	let value = |dx: usize, dy: usize, dz: usize| {
+	    self.values[(cell[0] + dx) + self.size[0] * ((cell[1] + dy) + self.size[1] * (cell[2] + dz))]
+	};
+	let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+	let [u, v, w] = frac;
+	lerp(w,
+	     lerp(v, lerp(u, value(0, 0, 0), value(1, 0, 0)), lerp(u, value(0, 1, 0), value(1, 1, 0))),
+	     lerp(v, lerp(u, value(0, 0, 1), value(1, 0, 1)), lerp(u, value(0, 1, 1), value(1, 1, 1))))
+    }
+}
+
+/// Matter filling space, either everywhere as fog or inside the boundary
+/// of an object.
+#[derive(Clone)]
+pub struct Medium {
+    /// Share of light absorbed per unit of length at density `1.`.
+    pub absorption: f64,
+    /// Share of light scattered per unit of length at density `1.`.
+    pub scattering: f64,
+    /// Tint of scattered light.
+    pub color: Color,
+    /// Asymmetry of the Henyey-Greenstein phase function, from `-1.` for
+    /// scattering back to `1.` for scattering forward, `0.` scatters
+    /// evenly.
+    pub anisotropy: f64,
+    /// Density varying over space, `None` for a homogeneous medium of
+    /// density `1.`.
+    pub density: Option<Arc<DensityGrid>>,
+}
+
+impl Medium {
+    pub fn new(absorption: f64, scattering: f64) -> Self {
+	Self { absorption, scattering, color: Color::white(), anisotropy: 0., density: None }
+    }
+
+    /// Light lost to absorption and scattering per unit of length at
+    /// density `1.`.
+    pub fn extinction(&self) -> f64 {
+	self.absorption + self.scattering
+    }
+
+    /// Chance that a collision scatters the light rather than absorbing
+    /// it.
+    pub fn albedo(&self) -> f64 {
+	let extinction = self.extinction();
+	if extinction <= 0. {
+	    return 0.;
+	}
+	self.scattering / extinction
+    }
+
+    pub fn density(&self, p: Point3) -> f64 {
+	match &self.density {
+	    Some(grid) => grid.density(p),
+	    None => 1.,
+	}
+    }
+
+    /// Share of light scattered from travelling along one direction into
+    /// another at cosine `cos` to it, per steradian.
+    pub fn phase(&self, cos: f64) -> f64 {
+	let g = self.anisotropy;
+	let d = 1. + g * g - 2. * g * cos;
+	(1. - g * g) / (4. * PI * d * d.sqrt())
+    }
+
+    /// Picks the direction light travelling along `direction` scatters
+    /// into, returns it with the probability density of picking it.
+    pub fn sample_phase(&self, direction: Vec3, rng: &mut Rng) -> (Vec3, f64) {
+	let dir = sampling::henyey_greenstein(direction, self.anisotropy, rng.next_f64(), rng.next_f64());
+	(dir, self.phase(dir.dot(direction)))
+    }
+
+    /// Part of the ray up to `dist` where the medium may be found, with
+    /// the largest extinction there.
+    pub(crate) fn extent(&self, ray: &Ray, dist: f64) -> Option<(f64, f64, f64)> {
+	match &self.density {
+	    Some(grid) => {
+		let inv_dir = Vec3(1. / ray.direction.0, 1. / ray.direction.1, 1. / ray.direction.2);
+		let (t0, t1) = grid.bounds().span(ray, inv_dir, dist)?;
+		Some((t0, t1, self.extinction() * grid.max()))
+	    },
+	    None => Some((0., dist, self.extinction())),
+	}
+    }
+
+    /// Distance along the ray to the next collision with the medium,
+    /// `None` if the light gets `dist` far without one.
+    pub fn sample_distance(&self, ray: &Ray, dist: f64, rng: &mut Rng) -> Option<f64> {
+	let (mut t, end, majorant) = self.extent(ray, dist)?;
+	if majorant <= 0. {
+	    return None;
+	}
+	loop {
+	    t -= (1. - rng.next_f64()).ln() / majorant;
+	    if t >= end {
+		return None;
+	    }
+	    // Collisions with the empty part of the majorant are null
+	    if self.density.is_none() || rng.next_f64() * majorant < self.extinction() * self.density(ray.point + ray.direction * t) {
+		return Some(t);
+	    }
+	}
+    }
+
+    /// Share of light left after travelling `dist` along the ray.
+    pub fn transmittance(&self, ray: &Ray, dist: f64, rng: &mut Rng) -> f64 {
+	let (mut t, end, majorant) = match self.extent(ray, dist) {
+	    Some(extent) => extent,
+	    None => return 1.,
+	};
+	if majorant <= 0. {
+	    return 1.;
+	}
+	if self.density.is_none() {
+	    return (-majorant * (end - t)).exp();
+	}
+	let mut res = 1.;
+	loop {
+	    t -= (1. - rng.next_f64()).ln() / majorant;
+	    if t >= end || res < 1e-4 {
+		return res;
+	    }
+	    res *= 1. - self.extinction() * self.density(ray.point + ray.direction * t) / majorant;
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phase() {
+	// Integrates to one for any asymmetry
+	for g in [-0.5, 0., 0.8] {
+	    let medium = Medium { anisotropy: g, ..Medium::new(0., 1.) };
+	    let steps = 10000;
+	    let total: f64 = (0..steps).map(|i| {
+		let cos = -1. + (i as f64 + 0.5) * 2. / steps as f64;
+		medium.phase(cos) * 2. * PI * 2. / steps as f64
+	    }).sum();
+	    assert!((total - 1.).abs() < 1e-3, "{}: {}", g, total);
+	}
+	// Forward scattering media mostly keep the direction
+	let medium = Medium { anisotropy: 0.8, ..Medium::new(0., 1.) };
+	let mut rng = Rng::new(3);
+	let axis = Vec3(0., 0., 1.);
+	let mean = (0..2000).map(|_| medium.sample_phase(axis, &mut rng).0.dot(axis)).sum::<f64>() / 2000.;
+	assert!((mean - 0.8).abs() < 0.05, "{}", mean);
+    }
+
+    #[test]
+    fn tracking() {
+	let ray = Ray::new(Vec3(-2., 0.5, 0.5), Vec3(1., 0., 0.));
+	let homogeneous = Medium::new(0.5, 0.5);
+	let mut rng = Rng::new(1);
+	assert!((homogeneous.transmittance(&ray, 2., &mut rng) - (-2f64).exp()).abs() < 1e-12);
+
+	// A unit cube of density 1 in a grid twice as dense at its center
+	let grid = DensityGrid::from_fn(Aabb::new(Vec3(0., 0., 0.), Vec3(1., 1., 1.)), [3, 3, 3], |p| {
+	    if p == Vec3(0.5, 0.5, 0.5) { 2. } else { 1. }
+	});
+	assert_eq!(grid.density(Vec3(0.5, 0.5, 0.5)), 2.);
+	assert_eq!(grid.density(Vec3(0.25, 0.5, 0.5)), 1.5);
+	assert_eq!(grid.density(Vec3(1.5, 0.5, 0.5)), 0.);
+	let smoke = Medium { density: Some(Arc::new(grid)), ..Medium::new(0., 1.) };
+	// Density 1 to 2 and back over the unit along the ray
+	let expected = (-1.5f64).exp();
+	let count = 20000;
+	let ratio = (0..count).map(|_| smoke.transmittance(&ray, 10., &mut rng)).sum::<f64>() / count as f64;
+	assert!((ratio - expected).abs() < 0.01, "{}", ratio);
+	let passed = (0..count).filter(|_| smoke.sample_distance(&ray, 10., &mut rng).is_none()).count() as f64 / count as f64;
+	assert!((passed - expected).abs() < 0.01, "{}", passed);
+	for _ in 0..100 {
+	    if let Some(t) = smoke.sample_distance(&ray, 10., &mut rng) {
+		assert!((2. ..=3.).contains(&t), "{}", t);
+	    }
+	}
+	assert!(smoke.sample_distance(&ray, 1.9, &mut rng).is_none());
+    }
+}
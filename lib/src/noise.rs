@@ -0,0 +1,116 @@
+//! Lattice noise for procedural textures.
+//!
+//! Instead of a permutation table the integer lattice is hashed, so the
+//! noise has no period and needs no state.
+
+use super::math::Vec3;
+
+/// Pseudo random bits of a lattice cell.
+fn hash(x: f64, y: f64, z: f64) -> u64 {
+    let mut h = (x as i64 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+	^ (y as i64 as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+	^ (z as i64 as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+    h ^= h >> 31;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 29;
+    h
+}
+
+/// Dot product of `d` with one of the twelve edge directions of a cube.
+fn gradient(h: u64, d: Vec3) -> f64 {
+    let Vec3(x, y, z) = d;
+    match h % 12 {
+	0 => x + y,
+	1 => y - x,
+	2 => x - y,
+	3 => -x - y,
+	4 => x + z,
+	5 => z - x,
+	6 => x - z,
+	7 => -x - z,
+	8 => y + z,
+	9 => z - y,
+	10 => y - z,
+	_ => -y - z,
+    }
+}
+
+/// Perlin's improved gradient noise, about `[-1, 1]` and zero at lattice
+/// points.
+pub fn perlin(p: Vec3) -> f64 {
+    let cell = Vec3(p.0.floor(), p.1.floor(), p.2.floor());
+    let d = p - cell;
+    let fade = |t: f64| t * t * t * (t * (t * 6. - 15.) + 10.);
+    let (u, v, w) = (fade(d.0), fade(d.1), fade(d.2));
+    let corner = |i: f64, j: f64, k: f64| gradient(hash(cell.0 + i, cell.1 + j, cell.2 + k), d - Vec3(i, j, k));
+    let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+    lerp(w,
+	 lerp(v, lerp(u, corner(0., 0., 0.), corner(1., 0., 0.)), lerp(u, corner(0., 1., 0.), corner(1., 1., 0.))),
+	 lerp(v, lerp(u, corner(0., 0., 1.), corner(1., 0., 1.)), lerp(u, corner(0., 1., 1.), corner(1., 1., 1.))))
+	.clamp(-1., 1.)
+}
+
+/// Fractional Brownian motion, `octaves` layers of noise each twice as
+/// fine and half as strong as the one before, about `[-1, 1]`.
+pub fn fbm(p: Vec3, octaves: u32) -> f64 {
+    octaves_sum(p, octaves, perlin)
+}
+
+/// Like `fbm` but of the absolute noise, which gives sharp creases, in
+/// `[0, 1]`.
+pub fn turbulence(p: Vec3, octaves: u32) -> f64 {
+    octaves_sum(p, octaves, |p| perlin(p).abs())
+}
+
+fn octaves_sum<F: Fn(Vec3) -> f64>(p: Vec3, octaves: u32, noise: F) -> f64 {
+    let (mut sum, mut total, mut amplitude, mut frequency) = (0., 0., 1., 1.);
+    for _ in 0..octaves.max(1) {
+	sum += amplitude * noise(p * frequency);
+	total += amplitude;
+	amplitude *= 0.5;
+	frequency *= 2.;
+    }
+    sum / total
+}
+
+/// Worley cellular noise, the distance to the closest of points
+/// scattered one per lattice cell, in `[0, √3]`.
+pub fn voronoi(p: Vec3) -> f64 {
+    let cell = Vec3(p.0.floor(), p.1.floor(), p.2.floor());
+    let mut closest = f64::INFINITY;
+    for i in -1..=1 {
+	for j in -1..=1 {
+	    for k in -1..=1 {
+		let neighbor = cell + Vec3(i as f64, j as f64, k as f64);
+		let h = hash(neighbor.0, neighbor.1, neighbor.2);
+		// Three 21-bit fractions place the point inside its cell
+		let fraction = |shift: u32| ((h >> shift) & 0x1f_ffff) as f64 / 0x20_0000 as f64;
+		let point = neighbor + Vec3(fraction(0), fraction(21), fraction(42));
+		closest = closest.min((point - p).len());
+	    }
+	}
+    }
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise() {
+	assert_eq!(perlin(Vec3(3., -2., 7.)), 0.);
+	let points: Vec<Vec3> = (0..1000).map(|i| Vec3(i as f64 * 0.37, i as f64 * 0.11 - 20., (i % 17) as f64 * 0.53)).collect();
+	let values: Vec<f64> = points.iter().map(|p| perlin(*p)).collect();
+	assert!(values.iter().any(|v| *v > 0.3) && values.iter().any(|v| *v < -0.3));
+	// Continuous across cell borders
+	let edge = Vec3(2., 0.5, 0.5);
+	assert!((perlin(edge - Vec3(1e-9, 0., 0.)) - perlin(edge + Vec3(1e-9, 0., 0.))).abs() < 1e-6);
+
+	for p in points {
+	    assert!((-1. ..=1.).contains(&fbm(p, 5)));
+	    assert!((0. ..=1.).contains(&turbulence(p, 5)));
+	    assert!((0. ..=3f64.sqrt()).contains(&voronoi(p)));
+	}
+    }
+}
@@ -0,0 +1,402 @@
+//! Wavefront OBJ and MTL import.
+//!
+//! Supports vertices, normals, texture coordinates and polygonal faces,
+//! which are triangulated as fans. Materials map onto `Material`: `Kd`
+//! gives the color, `Ns` the roughness of a matching Phong exponent when
+//! `Ks` is non-zero and the average of `Ks` the reflection for
+//! illumination models with ray traced reflection (`illum` 3 and up). The
+//! PBR extension's `Pr` and `Pm` set roughness and metallic directly.
+//! Dissolve `d` (or `Tr`) sets the transparency, `Ni` the index of
+//! refraction and `Ke` the radiance of glowing surfaces. `map_Kd` adds an
+//! image texture multiplied by `Kd`, `map_Pr` and `map_Pm` scale `Pr` and
+//! `Pm`, `bump` (or `map_Bump`) a height map whose slopes are scaled by
+//! its `-bm` option and `norm` a tangent space normal map. Of the texture
+//! options `-s` scales the texture and `-clamp on` clamps it instead of
+//! repeating.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::image::{self, Image};
+use super::math::*;
+use super::object::{Color, Material, Mesh, Relief, Triangle};
+use super::texture::{ImageTexture, Texture, Wrap};
+use super::tonemap::Encoding;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, io::Error),
+    Parse { file: PathBuf, line: usize, message: String },
+    Image(image::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    Error::Io(file, e) => write!(f, "{}: {}", file.display(), e),
+	    Error::Parse { file, line, message } => write!(f, "{}:{}: {}", file.display(), line + 1, message),
+	    Error::Image(e) => e.fmt(f),
+	}
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Material used by faces without `usemtl`, matching the MTL defaults.
+pub fn default_material() -> Material {
+    Material::new(Color::new(0xcc, 0xcc, 0xcc), 0.)
+}
+
+/// Loads a mesh, material libraries are looked up next to the file and
+/// textures next to the library.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Mesh, Error> {
+    let path = path.as_ref();
+    let src = fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse(&src, path, |name| {
+	let mtl_path = dir.join(name);
+	let mtl_src = fs::read_to_string(&mtl_path).map_err(|e| Error::Io(mtl_path.clone(), e))?;
+	let mtl_dir = mtl_path.parent().unwrap_or_else(|| Path::new(""));
+	parse_mtl(&mtl_src, &mtl_path, |name, encoding| {
+	    image::load(mtl_dir.join(name), encoding).map(Arc::new).map_err(Error::Image)
+	})
+    })
+}
+
+/// Parses OBJ source. `file` is only used in error messages, `mtllib`
+/// resolves material library names.
+pub fn parse<F>(src: &str, file: &Path, mut mtllib: F) -> Result<Mesh, Error>
+where F: FnMut(&str) -> Result<HashMap<String, Material>, Error> {
+    let mut vertices = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    let mut triangles = vec![];
+    let mut materials = vec![default_material()];
+
+    let mut library: HashMap<String, Material> = HashMap::new();
+    let mut material_ids: HashMap<String, usize> = HashMap::new();
+    let mut material = 0;
+
+    for (line, text) in src.lines().enumerate() {
+	let error = |message: String| Error::Parse { file: file.to_owned(), line, message };
+	let text = text.split('#').next().unwrap_or("");
+	let mut words = text.split_whitespace();
+	let keyword = match words.next() {
+	    Some(keyword) => keyword,
+	    None => continue,
+	};
+	let args: Vec<&str> = words.collect();
+
+	match keyword {
+	    "v" => {
+		let [x, y, z] = floats::<3>(&args, 3).map_err(error)?;
+		vertices.push(Vec3(x, y, z));
+	    },
+	    "vn" => {
+		let [x, y, z] = floats::<3>(&args, 3).map_err(error)?;
+		normals.push(Vec3(x, y, z));
+	    },
+	    "vt" => {
+		let [u, v] = floats::<2>(&args, 1).map_err(error)?;
+		uvs.push((u, v));
+	    },
+	    "f" => {
+		if args.len() < 3 {
+		    return Err(error(format!("face needs at least 3 vertices, got {}", args.len())));
+		}
+		let corners = args.iter()
+		    .map(|arg| corner(arg, vertices.len(), uvs.len(), normals.len()))
+		    .collect::<Result<Vec<_>, _>>()
+		    .map_err(error)?;
+		let all_uvs = corners.iter().all(|c| c.1.is_some());
+		let all_normals = corners.iter().all(|c| c.2.is_some());
+		for i in 1..corners.len() - 1 {
+		    let (a, b, c) = (corners[0], corners[i], corners[i + 1]);
+		    triangles.push(Triangle {
+			vertices: [a.0, b.0, c.0],
+			uvs: if all_uvs { Some([a.1.unwrap(), b.1.unwrap(), c.1.unwrap()]) } else { None },
+			normals: if all_normals { Some([a.2.unwrap(), b.2.unwrap(), c.2.unwrap()]) } else { None },
+			material,
+		    });
+		}
+	    },
+	    "mtllib" => {
+		for name in args {
+		    library.extend(mtllib(name)?);
+		}
+	    },
+	    "usemtl" => {
+		let name = args.join(" ");
+		material = match (material_ids.get(&name), library.get(&name)) {
+		    (Some(id), _) => *id,
+		    (None, Some(m)) => {
+			materials.push(m.clone());
+			material_ids.insert(name, materials.len() - 1);
+			materials.len() - 1
+		    },
+		    // Exporters often reference materials they did not write
+		    (None, None) => 0,
+		};
+	    },
+	    _ => {},
+	}
+    }
+
+    Ok(Mesh::new(vertices, normals, uvs, triangles, materials))
+}
+
+/// Parses an MTL material library. `file` is only used in error messages,
+/// `texture` resolves image names and decodes them with the given transfer
+/// function.
+pub fn parse_mtl<F>(src: &str, file: &Path, mut texture: F) -> Result<HashMap<String, Material>, Error>
+where F: FnMut(&str, Encoding) -> Result<Arc<Image>, Error> {
+    struct Desc {
+	kd: [f64; 3],
+	ks: [f64; 3],
+	ke: [f64; 3],
+	ns: f64,
+	pr: Option<f64>,
+	pm: f64,
+	illum: u32,
+	dissolve: f64,
+	ni: f64,
+	map_kd: Option<Arc<dyn Texture + Send + Sync>>,
+	map_pr: Option<Arc<dyn Texture + Send + Sync>>,
+	map_pm: Option<Arc<dyn Texture + Send + Sync>>,
+	relief: Option<Relief>,
+    }
+
+    fn material(d: &Desc) -> Material {
+	let [r, g, b] = d.kd.map(|c| (c.clamp(0., 1.) * 255.).round() as u8);
+	let color = Color::new(r, g, b);
+	let specular = (d.ks[0] + d.ks[1] + d.ks[2]) / 3.;
+	let reflection = if d.illum >= 3 { specular.clamp(0., 1.) } else { 0. };
+	let mut material = match d.pr {
+	    Some(pr) => Material { roughness: pr.clamp(0., 1.), ..Material::new(color, reflection) },
+	    None if specular > 0. && d.ns > 0. => Material::new_shine(color, d.ns.round() as i32, reflection),
+	    None => Material::new(color, reflection),
+	};
+	material.metallic = d.pm.clamp(0., 1.);
+	material.roughness_texture = d.map_pr.clone();
+	material.metallic_texture = d.map_pm.clone();
+	material.transparency = (1. - d.dissolve).clamp(0., 1.);
+	material.ior = d.ni;
+	material.emission = Color::rgb(d.ke[0].max(0.), d.ke[1].max(0.), d.ke[2].max(0.));
+	material.texture = d.map_kd.clone();
+	material.relief = d.relief.clone();
+	material
+    }
+
+    let mut res = HashMap::new();
+    let mut current: Option<(String, Desc)> = None;
+
+    for (line, text) in src.lines().enumerate() {
+	let error = |message: String| Error::Parse { file: file.to_owned(), line, message };
+	let text = text.split('#').next().unwrap_or("");
+	let mut words = text.split_whitespace();
+	let keyword = match words.next() {
+	    Some(keyword) => keyword,
+	    None => continue,
+	};
+	let args: Vec<&str> = words.collect();
+
+	if keyword == "newmtl" {
+	    if let Some((name, desc)) = current.take() {
+		res.insert(name, material(&desc));
+	    }
+	    current = Some((args.join(" "), Desc { kd: [0.8; 3], ks: [0.; 3], ke: [0.; 3], ns: 0., pr: None, pm: 0., illum: 2, dissolve: 1., ni: 1., map_kd: None, map_pr: None, map_pm: None, relief: None }));
+	    continue;
+	}
+
+	let desc = match &mut current {
+	    Some((_, desc)) => desc,
+	    None if ["Kd", "Ks", "Ke", "Ns", "Pr", "Pm", "illum", "d", "Tr", "Ni", "map_Kd", "map_Pr", "map_Pm", "map_Bump", "bump", "norm"].contains(&keyword) => {
+		return Err(error(format!("`{}` before any `newmtl`", keyword)));
+	    },
+	    None => continue,
+	};
+	match keyword {
+	    "Kd" => desc.kd = color(&args).map_err(error)?,
+	    "Ks" => desc.ks = color(&args).map_err(error)?,
+	    "Ke" => desc.ke = color(&args).map_err(error)?,
+	    "Ns" => desc.ns = floats::<1>(&args, 1).map_err(error)?[0],
+	    "Pr" => desc.pr = Some(floats::<1>(&args, 1).map_err(error)?[0]),
+	    "Pm" => desc.pm = floats::<1>(&args, 1).map_err(error)?[0],
+	    "d" => desc.dissolve = floats::<1>(&args, 1).map_err(error)?[0],
+	    "Tr" => desc.dissolve = 1. - floats::<1>(&args, 1).map_err(error)?[0],
+	    "Ni" => {
+		desc.ni = floats::<1>(&args, 1).map_err(error)?[0];
+		if desc.ni <= 0. {
+		    return Err(error(format!("index of refraction must be positive, got {}", desc.ni)));
+		}
+	    },
+	    "map_Kd" | "map_Pr" | "map_Pm" | "map_Bump" | "bump" | "norm" => {
+		let (name, options) = args.split_last().ok_or_else(|| error(String::from("expected a texture file")))?;
+		// Only colors are stored with the sRGB curve
+		let encoding = if keyword == "map_Kd" { Encoding::Srgb } else { Encoding::Linear };
+		let mut map = ImageTexture::new(texture(name, encoding)?);
+		let mut bump_multiplier = 1.;
+		for (i, option) in options.iter().enumerate() {
+		    match *option {
+			"-s" => {
+			    let [u, v] = floats::<2>(&options[i + 1..], 2).map_err(error)?;
+			    // Scaling the coordinates shrinks the image
+			    if u == 0. || v == 0. {
+				return Err(error(String::from("texture scale must not be zero")));
+			    }
+			    map.scale = (1. / u, 1. / v);
+			},
+			"-clamp" if options.get(i + 1) == Some(&"on") => map.wrap = Wrap::Clamp,
+			"-bm" => bump_multiplier = floats::<1>(&options[i + 1..], 1).map_err(error)?[0],
+			_ => {},
+		    }
+		}
+		let map = Arc::new(map);
+		match keyword {
+		    "map_Kd" => desc.map_kd = Some(map),
+		    "map_Pr" => desc.map_pr = Some(map),
+		    "map_Pm" => desc.map_pm = Some(map),
+		    "norm" => desc.relief = Some(Relief::Normal(map)),
+		    _ => desc.relief = Some(Relief::Bump(map, bump_multiplier)),
+		}
+	    },
+	    "illum" => {
+		desc.illum = args.first()
+		    .and_then(|s| s.parse().ok())
+		    .ok_or_else(|| error(String::from("expected an illumination model number")))?;
+	    },
+	    _ => {},
+	}
+    }
+
+    if let Some((name, desc)) = current.take() {
+	res.insert(name, material(&desc));
+    }
+    Ok(res)
+}
+
+/// Parses between `required` and `N` numbers, missing ones are zero.
+fn floats<const N: usize>(args: &[&str], required: usize) -> Result<[f64; N], String> {
+    if args.len() < required {
+	return Err(format!("expected at least {} numbers, got {}", required, args.len()));
+    }
+    let mut res = [0.; N];
+    for (r, arg) in res.iter_mut().zip(args) {
+	*r = arg.parse().map_err(|_| format!("invalid number `{}`", arg))?;
+    }
+    Ok(res)
+}
+
+/// `r g b` color, a single value is used for all channels.
+fn color(args: &[&str]) -> Result<[f64; 3], String> {
+    match args.len() {
+	1 => Ok([floats::<1>(args, 1)?[0]; 3]),
+	_ => floats::<3>(args, 3),
+    }
+}
+
+/// Face corner `v`, `v/vt`, `v//vn` or `v/vt/vn` as zero based indices.
+fn corner(arg: &str, vertices: usize, uvs: usize, normals: usize) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = arg.split('/');
+    let index = |part: Option<&str>, count: usize, what: &str| -> Result<Option<usize>, String> {
+	let part = match part {
+	    Some(part) if !part.is_empty() => part,
+	    _ => return Ok(None),
+	};
+	let i: i64 = part.parse().map_err(|_| format!("invalid {} index `{}`", what, part))?;
+	// Negative indices count back from the last element
+	let resolved = if i < 0 { count as i64 + i } else { i - 1 };
+	if resolved < 0 || resolved >= count as i64 {
+	    return Err(format!("{} index {} out of range, {} defined so far", what, i, count));
+	}
+	Ok(Some(resolved as usize))
+    };
+
+    let v = index(parts.next(), vertices, "vertex")?
+	.ok_or_else(|| format!("missing vertex index in `{}`", arg))?;
+    let vt = index(parts.next(), uvs, "texture coordinate")?;
+    let vn = index(parts.next(), normals, "normal")?;
+    Ok((v, vt, vn))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::object::Object;
+
+    const CUBE_FACE: &str = "
+mtllib face.mtl
+v -1 -1 0
+v 1 -1 0
+v 1 1 0
+v -1 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 -1
+usemtl red
+f 1/1/1 2/2/1 3/3/1 4/4/1
+";
+
+    const MTL: &str = "
+newmtl red
+Kd 1 0 0
+Ks 0.5 0.5 0.5
+Ns 20
+illum 3
+map_Kd -s 2 2 stripes.png
+bump -bm 0.5 stripes.png
+";
+
+    #[test]
+    fn parse_quad() {
+	let mesh = parse(CUBE_FACE, Path::new("face.obj"), |name| {
+	    assert_eq!(name, "face.mtl");
+	    parse_mtl(MTL, Path::new(name), |name, encoding| {
+		assert_eq!(name, "stripes.png");
+		assert!(encoding == Encoding::Srgb || encoding == Encoding::Linear);
+		Ok(Arc::new(Image::new(2, 1, vec![Color::white(), Color::default()])))
+	    })
+	}).unwrap();
+
+	assert_eq!(mesh.vertices().len(), 4);
+	assert_eq!(mesh.triangles().len(), 2);
+	assert_eq!(mesh.triangles()[1].vertices, [0, 2, 3]);
+	assert_eq!(mesh.triangles()[1].normals, Some([0, 0, 0]));
+
+	let int = mesh.intersect(&Ray::new(Vec3(0.5, 0.5, -1.), Vec3(0., 0., 1.))).unwrap();
+	assert!(int.point.2.abs() < 1e-9);
+	assert_eq!(int.n, Vec3(0., 0., -1.));
+	// Highlights about as sharp as those of the Phong exponent
+	assert!((0.3..0.45).contains(&int.material.roughness), "{}", int.material.roughness);
+	assert_eq!(int.material.metallic, 0.);
+	assert_eq!(int.material.reflection, 0.5);
+	assert!((int.uv.0 - 0.75).abs() < 1e-9 && (int.uv.1 - 0.75).abs() < 1e-9);
+	assert!((int.tangent - Vec3(1., 0., 0.)).len() < 1e-9);
+	// A quarter of the way from the white pixel center to the black one
+	assert_eq!(int.color(), Color::rgb(0.75, 0., 0.));
+	// Heights rise towards the white pixel repeated to the right
+	let bumped = int.with_relief();
+	assert!(bumped.n.0 < -0.1 && bumped.n.2 < 0., "{:?}", bumped.n);
+	assert!(mesh.intersect(&Ray::new(Vec3(1.5, 0.5, -1.), Vec3(0., 0., 1.))).is_none());
+    }
+
+    #[test]
+    fn parse_errors() {
+	let no_mtl = |_: &str| Ok(HashMap::new());
+	let error = |src: &str| parse(src, Path::new("a.obj"), no_mtl).err().unwrap().to_string();
+
+	assert_eq!(error("v 0 0 0\nv 1 0 0\nf 1 2 3\n"), "a.obj:3: vertex index 3 out of range, 2 defined so far");
+	assert_eq!(error("v 0 0\n"), "a.obj:1: expected at least 3 numbers, got 2");
+	assert_eq!(error("v 0 0 x\n"), "a.obj:1: invalid number `x`");
+	assert_eq!(error("v 0 0 0\nf 1 -1\n"), "a.obj:2: face needs at least 3 vertices, got 2");
+	assert_eq!(
+	    parse_mtl("Kd 1 1 1\n", Path::new("a.mtl"), |_, _| unreachable!()).err().unwrap().to_string(),
+	    "a.mtl:1: `Kd` before any `newmtl`");
+    }
+}
@@ -0,0 +1,129 @@
+use super::super::math::*;
+
+use super::figures::{Object, Intersection};
+
+/// Scene objects together with the acceleration structure used to
+/// intersect them.
+pub struct Bodies {
+    objects: Vec<Box<dyn Object + Send + Sync>>,
+    /// Hierarchy over bounded objects, `None` when out of date.
+    bvh: Option<Bvh>,
+    /// Object index of every hierarchy primitive.
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+impl Default for Bodies {
+    fn default() -> Self {
+	Bodies::new()
+    }
+}
+
+impl Bodies {
+    pub fn new() -> Bodies {
+	Bodies {
+	    objects: vec![],
+	    bvh: None,
+	    bounded: vec![],
+	    unbounded: vec![],
+	}
+    }
+
+    pub fn push(&mut self, obj: Box<dyn Object + Send + Sync>) {
+	self.objects.push(obj);
+	self.bvh = None;
+    }
+
+    pub fn len(&self) -> usize {
+	self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+	self.objects.is_empty()
+    }
+
+    /// Builds the hierarchy unless it is up to date.
+    pub fn build(&mut self) {
+	if self.bvh.is_some() {
+	    return;
+	}
+
+	let mut bounds = vec![];
+	self.bounded.clear();
+	self.unbounded.clear();
+	for (i, obj) in self.objects.iter().enumerate() {
+	    match obj.bounds() {
+		Some(b) => {
+		    bounds.push(b);
+		    self.bounded.push(i);
+		},
+		None => self.unbounded.push(i),
+	    }
+	}
+	self.bvh = Some(Bvh::new(&bounds));
+    }
+
+    /// Box enclosing all bounded objects, `None` without any.
+    pub fn bounds(&self) -> Option<Aabb> {
+	match &self.bvh {
+	    Some(bvh) => (!self.bounded.is_empty()).then(|| bvh.bounds()),
+	    None => self.objects.iter().filter_map(|obj| obj.bounds()).reduce(|a, b| a.union(&b)),
+	}
+    }
+
+    /// Closest intersection, ties go to the object added first.
+    pub fn nearest(&self, ray: &Ray) -> Option<Intersection<'_>> {
+	self.nearest_object(ray).map(|(_, int)| int)
+    }
+
+    /// Like `nearest` together with the index of the object hit, in the
+    /// order objects were pushed.
+    pub fn nearest_object(&self, ray: &Ray) -> Option<(usize, Intersection<'_>)> {
+	let mut res: Option<(usize, f64, Intersection)> = None;
+	let mut test = |i: usize, t_max: f64| -> Option<f64> {
+	    let int = self.objects[i].intersect(ray)?;
+	    let dist = ray.distance(int.point);
+	    let closer = match &res {
+		Some((res_i, res_dist, _)) => dist < *res_dist || (dist == *res_dist && i < *res_i),
+		None => dist <= t_max,
+	    };
+	    if !closer {
+		return None;
+	    }
+	    res = Some((i, dist, int));
+	    Some(dist)
+	};
+
+	match &self.bvh {
+	    Some(bvh) => {
+		let t_max = self.unbounded.iter()
+		    .filter_map(|&i| test(i, f64::INFINITY))
+		    .fold(f64::INFINITY, f64::min);
+		bvh.nearest(ray, t_max, |i, t_max| test(self.bounded[i], t_max));
+	    },
+	    None => {
+		for i in 0..self.objects.len() {
+		    test(i, f64::INFINITY);
+		}
+	    },
+	}
+
+	res.map(|(i, _, int)| (i, int))
+    }
+
+    /// Whether anything blocks the ray closer than `max_dist`. Boundaries
+    /// of media do not.
+    pub fn occluded(&self, ray: &Ray, max_dist: f64) -> bool {
+	let blocks = |i: usize| {
+	    self.objects[i].intersect(ray).is_some_and(|int| int.material.medium.is_none() && ray.distance(int.point) < max_dist)
+	};
+
+	match &self.bvh {
+	    Some(bvh) => {
+		self.unbounded.iter().any(|&i| blocks(i))
+		    || bvh.any(ray, max_dist, |i| blocks(self.bounded[i]))
+	    },
+	    None => (0..self.objects.len()).any(blocks),
+	}
+    }
+}
@@ -0,0 +1,208 @@
+//! Reflection of light by opaque surfaces.
+//!
+//! Surfaces are GGX (Trowbridge-Reitz) microfacets with the height
+//! correlated Smith shadowing term and Schlick's Fresnel approximation,
+//! above a Lambertian base for the light refracted into dielectrics.
+//! Metals have no such base and tint their highlights with their color
+//! instead.
+
+use std::f64::consts::PI;
+
+use super::super::math::*;
+use super::super::sampling::{self, Rng};
+
+use super::figures::Intersection;
+use super::material::Color;
+
+/// Reflectance of common dielectrics at normal incidence.
+const DIELECTRIC_F0: f64 = 0.04;
+
+/// Smallest GGX alpha, perfectly smooth surfaces would only ever reflect
+/// light from single directions.
+const MIN_ALPHA: f64 = 1e-3;
+
+/// Least share of bounces taken from the specular lobe.
+const MIN_SPECULAR_SAMPLES: f64 = 0.1;
+
+/// Reflectance at a surface point for light leaving towards the viewer.
+pub struct Brdf {
+    /// Unit shading normal.
+    n: Vec3,
+    /// Unit vector towards the viewer.
+    wo: Vec3,
+    /// Albedo of the diffuse base.
+    diffuse: Color,
+    /// Fresnel reflectance at normal incidence.
+    f0: Color,
+    alpha: f64,
+    /// Share of bounces taken from the specular lobe.
+    specular_samples: f64,
+}
+
+impl Brdf {
+    /// Reflectance of the surface at `int` seen along the ray direction
+    /// `incoming`.
+    pub fn new(int: &Intersection, incoming: Vec3) -> Self {
+	Self::from_parts(int.n, -1. * incoming.norm(), int.color(), int.roughness(), int.metallic())
+    }
+
+    /// Reflectance of a surface with normal `n` seen from `wo`, both unit
+    /// vectors.
+    pub fn from_parts(n: Vec3, wo: Vec3, color: Color, roughness: f64, metallic: f64) -> Self {
+	let (roughness, metallic) = (roughness.clamp(0., 1.), metallic.clamp(0., 1.));
+	let diffuse = color.mul_float(1. - metallic);
+	let f0 = Color::white().mul_float(DIELECTRIC_F0 * (1. - metallic)).add(color.mul_float(metallic));
+	let specular = fresnel(f0, n.dot(wo).max(0.)).luminance();
+	let specular_samples = if diffuse.luminance() <= 0. {
+	    1.
+	} else {
+	    (specular / (specular + diffuse.luminance() * (1. - specular))).max(MIN_SPECULAR_SAMPLES)
+	};
+	Self { n, wo, diffuse, f0, alpha: (roughness * roughness).max(MIN_ALPHA), specular_samples }
+    }
+
+    pub fn normal(&self) -> Vec3 {
+	self.n
+    }
+
+    /// Share of light arriving from the unit vector `wi` reflected towards
+    /// the viewer, per steradian.
+    pub fn eval(&self, wi: Vec3) -> Color {
+	let (cos_i, cos_o) = (self.n.dot(wi), self.n.dot(self.wo));
+	if cos_i <= 0. || cos_o <= 0. {
+	    return Color::default();
+	}
+	let h = (wi + self.wo).norm();
+	let f = fresnel(self.f0, wi.dot(h).max(0.));
+	let g = 1. / (1. + self.smith_lambda(cos_i) + self.smith_lambda(cos_o));
+	let specular = f.mul_float(self.ggx(self.n.dot(h)) * g / (4. * cos_i * cos_o));
+	// Light the facets do not reflect enters the diffuse base
+	let diffuse = self.diffuse.mul(Color::white().sub(f)).div(PI);
+	specular.add(diffuse)
+    }
+
+    /// Picks the direction of the next bounce, returns it with the BRDF
+    /// times cosine divided by the probability density of picking it, and
+    /// that density.
+    pub fn sample(&self, rng: &mut Rng) -> Option<(Vec3, Color, f64)> {
+	let wi = if rng.next_f64() < self.specular_samples {
+	    let h = sampling::ggx_normal(self.n, self.alpha, rng.next_f64(), rng.next_f64());
+	    reflect(-1. * self.wo, h)
+	} else {
+	    sampling::cosine_hemisphere(self.n, rng)
+	};
+	let cos = wi.dot(self.n);
+	let pdf = self.pdf(wi);
+	if cos <= 0. || pdf <= 0. {
+	    return None;
+	}
+	Some((wi, self.eval(wi).mul_float(cos / pdf), pdf))
+    }
+
+    /// Probability density of `sample` picking the unit vector `wi`.
+    pub fn pdf(&self, wi: Vec3) -> f64 {
+	let cos = wi.dot(self.n);
+	if cos <= 0. {
+	    return 0.;
+	}
+	let h = (wi + self.wo).norm();
+	let cos_h = self.n.dot(h);
+	let wo_h = self.wo.dot(h);
+	let specular = if cos_h > 0. && wo_h > 0. { self.ggx(cos_h) * cos_h / (4. * wo_h) } else { 0. };
+	self.specular_samples * specular + (1. - self.specular_samples) * cos / PI
+    }
+
+    /// Share of light arriving evenly from all directions that is
+    /// reflected, roughly.
+    pub fn albedo(&self) -> Color {
+	self.diffuse.mul(Color::white().sub(self.f0)).add(self.f0)
+    }
+
+    /// GGX density of facets with the normal at cosine `cos` to `n`.
+    fn ggx(&self, cos: f64) -> f64 {
+	if cos <= 0. {
+	    return 0.;
+	}
+	let a2 = self.alpha * self.alpha;
+	let d = cos * cos * (a2 - 1.) + 1.;
+	a2 / (PI * d * d)
+    }
+
+    /// Facets hidden from a direction at cosine `cos` to `n`, relative to
+    /// those visible.
+    fn smith_lambda(&self, cos: f64) -> f64 {
+	let cos2 = cos * cos;
+	((1. + self.alpha * self.alpha * (1. - cos2) / cos2).sqrt() - 1.) / 2.
+    }
+}
+
+/// Schlick's approximation of the reflectance at cosine `cos` to the
+/// facet normal.
+fn fresnel(f0: Color, cos: f64) -> Color {
+    let t = (1. - cos).powi(5);
+    f0.add(Color::white().sub(f0).mul_float(t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directions(n: usize) -> impl Iterator<Item = Vec3> {
+	let mut rng = Rng::new(7);
+	(0..n).map(move |_| sampling::cosine_hemisphere(Vec3(0., 0., 1.), &mut rng))
+    }
+
+    #[test]
+    fn energy() {
+	let n = Vec3(0., 0., 1.);
+	for roughness in [0.2, 0.5, 1.] {
+	    for metallic in [0., 1.] {
+		let brdf = Brdf::from_parts(n, Vec3(0.6, 0., 0.8), Color::white(), roughness, metallic);
+		let mut rng = Rng::new(1);
+		let count = 20000;
+		let total = (0..count).filter_map(|_| brdf.sample(&mut rng)).fold(0., |sum, (_, w, _)| sum + w.luminance());
+		let albedo = total / count as f64;
+		assert!(albedo <= 1.02, "roughness {} metallic {}: {}", roughness, metallic, albedo);
+		// Light scattered more than once between facets is lost
+		assert!(albedo >= 0.3, "roughness {} metallic {}: {}", roughness, metallic, albedo);
+	    }
+	}
+    }
+
+    #[test]
+    fn pdf_matches_sample() {
+	let brdf = Brdf::from_parts(Vec3(0., 0., 1.), Vec3(0., 0.6, 0.8), Color::rgb(0.8, 0.4, 0.2), 0.4, 0.3);
+	let mut rng = Rng::new(3);
+	for _ in 0..100 {
+	    if let Some((wi, weight, pdf)) = brdf.sample(&mut rng) {
+		assert!((brdf.pdf(wi) - pdf).abs() < 1e-9);
+		let expected = brdf.eval(wi).mul_float(wi.2 / pdf);
+		assert!((weight.r - expected.r).abs() < 1e-9);
+	    }
+	}
+	// The density integrates to one over the hemisphere
+	let count = 20000;
+	let integral: f64 = directions(count).map(|wi| brdf.pdf(wi) / (wi.2 / PI)).sum::<f64>() / count as f64;
+	assert!((integral - 1.).abs() < 0.05, "{}", integral);
+    }
+
+    #[test]
+    fn highlights() {
+	let n = Vec3(0., 0., 1.);
+	let wo = Vec3(0.6, 0., 0.8);
+	let mirror = Vec3(-0.6, 0., 0.8);
+	let gold = Color::rgb(1., 0.7, 0.3);
+	// Metals reflect in their own color, dielectrics in that of the light
+	let metal = Brdf::from_parts(n, wo, gold, 0.3, 1.).eval(mirror);
+	assert!(metal.r > metal.g && metal.g > metal.b);
+	let plastic = Brdf::from_parts(n, wo, Color::rgb(0., 0., 1.), 0.1, 0.).eval(mirror);
+	assert!(plastic.r > 0.5 && (plastic.r - plastic.g).abs() < 1e-9);
+	// Smoother surfaces have brighter and narrower highlights
+	let smooth = Brdf::from_parts(n, wo, gold, 0.1, 1.);
+	let rough = Brdf::from_parts(n, wo, gold, 0.6, 1.);
+	assert!(smooth.eval(mirror).r > rough.eval(mirror).r);
+	let off = Vec3(-0.2, 0., 1.).norm();
+	assert!(smooth.eval(off).r < rough.eval(off).r);
+	assert_eq!(smooth.eval(Vec3(0., 0., -1.)), Color::default());
+    }
+}
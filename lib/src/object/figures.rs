@@ -1,40 +1,205 @@
+use std::f64::consts::PI;
+
 use super::super::{math, math::*};
+use super::super::sampling;
+
+use super::light::{MeshLight, Patch};
+use super::material::{Color, Material};
 
-use super::material::Material;
+/// Material of mesh triangles pointing past the materials of their mesh.
+static MISSING_MATERIAL: Material = Material::new(Color::rgb(0., 0., 0.), 0.);
 
-pub struct Intersection {
+pub struct Intersection<'a> {
     pub point: Point3,
+    /// Shading normal facing the incoming ray, bent by the relief of the
+    /// material.
     pub n: Vec3,
+    /// Normal of the surface itself facing the incoming ray.
+    pub geometric_n: Vec3,
     pub reflect: Ray,
-    pub material: Material,
+    pub material: &'a Material,
+    /// Whether the surface was hit from the back, i.e. from inside the
+    /// object.
+    pub inside: bool,
+    /// Texture coordinates of the point.
+    pub uv: (f64, f64),
+    /// Unit vector perpendicular to `n` pointing where `u` grows.
+    pub tangent: Vec3,
+    /// Unit vector perpendicular to `n` and `tangent` pointing where `v`
+    /// grows.
+    pub bitangent: Vec3,
+}
+
+impl<'a> Intersection<'a> {
+    pub fn new(point: Point3, n: Vec3, reflect: Ray, material: &'a Material, inside: bool) -> Self {
+	let (tangent, bitangent) = sampling::basis(n);
+	Self { point, n, geometric_n: n, reflect, material, inside, uv: (0., 0.), tangent, bitangent }
+    }
+
+    /// Sets the texture coordinates and the directions `u` and `v` grow
+    /// in, which are made perpendicular to the normal and each other.
+    pub fn with_uv(mut self, uv: (f64, f64), tangent: Vec3, bitangent: Vec3) -> Self {
+	self.uv = uv;
+	let tangent = tangent - self.n * tangent.dot(self.n);
+	if tangent.len() > f64::EPSILON {
+	    self.tangent = tangent.norm();
+	    // Mirrored texture coordinates flip the frame
+	    self.bitangent = self.n.cross(self.tangent);
+	    if self.bitangent.dot(bitangent) < 0. {
+		self.bitangent = -1. * self.bitangent;
+	    }
+	}
+	self
+    }
+
+    /// Bends the shading normal by the relief of the material, the
+    /// mirrored ray follows it unless that would enter the surface.
+    pub fn with_relief(mut self) -> Self {
+	let relief = match &self.material.relief {
+	    Some(relief) => relief,
+	    None => return self,
+	};
+	let n = relief.normal(self.uv, self.point, self.n, self.tangent, self.bitangent);
+	let incoming = reflect(self.reflect.direction, self.n);
+	// Normals turned away from the viewer would shade the surface black
+	if n.dot(incoming) >= 0. {
+	    return self;
+	}
+	let mirrored = reflect(incoming, n);
+	if mirrored.dot(self.geometric_n) > 0. {
+	    self.reflect = Ray::new(self.point, mirrored);
+	}
+	self.n = n;
+	self
+    }
+
+    /// Mirrored ray of the `incoming` direction off a facet of the rough
+    /// reflection for a point `(u, v)` in `[0, 1)²`, `None` if it would
+    /// enter the surface.
+    pub fn glossy_reflect(&self, incoming: Vec3, u: f64, v: f64) -> Option<Ray> {
+	let roughness = self.material.reflection_roughness;
+	if roughness <= 0. {
+	    return Some(Ray::new(self.reflect.point, self.reflect.direction));
+	}
+	let h = sampling::ggx_normal(self.n, roughness * roughness, u, v);
+	let dir = reflect(incoming, h);
+	if dir.dot(self.geometric_n) <= 0. {
+	    return None;
+	}
+	Some(Ray::new(self.point, dir))
+    }
 
+    /// Radiance the surface gives off towards the viewer, only the front
+    /// glows.
+    pub fn emission(&self) -> Color {
+	if self.inside {
+	    Color::default()
+	} else {
+	    self.material.emission
+	}
+    }
+
+    /// Color of the surface at the point.
+    pub fn color(&self) -> Color {
+	self.material.color_at(self.uv, self.point)
+    }
+
+    /// Share of light mirrored at the point.
+    pub fn reflection(&self) -> f64 {
+	self.material.reflection_at(self.uv, self.point)
+    }
+
+    pub fn roughness(&self) -> f64 {
+	self.material.roughness_at(self.uv, self.point)
+    }
+
+    pub fn metallic(&self) -> f64 {
+	self.material.metallic_at(self.uv, self.point)
+    }
 }
 
-impl Intersection {
-    pub fn new(point: Point3, n: Vec3, reflect: Ray, material: Material) -> Self { Self { point, n, reflect, material } }
+/// Directions in which `u` and `v` grow over a triangle with edges `e1`
+/// and `e2` whose texture coordinates change by `d1` and `d2` along them.
+fn uv_tangents(e1: Vec3, e2: Vec3, d1: (f64, f64), d2: (f64, f64)) -> (Vec3, Vec3) {
+    let det = d1.0 * d2.1 - d2.0 * d1.1;
+    if det.abs() < f64::EPSILON {
+	return (e1, e2);
+    }
+    ((e1 * d2.1 - e2 * d1.1) * (1. / det), (e2 * d1.0 - e1 * d2.0) * (1. / det))
 }
 
 pub trait Object {
-    fn intersect(&self, ray: &Ray) -> Option<Intersection>;
+    fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>>;
+
+    /// Box enclosing the object, `None` for unbounded ones which are then
+    /// tested against every ray.
+    fn bounds(&self) -> Option<Aabb> {
+	None
+    }
+
+    /// Light given off by the parts of the object whose material glows,
+    /// `None` for objects that do not glow or cannot be sampled.
+    fn emitter(&self) -> Option<MeshLight> {
+	None
+    }
+
+    /// Whether the object bounds a participating medium.
+    fn has_medium(&self) -> bool {
+	false
+    }
 }
 
 pub struct Polygon {
     polygon: math::Polygon,
-    material: Material
+    material: Material,
+    /// Texture coordinates of the corners.
+    uvs: [(f64, f64); 3],
 }
 
 impl Polygon {
+    /// The corners get texture coordinates `(0, 0)`, `(1, 0)` and `(0, 1)`.
     pub fn new(p1: Point3, p2: Point3, p3: Point3, material: Material) -> Self {
 	Self {
 	    polygon: math::Polygon(p1, p2, p3),
-	    material }
+	    material,
+	    uvs: [(0., 0.), (1., 0.), (0., 1.)],
+	}
+    }
+
+    pub fn with_uvs(mut self, uvs: [(f64, f64); 3]) -> Self {
+	self.uvs = uvs;
+	self
     }
 }
 
 impl Object for Polygon {
-    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
 	let (p, refl, n) = self.polygon.intersection(ray)?;
-	Some(Intersection::new(p, n, Ray::new(p, refl), self.material))
+	let math::Polygon(a, b, c) = self.polygon;
+	let (e1, e2) = (b - a, c - a);
+	let normal = e1.cross(e2);
+	let inside = normal.dot(n) < 0.;
+	// Barycentric coordinates of `b` and `c`
+	let area = normal.dot(normal);
+	let (u, v) = ((p - a).cross(e2).dot(normal) / area, e1.cross(p - a).dot(normal) / area);
+	let [ta, tb, tc] = self.uvs;
+	let uv = (ta.0 * (1. - u - v) + tb.0 * u + tc.0 * v, ta.1 * (1. - u - v) + tb.1 * u + tc.1 * v);
+	let (tangent, bitangent) = uv_tangents(e1, e2, (tb.0 - ta.0, tb.1 - ta.1), (tc.0 - ta.0, tc.1 - ta.1));
+	Some(Intersection::new(p, n, Ray::new(p, refl), &self.material, inside).with_uv(uv, tangent, bitangent))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+	let math::Polygon(a, b, c) = self.polygon;
+	Some(Aabb::from_points(&[a, b, c]))
+    }
+
+    fn emitter(&self) -> Option<MeshLight> {
+	let math::Polygon(a, b, c) = self.polygon;
+	MeshLight::new(vec![(Patch::Triangle(a, b, c), self.material.emission)])
+    }
+
+    fn has_medium(&self) -> bool {
+	self.material.medium.is_some()
     }
 }
 
@@ -53,9 +218,18 @@ impl Plane {
 }
 
 impl Object for Plane {
-    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+    /// Texture coordinates are distances along two fixed directions in
+    /// the plane.
+    fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
 	let (p, refl, n) = self.plane.intersection(ray)?;
-	Some(Intersection::new(p, n, Ray::new(p, refl), self.material))
+	let inside = self.plane.0.dot(n) < 0.;
+	let (t, b) = sampling::basis(self.plane.0.norm());
+	let uv = (p.dot(t), p.dot(b));
+	Some(Intersection::new(p, n, Ray::new(p, refl), &self.material, inside).with_uv(uv, t, b))
+    }
+
+    fn has_medium(&self) -> bool {
+	self.material.medium.is_some()
     }
 }
 
@@ -74,8 +248,236 @@ impl Sphere {
 }
 
 impl Object for Sphere {
-    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+    /// Texture coordinates are longitude and latitude, `u` starts at `-z`
+    /// and goes around `+y` towards `+x`, `v` goes up from the bottom.
+    fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
 	let (p, refl, n) = self.sphere.intersection(ray)?;
-	Some(Intersection::new(p, n, Ray::new(p, refl), self.material))
+	let d = (p - self.sphere.0).norm();
+	let inside = d.dot(n) < 0.;
+	let phi = d.0.atan2(d.2);
+	let uv = (0.5 + phi / (2. * PI), 1. - d.1.clamp(-1., 1.).acos() / PI);
+	let tangent = Vec3(phi.cos(), 0., -phi.sin());
+	Some(Intersection::new(p, n, Ray::new(p, refl), &self.material, inside).with_uv(uv, tangent, Vec3(0., 1., 0.)))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+	let math::Sphere(center, radius) = self.sphere;
+	let r = Vec3(radius, radius, radius);
+	Some(Aabb::new(center - r, center + r))
+    }
+
+    fn emitter(&self) -> Option<MeshLight> {
+	let math::Sphere(center, radius) = self.sphere;
+	MeshLight::new(vec![(Patch::Sphere(center, radius), self.material.emission)])
+    }
+
+    fn has_medium(&self) -> bool {
+	self.material.medium.is_some()
+    }
+}
+
+/// Triangle of a `Mesh`, all indices point into the mesh buffers.
+#[derive(Clone, Copy, Debug)]
+pub struct Triangle {
+    pub vertices: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+    pub material: usize,
+}
+
+/// Indexed triangle mesh with shared vertex, normal and texture coordinate
+/// buffers.
+pub struct Mesh {
+    vertices: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    triangles: Vec<Triangle>,
+    materials: Vec<Material>,
+    bvh: Bvh,
+}
+
+impl Mesh {
+    pub fn new(vertices: Vec<Point3>, normals: Vec<Vec3>, uvs: Vec<(f64, f64)>, triangles: Vec<Triangle>, materials: Vec<Material>) -> Self {
+	let bounds: Vec<Aabb> = triangles.iter()
+	    .map(|t| Aabb::from_points(&[vertices[t.vertices[0]], vertices[t.vertices[1]], vertices[t.vertices[2]]]))
+	    .collect();
+	Self {
+	    bvh: Bvh::new(&bounds),
+	    vertices,
+	    normals,
+	    uvs,
+	    triangles,
+	    materials,
+	}
+    }
+
+    /// Scales the mesh around the origin and then moves it by `offset`.
+    pub fn transform(self, scale: f64, offset: Vec3) -> Self {
+	let vertices = self.vertices.iter().map(|v| *v * scale + offset).collect();
+	Mesh::new(vertices, self.normals, self.uvs, self.triangles, self.materials)
+    }
+
+    /// Replaces every material of the mesh.
+    pub fn with_material(mut self, material: Material) -> Self {
+	self.materials = vec![material];
+	for t in self.triangles.iter_mut() {
+	    t.material = 0;
+	}
+	self
+    }
+
+    pub fn vertices(&self) -> &[Point3] { &self.vertices }
+    pub fn normals(&self) -> &[Vec3] { &self.normals }
+    pub fn uvs(&self) -> &[(f64, f64)] { &self.uvs }
+    pub fn triangles(&self) -> &[Triangle] { &self.triangles }
+    pub fn materials(&self) -> &[Material] { &self.materials }
+
+    fn hit(&self, ray: &Ray, triangle: &Triangle) -> Option<(f64, f64, f64)> {
+	let [a, b, c] = triangle.vertices;
+	math::intersect_triangle(ray, self.vertices[a], self.vertices[b], self.vertices[c])
+    }
+}
+
+impl Object for Mesh {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
+	let mut res: Option<(usize, f64, f64, f64)> = None;
+	self.bvh.nearest(ray, f64::INFINITY, |i, t_max| {
+	    let (t, u, v) = self.hit(ray, &self.triangles[i]).filter(|(t, _, _)| *t < t_max)?;
+	    res = Some((i, t, u, v));
+	    Some(t)
+	});
+
+	let (i, t, u, v) = res?;
+	let triangle = &self.triangles[i];
+	let [a, b, c] = triangle.vertices;
+	let geometric_n = (self.vertices[b] - self.vertices[a]).cross(self.vertices[c] - self.vertices[a]);
+	let n = match triangle.normals {
+	    Some([na, nb, nc]) => self.normals[na] * (1. - u - v) + self.normals[nb] * u + self.normals[nc] * v,
+	    None => geometric_n,
+	};
+	let inside = geometric_n.dot(ray.direction) > 0.;
+	let (p, refl, n) = math::ray::fix_point_reflect(ray.point + ray.direction * t, ray.direction, n);
+	let material = self.materials.get(triangle.material).unwrap_or(&MISSING_MATERIAL);
+	// Without texture coordinates the triangle spans the unit one
+	let [ta, tb, tc] = match triangle.uvs {
+	    Some([ua, ub, uc]) => [self.uvs[ua], self.uvs[ub], self.uvs[uc]],
+	    None => [(0., 0.), (1., 0.), (0., 1.)],
+	};
+	let uv = (ta.0 * (1. - u - v) + tb.0 * u + tc.0 * v, ta.1 * (1. - u - v) + tb.1 * u + tc.1 * v);
+	let (tangent, bitangent) = uv_tangents(
+	    self.vertices[b] - self.vertices[a], self.vertices[c] - self.vertices[a],
+	    (tb.0 - ta.0, tb.1 - ta.1), (tc.0 - ta.0, tc.1 - ta.1));
+	Some(Intersection::new(p, n, Ray::new(p, refl), material, inside).with_uv(uv, tangent, bitangent))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+	if self.triangles.is_empty() {
+	    None
+	} else {
+	    Some(self.bvh.bounds())
+	}
+    }
+
+    fn emitter(&self) -> Option<MeshLight> {
+	let patches = self.triangles.iter()
+	    .filter_map(|t| {
+		let emission = self.materials.get(t.material)?.emission;
+		let [a, b, c] = t.vertices.map(|i| self.vertices[i]);
+		Some((Patch::Triangle(a, b, c), emission))
+	    })
+	    .filter(|(_, emission)| emission.luminance() > 0.)
+	    .collect();
+	MeshLight::new(patches)
+    }
+
+    fn has_medium(&self) -> bool {
+	self.materials.iter().any(|m| m.medium.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use super::super::material::Relief;
+    use super::super::super::texture::Texture;
+
+    /// Same color everywhere.
+    struct Flat(Color);
+
+    impl Texture for Flat {
+	fn color(&self, _: (f64, f64), _: Point3) -> Color {
+	    self.0
+	}
+    }
+
+    /// Height growing along `u`.
+    struct Ramp;
+
+    impl Texture for Ramp {
+	fn color(&self, (u, _): (f64, f64), _: Point3) -> Color {
+	    Color::rgb(u, u, u)
+	}
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+	(a - b).len() < 1e-6
+    }
+
+    #[test]
+    fn relief_normal() {
+	let (n, t, b) = (Vec3(0., 0., 1.), Vec3(1., 0., 0.), Vec3(0., 1., 0.));
+	let normal = |relief: Relief| relief.normal((0.5, 0.5), Vec3(0., 0., 0.), n, t, b);
+	let map = |r, g, b| Relief::Normal(Arc::new(Flat(Color::rgb(r, g, b))));
+
+	assert!(close(normal(map(0.5, 0.5, 1.)), n));
+	let bent = normal(map(1., 0.5, 1.));
+	assert!(close(bent, Vec3(1., 0., 1.).norm()), "{:?}", bent);
+	let bent = normal(map(0.5, 0., 1.));
+	assert!(close(bent, Vec3(0., -1., 1.).norm()), "{:?}", bent);
+
+	// Heights rising along the tangent tilt the normal back against it
+	let bent = normal(Relief::Bump(Arc::new(Ramp), 0.5));
+	assert!(close(bent, Vec3(-0.5, 0., 1.).norm()), "{:?}", bent);
+	assert!(close(normal(Relief::Bump(Arc::new(Flat(Color::white())), 2.)), n));
+    }
+
+    #[test]
+    fn uv_frame() {
+	let material = Material::new(Color::white(), 0.);
+	let int = |bitangent| {
+	    let incoming = Vec3(0., 0., -1.);
+	    Intersection::new(Vec3(0., 0., 0.), Vec3(0., 0., 1.), Ray::new(Vec3(0., 0., 0.), reflect(incoming, Vec3(0., 0., 1.))), &material, false)
+		.with_uv((0.5, 0.5), Vec3(2., 0., 1.), bitangent)
+	};
+
+	let straight = int(Vec3(0., 3., 0.));
+	assert!(close(straight.tangent, Vec3(1., 0., 0.)));
+	assert!(close(straight.bitangent, Vec3(0., 1., 0.)));
+	// Mirrored texture coordinates keep the tangent and flip the
+	// bitangent
+	let mirrored = int(Vec3(0., -3., 0.));
+	assert!(close(mirrored.tangent, Vec3(1., 0., 0.)));
+	assert!(close(mirrored.bitangent, Vec3(0., -1., 0.)));
+    }
+
+    #[test]
+    fn relief_faces_viewer() {
+	let n = Vec3(0., 0., 1.);
+	for (r, g) in [(0., 0.5), (1., 0.5), (0.5, 0.), (0.5, 1.), (0., 0.)] {
+	    let mut material = Material::new(Color::white(), 0.);
+	    material.relief = Some(Relief::Normal(Arc::new(Flat(Color::rgb(r, g, 0.6)))));
+	    for i in 0..16 {
+		let phi = i as f64 * PI / 8.;
+		// Grazing rays from all around
+		let incoming = Vec3(phi.cos(), phi.sin(), -0.05).norm();
+		let int = Intersection::new(Vec3(0., 0., 0.), n, Ray::new(Vec3(0., 0., 0.), reflect(incoming, n)), &material, false)
+		    .with_uv((0.5, 0.5), Vec3(1., 0., 0.), Vec3(0., 1., 0.))
+		    .with_relief();
+		assert!(int.n.dot(incoming) < 0., "{:?} {:?}", int.n, incoming);
+		assert!(int.reflect.direction.dot(int.geometric_n) > 0.);
+	    }
+	}
     }
 }
@@ -1,88 +1,255 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::super::image::Image;
+use super::super::ies::Profile;
 use super::super::math::*;
+use super::super::sampling::{self, Distribution, Rng};
 
-use super::figures::Object;
+use super::bodies::Bodies;
+use super::brdf::Brdf;
 use super::figures::Intersection;
 use super::material::Color;
-use super::material::Material;
 
-#[derive(Clone, Copy)]
-pub struct LightColor {
-    color: Option<Color>,
-    intensity: f64,
+/// Light of `color` arriving from `dir` and reflected towards the viewer,
+/// `irradiance` is what it gives on a surface facing it.
+fn calc_light(dir: Vec3, irradiance: f64, color: Option<Color>, brdf: &Brdf) -> Color {
+    let dir = dir.norm();
+    let cos = dir.dot(brdf.normal()).max(0.);
+    brdf.eval(dir).mul(light_color(color)).mul_float(irradiance * cos)
 }
 
-impl LightColor {
-    pub fn new(color: Option<Color>, intensity: f64) -> Self { Self { color, intensity } }
-    pub fn add(&self, other: LightColor) -> LightColor {
-	if self.intensity < f64::EPSILON {
-	    other
-	} else if other.intensity < f64::EPSILON  {
-	    self.clone()
-	} else {
-	    let fact = other.intensity / (self.intensity + other.intensity);
-	    let intensity = self.intensity + other.intensity;
-	    if let Some(color) = self.color {
-		if let Some(other_color) = other.color {
-		    LightColor::new(Some(color.mul_float(1. - fact).add(other_color.mul_float(fact))), intensity)
-		} else {
-		    LightColor::new(Some(color), intensity)
-		}
-	    } else if let Some(other_color) = other.color {
-		LightColor::new(Some(other_color), intensity)
-	    } else {
-		LightColor::new(None, self.intensity + other.intensity)
-	    }
-	}
+/// Light arriving at a point from one direction.
+pub struct LightSample {
+    /// Unit vector towards the light.
+    pub direction: Vec3,
+    /// Distance to the light, nothing closer may block it.
+    pub distance: f64,
+    /// Irradiance on a surface facing the light, divided by the
+    /// probability of picking this direction. Directional and ambient
+    /// lights of intensity `1.` give `π` so that a white diffuse surface
+    /// facing them shows radiance about `1.`, as with `calc`.
+    pub irradiance: Color,
+    /// Probability density of picking the direction for lights rays
+    /// leaving a surface may find as well, `None` for lights only found
+    /// by sampling them.
+    pub pdf: Option<f64>,
+}
+
+pub trait Light {
+    /// Radiance the light makes the surface at `intersection` reflect
+    /// towards the viewer, `None` if none reaches it.
+    fn calc(&self, intersection: &Intersection, brdf: &Brdf, bodies: &Bodies) -> Option<Color>;
+
+    /// Picks light reaching `point` for path tracing, `None` for lights
+    /// that are only found by rays leaving the scene.
+    fn sample(&self, _point: Point3, _rng: &mut Rng) -> Option<LightSample> {
+	None
     }
-    pub fn calc_color(&self, material: Material) -> Color {
-	if let Some(color) = self.color {
-	    // FIXME
-	    material.color.mul_float(self.intensity * 3. / 4.).add(color.mul_float(self.intensity / 4.)) 
-	} else {
-	    material.color.mul_float(self.intensity)
-	}
+
+    /// Radiance of rays leaving the scene in `direction`.
+    fn background(&self, _direction: Vec3) -> Color {
+	Color::default()
+    }
+
+    /// Probability density of `sample` picking `direction` towards the
+    /// background, zero for lights that are not seen there.
+    fn pdf(&self, _direction: Vec3) -> f64 {
+	0.
     }
 }
 
-fn calc_light(dir: Vec3, ray: &Ray, intensity: f64, color: Option<Color>, intersection: &Intersection) -> LightColor {
-	let diffuse = intensity * dir.dot(intersection.n) / (dir.len() * intersection.n.len());
-	let shine_base = intersection.reflect.direction.dot(ray.direction) / (intersection.reflect.direction.len() * ray.direction.len()) * -1.;
-	LightColor::new(color, diffuse + if let Some(shine) = intersection.material.shine {
-	    intensity * shine_base.max(0.).powi(shine)
-	} else {
-	    0.
-	})
+impl<T: Light + ?Sized> Light for Arc<T> {
+    fn calc(&self, intersection: &Intersection, brdf: &Brdf, bodies: &Bodies) -> Option<Color> {
+	(**self).calc(intersection, brdf, bodies)
+    }
+
+    fn sample(&self, point: Point3, rng: &mut Rng) -> Option<LightSample> {
+	(**self).sample(point, rng)
+    }
+
+    fn background(&self, direction: Vec3) -> Color {
+	(**self).background(direction)
+    }
+
+    fn pdf(&self, direction: Vec3) -> f64 {
+	(**self).pdf(direction)
+    }
 }
 
-pub trait Light {
-    fn calc(&self, ray: &Ray, intersection: &Intersection, it: &[Box<dyn Object + Send + Sync>]) -> Option<LightColor>;
+fn light_color(color: Option<Color>) -> Color {
+    color.unwrap_or_else(Color::white)
+}
+
+/// Falloff of point-like lights with the distance `d`. Their light is
+/// divided by `constant + linear·d + quadratic·d²` and smoothly fades out
+/// to nothing at `range`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attenuation {
+    pub constant: f64,
+    pub linear: f64,
+    pub quadratic: f64,
+    pub range: Option<f64>,
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+	Attenuation::inverse_square()
+    }
+}
+
+impl Attenuation {
+    pub fn new(constant: f64, linear: f64, quadratic: f64) -> Self { Self { constant, linear, quadratic, range: None } }
+
+    /// Physical falloff of light spreading from a point.
+    pub fn inverse_square() -> Self { Self::new(0., 0., 1.) }
+
+    /// Share of the light left at distance `d`.
+    pub fn factor(&self, d: f64) -> f64 {
+	let window = match self.range {
+	    Some(range) => (1. - (d / range).powi(4)).max(0.).powi(2),
+	    None => 1.,
+	};
+	let denominator = self.constant + self.linear * d + self.quadratic * d * d;
+	if denominator <= 0. {
+	    return 0.;
+	}
+	window / denominator
+    }
 }
 
+/// Light spreading from a point. `intensity` is the radiant intensity in
+/// W/sr, the irradiance it gives is `intensity` divided by the squared
+/// distance unless the attenuation says otherwise.
 pub struct PointLight {
     position: Point3,
     color: Option<Color>,
     intensity: f64,
+    attenuation: Attenuation,
 }
 
 impl PointLight {
-    pub fn new(position: Point3, intensity: f64) -> Self { Self { position, color: None, intensity } }
-    pub fn new_color(position: Point3, intensity: f64, color: Color) -> Self { Self { position, color: Some(color), intensity } }
+    pub fn new(position: Point3, intensity: f64) -> Self { Self { position, color: None, intensity, attenuation: Attenuation::default() } }
+    pub fn new_color(position: Point3, intensity: f64, color: Color) -> Self { Self { color: Some(color), ..Self::new(position, intensity) } }
+
+    pub fn set_attenuation(&mut self, attenuation: Attenuation) {
+	self.attenuation = attenuation;
+    }
 }
 
 impl Light for PointLight {
-    fn calc(&self, origin_ray: &Ray, intersection: &Intersection, it: &[Box<dyn Object + Send + Sync>]) -> Option<LightColor> {
+    fn calc(&self, intersection: &Intersection, brdf: &Brdf, bodies: &Bodies) -> Option<Color> {
 	let p = intersection.point;
 	let dist = p.distance(self.position);
 	let dir = self.position - p;
 	let ray = Ray::new(p, dir);
-	for object in it {
-	    if let Some(int) = object.intersect(&ray) {
-		if ray.distance(int.point) < dist {
-		    return None;
-		}
-	    }
+	let irradiance = self.intensity * self.attenuation.factor(dist);
+	if irradiance <= 0. || bodies.occluded(&ray, dist) {
+	    return None;
+	}
+	Some(calc_light(dir, irradiance, self.color, brdf))
+    }
+
+    fn sample(&self, point: Point3, _: &mut Rng) -> Option<LightSample> {
+	let dir = self.position - point;
+	Some(LightSample {
+	    direction: dir.norm(),
+	    distance: dir.len(),
+	    irradiance: light_color(self.color).mul_float(self.intensity * self.attenuation.factor(dir.len())),
+	    pdf: None,
+	})
+    }
+}
+
+/// Point light shining into a cone. Full intensity inside the inner
+/// angle smoothly fades out towards the outer one, an optional IES profile
+/// shapes the distribution within the cone. Intensity and attenuation are
+/// those of `PointLight`.
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3,
+    cos_inner: f64,
+    cos_outer: f64,
+    color: Option<Color>,
+    intensity: f64,
+    attenuation: Attenuation,
+    profile: Option<Arc<Profile>>,
+}
+
+impl SpotLight {
+    /// Cone angles are measured from `direction` in radians.
+    pub fn new(position: Point3, direction: Vec3, inner: f64, outer: f64, intensity: f64) -> Self {
+	Self {
+	    position,
+	    direction: direction.norm(),
+	    cos_inner: inner.cos(),
+	    cos_outer: outer.cos(),
+	    color: None,
+	    intensity,
+	    attenuation: Attenuation::default(),
+	    profile: None,
+	}
+    }
+    pub fn new_color(position: Point3, direction: Vec3, inner: f64, outer: f64, intensity: f64, color: Color) -> Self {
+	Self { color: Some(color), ..Self::new(position, direction, inner, outer, intensity) }
+    }
+
+    pub fn set_attenuation(&mut self, attenuation: Attenuation) {
+	self.attenuation = attenuation;
+    }
+
+    /// Shapes the light by a photometric profile, its nadir points along
+    /// the spot direction.
+    pub fn set_profile(&mut self, profile: Arc<Profile>) {
+	self.profile = Some(profile);
+    }
+
+    /// Share of the intensity sent along the unit vector `dir`.
+    fn falloff(&self, dir: Vec3) -> f64 {
+	let cos = self.direction.dot(dir);
+	let cone = if cos >= self.cos_inner {
+	    1.
+	} else if cos <= self.cos_outer {
+	    0.
+	} else {
+	    let t = (cos - self.cos_outer) / (self.cos_inner - self.cos_outer);
+	    t * t * (3. - 2. * t)
+	};
+	match &self.profile {
+	    Some(profile) if cone > 0. => {
+		let (t, b) = sampling::basis(self.direction);
+		let vertical = cos.clamp(-1., 1.).acos();
+		let horizontal = dir.dot(b).atan2(dir.dot(t));
+		cone * profile.intensity(vertical, horizontal)
+	    },
+	    _ => cone,
 	}
-	Some(calc_light(dir, &origin_ray, self.intensity, self.color, intersection))
+    }
+}
+
+impl Light for SpotLight {
+    fn calc(&self, intersection: &Intersection, brdf: &Brdf, bodies: &Bodies) -> Option<Color> {
+	let p = intersection.point;
+	let dir = self.position - p;
+	let irradiance = self.intensity * self.falloff((-1. * dir).norm()) * self.attenuation.factor(dir.len());
+	if irradiance <= 0. || bodies.occluded(&Ray::new(p, dir), dir.len()) {
+	    return None;
+	}
+	Some(calc_light(dir, irradiance, self.color, brdf))
+    }
+
+    fn sample(&self, point: Point3, _: &mut Rng) -> Option<LightSample> {
+	let dir = self.position - point;
+	let irradiance = self.intensity * self.falloff((-1. * dir).norm()) * self.attenuation.factor(dir.len());
+	if irradiance <= 0. {
+	    return None;
+	}
+	Some(LightSample {
+	    direction: dir.norm(),
+	    distance: dir.len(),
+	    irradiance: light_color(self.color).mul_float(irradiance),
+	    pdf: None,
+	})
     }
 }
 
@@ -93,11 +260,18 @@ pub struct AmbientLight {
 
 impl AmbientLight {
     pub fn new(intensity: f64) -> Self { Self { color: None, intensity } }
+    pub fn new_color(intensity: f64, color: Color) -> Self { Self { color: Some(color), intensity } }
 }
 
 impl Light for AmbientLight {
-    fn calc(&self, _: &Ray, intersection: &Intersection, _: &[Box<dyn Object + Send + Sync>]) -> Option<LightColor> {
-	Some(LightColor::new(self.color, self.intensity))
+    fn calc(&self, _: &Intersection, brdf: &Brdf, _: &Bodies) -> Option<Color> {
+	Some(brdf.albedo().mul(light_color(self.color)).mul_float(self.intensity))
+    }
+
+    /// Surrounds the scene evenly, so unoccluded diffuse surfaces get the
+    /// same light as from `calc`.
+    fn background(&self, _: Vec3) -> Color {
+	light_color(self.color).mul_float(self.intensity)
     }
 }
 
@@ -109,16 +283,606 @@ pub struct DirectLight {
 
 impl DirectLight {
     pub fn new(direction: Vec3, intensity: f64) -> Self { Self { color: None, intensity, direction } }
+    pub fn new_color(direction: Vec3, intensity: f64, color: Color) -> Self { Self { color: Some(color), intensity, direction } }
 }
 
 impl Light for DirectLight {
-    fn calc(&self, origin_ray: &Ray, intersection: &Intersection, it: &[Box<dyn Object + Send + Sync>]) -> Option<LightColor> {
+    fn calc(&self, intersection: &Intersection, brdf: &Brdf, bodies: &Bodies) -> Option<Color> {
 	let ray = Ray::new(intersection.point, -1. * self.direction);
-	for object in it {
-	    if let Some(_) = object.intersect(&ray) {
-		return None
-	    }
+	if bodies.occluded(&ray, f64::INFINITY) {
+	    return None
+	}
+	Some(calc_light(-1. * self.direction, self.intensity * PI, self.color, brdf))
+    }
+
+    fn sample(&self, _: Point3, _: &mut Rng) -> Option<LightSample> {
+	Some(LightSample {
+	    direction: (-1. * self.direction).norm(),
+	    distance: f64::INFINITY,
+	    irradiance: light_color(self.color).mul_float(self.intensity * PI),
+	    pdf: None,
+	})
+    }
+}
+
+/// Points shading takes on the surface of an area light by default.
+pub const AREA_LIGHT_SAMPLES: u32 = 16;
+
+/// Surface of radiance `intensity`, maps `[0, 1)²` to (point, area-measure weight).
+struct Emitter {
+    color: Option<Color>,
+    intensity: f64,
+    samples: u32,
+}
+
+impl Emitter {
+    fn new(intensity: f64, color: Option<Color>) -> Self {
+	Self { color, intensity, samples: AREA_LIGHT_SAMPLES }
+    }
+
+    fn calc<F>(&self, point: F, intersection: &Intersection, brdf: &Brdf, bodies: &Bodies) -> Option<Color>
+    where F: Fn(f64, f64) -> Option<(Point3, f64)> {
+	let p = intersection.point;
+	let share = self.intensity / self.samples as f64;
+	let total = (0..self.samples)
+	    .filter_map(|i| {
+		let (u, v) = sampling::halton(i);
+		let (q, weight) = point(u, v)?;
+		let dir = q - p;
+		if bodies.occluded(&Ray::new(p, dir), dir.len()) {
+		    return None;
+		}
+		Some(calc_light(dir, share * weight / dir.dot(dir), self.color, brdf))
+	    })
+	    .fold(Color::default(), |res, color| res.add(color));
+	Some(total)
+    }
+
+    /// Picks a point of the surface. Bounces never hit area lights, so
+    /// the sample is left without a density and takes full weight.
+    fn sample<F>(&self, point: F, p: Point3, rng: &mut Rng) -> Option<LightSample>
+    where F: Fn(f64, f64) -> Option<(Point3, f64)> {
+	let (q, weight) = point(rng.next_f64(), rng.next_f64())?;
+	let dir = q - p;
+	Some(LightSample {
+	    direction: dir.norm(),
+	    distance: dir.len(),
+	    irradiance: light_color(self.color).mul_float(self.intensity * weight / dir.dot(dir)),
+	    pdf: None,
+	})
+    }
+}
+
+/// Weight of the point `q` standing for `area` of a one-sided emitter
+/// with normal `n`, as seen from `p`.
+fn emitted(q: Point3, n: Vec3, area: f64, p: Point3) -> Option<(Point3, f64)> {
+    let cos = n.dot((p - q).norm());
+    if cos <= 0. {
+	None
+    } else {
+	Some((q, area * cos))
+    }
+}
+
+/// Parallelogram shining to the side of `u × v`.
+pub struct RectLight {
+    center: Point3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    emitter: Emitter,
+}
+
+impl RectLight {
+    /// Light spanned by the edges `u` and `v` around `center`.
+    pub fn new(center: Point3, u: Vec3, v: Vec3, intensity: f64) -> Self {
+	Self { center, u, v, normal: u.cross(v).norm(), emitter: Emitter::new(intensity, None) }
+    }
+    pub fn new_color(center: Point3, u: Vec3, v: Vec3, intensity: f64, color: Color) -> Self {
+	Self { center, u, v, normal: u.cross(v).norm(), emitter: Emitter::new(intensity, Some(color)) }
+    }
+
+    /// Sets the number of points Whitted shading takes on the surface.
+    pub fn set_samples(&mut self, samples: u32) {
+	self.emitter.samples = samples;
+    }
+
+    fn point(&self, p: Point3, a: f64, b: f64) -> Option<(Point3, f64)> {
+	let area = self.u.cross(self.v).len();
+	emitted(self.center + self.u * (a - 0.5) + self.v * (b - 0.5), self.normal, area, p)
+    }
+}
+
+impl Light for RectLight {
+    fn calc(&self, intersection: &Intersection, brdf: &Brdf, bodies: &Bodies) -> Option<Color> {
+	let p = intersection.point;
+	self.emitter.calc(|a, b| self.point(p, a, b), intersection, brdf, bodies)
+    }
+
+    fn sample(&self, p: Point3, rng: &mut Rng) -> Option<LightSample> {
+	self.emitter.sample(|a, b| self.point(p, a, b), p, rng)
+    }
+}
+
+/// Disk shining to the side of its normal.
+pub struct DiskLight {
+    center: Point3,
+    normal: Vec3,
+    radius: f64,
+    emitter: Emitter,
+}
+
+impl DiskLight {
+    pub fn new(center: Point3, normal: Vec3, radius: f64, intensity: f64) -> Self {
+	Self { center, normal: normal.norm(), radius, emitter: Emitter::new(intensity, None) }
+    }
+    pub fn new_color(center: Point3, normal: Vec3, radius: f64, intensity: f64, color: Color) -> Self {
+	Self { center, normal: normal.norm(), radius, emitter: Emitter::new(intensity, Some(color)) }
+    }
+
+    /// Sets the number of points Whitted shading takes on the surface.
+    pub fn set_samples(&mut self, samples: u32) {
+	self.emitter.samples = samples;
+    }
+
+    fn point(&self, p: Point3, a: f64, b: f64) -> Option<(Point3, f64)> {
+	let (t, s) = sampling::basis(self.normal);
+	let (r, phi) = (self.radius * a.sqrt(), 2. * PI * b);
+	let area = PI * self.radius * self.radius;
+	emitted(self.center + t * (r * phi.cos()) + s * (r * phi.sin()), self.normal, area, p)
+    }
+}
+
+impl Light for DiskLight {
+    fn calc(&self, intersection: &Intersection, brdf: &Brdf, bodies: &Bodies) -> Option<Color> {
+	let p = intersection.point;
+	self.emitter.calc(|a, b| self.point(p, a, b), intersection, brdf, bodies)
+    }
+
+    fn sample(&self, p: Point3, rng: &mut Rng) -> Option<LightSample> {
+	self.emitter.sample(|a, b| self.point(p, a, b), p, rng)
+    }
+}
+
+/// Ball shining evenly in all directions.
+pub struct SphereLight {
+    center: Point3,
+    radius: f64,
+    emitter: Emitter,
+}
+
+impl SphereLight {
+    pub fn new(center: Point3, radius: f64, intensity: f64) -> Self {
+	Self { center, radius, emitter: Emitter::new(intensity, None) }
+    }
+    pub fn new_color(center: Point3, radius: f64, intensity: f64, color: Color) -> Self {
+	Self { center, radius, emitter: Emitter::new(intensity, Some(color)) }
+    }
+
+    /// Sets the number of points Whitted shading takes on the surface.
+    pub fn set_samples(&mut self, samples: u32) {
+	self.emitter.samples = samples;
+    }
+
+    /// Points of the ball facing `p`, picked evenly over the cone it
+    /// fills as seen from there.
+    fn point(&self, p: Point3, a: f64, b: f64) -> Option<(Point3, f64)> {
+	let axis = self.center - p;
+	let dist2 = axis.dot(axis);
+	let r2 = self.radius * self.radius;
+	if dist2 <= r2 {
+	    return None;
+	}
+	let cos_max = (1. - r2 / dist2).sqrt();
+	let dir = sampling::uniform_cone(axis.norm(), cos_max, a, b);
+	let along = dir.dot(axis);
+	let t = along - (r2 - (dist2 - along * along)).max(0.).sqrt();
+	let solid_angle = 2. * PI * (1. - cos_max);
+	Some((p + dir * t, solid_angle * t * t))
+    }
+}
+
+impl Light for SphereLight {
+    fn calc(&self, intersection: &Intersection, brdf: &Brdf, bodies: &Bodies) -> Option<Color> {
+	let p = intersection.point;
+	self.emitter.calc(|a, b| self.point(p, a, b), intersection, brdf, bodies)
+    }
+
+    fn sample(&self, p: Point3, rng: &mut Rng) -> Option<LightSample> {
+	self.emitter.sample(|a, b| self.point(p, a, b), p, rng)
+    }
+}
+
+/// Part of the way to a point of glowing geometry that shadow rays test,
+/// so the geometry does not shadow itself.
+const SHADOW_REACH: f64 = 1. - 1e-4;
+
+/// Piece of glowing geometry, it shines to the outside.
+#[derive(Clone, Copy, Debug)]
+pub enum Patch {
+    /// Triangle `a`, `b`, `c` shining to the side of `(b - a) × (c - a)`.
+    Triangle(Point3, Point3, Point3),
+    /// Ball of the given center and radius.
+    Sphere(Point3, f64),
+}
+
+impl Patch {
+    fn area(&self) -> f64 {
+	match *self {
+	    Patch::Triangle(a, b, c) => (b - a).cross(c - a).len() / 2.,
+	    Patch::Sphere(_, r) => 4. * PI * r * r,
+	}
+    }
+
+    /// Point evenly spread over the surface for `(u, v)` in `[0, 1)²`,
+    /// with the unit normal there.
+    fn point(&self, u: f64, v: f64) -> (Point3, Vec3) {
+	match *self {
+	    Patch::Triangle(a, b, c) => {
+		let s = u.sqrt();
+		(a * (1. - s) + b * (s * (1. - v)) + c * (s * v), (b - a).cross(c - a).norm())
+	    },
+	    Patch::Sphere(center, r) => {
+		let (z, phi) = (1. - 2. * u, 2. * PI * v);
+		let sin = (1. - z * z).max(0.).sqrt();
+		let n = Vec3(sin * phi.cos(), sin * phi.sin(), z);
+		(center + n * r, n)
+	    },
+	}
+    }
+}
+
+/// Geometry of an object whose material glows, the `emission` of each
+/// patch is its radiance. Patches are picked by their share of the light.
+pub struct MeshLight {
+    patches: Vec<(Patch, Color)>,
+    distribution: Distribution,
+    samples: u32,
+}
+
+impl MeshLight {
+    /// `None` when no patch gives off light.
+    pub fn new(patches: Vec<(Patch, Color)>) -> Option<Self> {
+	let weights: Vec<_> = patches.iter().map(|(patch, emission)| patch.area() * emission.luminance()).collect();
+	let distribution = Distribution::new(&weights)?;
+	Some(Self { patches, distribution, samples: AREA_LIGHT_SAMPLES })
+    }
+
+    /// Sets the number of points Whitted shading takes on the surface.
+    pub fn set_samples(&mut self, samples: u32) {
+	self.samples = samples;
+    }
+
+    /// Point lighting `p` for `(u, v)` in `[0, 1)²`, with the unit vector
+    /// and distance towards it, its radiance and the probability density
+    /// of the direction.
+    fn pick(&self, p: Point3, u: f64, v: f64) -> Option<(Vec3, f64, Color, f64)> {
+	let (x, i) = self.distribution.sample(u);
+	// Position within the step picks the point on the patch
+	let u = (x * self.patches.len() as f64 - i as f64).clamp(0., 1.);
+	let (patch, emission) = &self.patches[i];
+	let (q, n) = patch.point(u, v);
+	let (q, cos) = emitted(q, n, 1., p)?;
+	let dist = p.distance(q);
+	if dist <= 0. {
+	    return None;
 	}
-	Some(calc_light(-1. * self.direction, &origin_ray, self.intensity, self.color, intersection))
+	let pdf = self.area_pdf(*emission) * dist * dist / cos;
+	Some(((q - p) / dist, dist, *emission, pdf))
+    }
+
+    /// Probability density of points glowing with `emission` per unit of
+    /// area.
+    fn area_pdf(&self, emission: Color) -> f64 {
+	emission.luminance() / self.distribution.total()
+    }
+
+    /// Probability density of `sample` picking the direction from
+    /// `origin` towards the surface point of `intersection`, which must
+    /// belong to this light.
+    pub fn pdf_at(&self, origin: Point3, intersection: &Intersection) -> f64 {
+	let dir = origin - intersection.point;
+	let dist = dir.len();
+	let cos = intersection.geometric_n.dot(dir) / dist;
+	if intersection.inside || cos <= 0. {
+	    return 0.;
+	}
+	self.area_pdf(intersection.material.emission) * dist * dist / cos
+    }
+}
+
+impl Light for MeshLight {
+    fn calc(&self, intersection: &Intersection, brdf: &Brdf, bodies: &Bodies) -> Option<Color> {
+	let p = intersection.point;
+	let total = (0..self.samples)
+	    .filter_map(|i| {
+		let (u, v) = sampling::halton(i);
+		let (dir, dist, emission, pdf) = self.pick(p, u, v)?;
+		if bodies.occluded(&Ray::new(p, dir), dist * SHADOW_REACH) {
+		    return None;
+		}
+		Some(calc_light(dir, 1. / (pdf * self.samples as f64), Some(emission), brdf))
+	    })
+	    .fold(Color::default(), |res, color| res.add(color));
+	Some(total)
+    }
+
+    fn sample(&self, p: Point3, rng: &mut Rng) -> Option<LightSample> {
+	let (direction, dist, emission, pdf) = self.pick(p, rng.next_f64(), rng.next_f64())?;
+	Some(LightSample {
+	    direction,
+	    distance: dist * SHADOW_REACH,
+	    irradiance: emission.div(pdf),
+	    pdf: Some(pdf),
+	})
+    }
+}
+
+/// Directions Whitted shading takes towards an environment by default.
+pub const ENVIRONMENT_SAMPLES: u32 = 64;
+
+/// Equirectangular image surrounding the scene at infinite distance. The
+/// top of the image is up along `y` and its center lies towards `+z`.
+/// Bright parts of the image are picked more often when sampling.
+pub struct EnvironmentLight {
+    image: Arc<Image>,
+    intensity: f64,
+    /// Turn around `y` in radians.
+    rotation: f64,
+    samples: u32,
+    /// Rows of the image by their share of the light, `None` for a black
+    /// image.
+    rows: Option<Distribution>,
+    /// Pixels of every row by their share of its light.
+    columns: Vec<Option<Distribution>>,
+}
+
+impl EnvironmentLight {
+    /// An `intensity` of `1.` shows the image as it is.
+    pub fn new(image: Arc<Image>, intensity: f64) -> Self {
+	let (width, height) = (image.width(), image.height());
+	let columns: Vec<_> = (0..height)
+	    .map(|y| {
+		// Rows near the poles cover a smaller solid angle
+		let sin = (PI * (y as f64 + 0.5) / height as f64).sin();
+		let weights: Vec<_> = (0..width).map(|x| image.get(x, y).luminance() * sin).collect();
+		Distribution::new(&weights)
+	    })
+	    .collect();
+	let rows = Distribution::new(&columns.iter().map(|c| c.as_ref().map_or(0., Distribution::total)).collect::<Vec<_>>());
+	Self { image, intensity, rotation: 0., samples: ENVIRONMENT_SAMPLES, rows, columns }
+    }
+
+    pub fn set_rotation(&mut self, rotation: f64) {
+	self.rotation = rotation;
+    }
+
+    /// Sets the number of directions Whitted shading takes.
+    pub fn set_samples(&mut self, samples: u32) {
+	self.samples = samples;
+    }
+
+    fn direction(&self, u: f64, v: f64) -> Vec3 {
+	let (theta, phi) = (PI * v, 2. * PI * (u - 0.5) + self.rotation);
+	Vec3(theta.sin() * phi.sin(), theta.cos(), theta.sin() * phi.cos())
+    }
+
+    /// Image coordinates in `[0, 1)²` of the unit vector `dir`.
+    fn coords(&self, dir: Vec3) -> (f64, f64) {
+	let phi = dir.0.atan2(dir.2) - self.rotation;
+	let u = (phi / (2. * PI) + 0.5).rem_euclid(1.);
+	(u, dir.1.clamp(-1., 1.).acos() / PI)
+    }
+
+    fn radiance(&self, u: f64, v: f64) -> Color {
+	let x = ((u * self.image.width() as f64) as usize).min(self.image.width() - 1);
+	let y = ((v * self.image.height() as f64) as usize).min(self.image.height() - 1);
+	self.image.get(x, y).mul_float(self.intensity)
+    }
+
+    /// Direction following the brightness of the image for a point in
+    /// `[0, 1)²`, with its radiance and probability density.
+    fn pick(&self, a: f64, b: f64) -> Option<(Vec3, Color, f64)> {
+	let rows = self.rows.as_ref()?;
+	let (v, row) = rows.sample(b);
+	let columns = self.columns[row].as_ref()?;
+	let (u, _) = columns.sample(a);
+	let sin = (PI * v).sin();
+	if sin <= 0. {
+	    return None;
+	}
+	let pdf = rows.pdf(v) * columns.pdf(u) / (2. * PI * PI * sin);
+	Some((self.direction(u, v), self.radiance(u, v), pdf))
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn calc(&self, intersection: &Intersection, brdf: &Brdf, bodies: &Bodies) -> Option<Color> {
+	let p = intersection.point;
+	let res = (0..self.samples)
+	    .filter_map(|i| {
+		let (a, b) = sampling::halton(i);
+		let (dir, radiance, pdf) = self.pick(a, b)?;
+		if dir.dot(intersection.n) <= 0. || bodies.occluded(&Ray::new(p, dir), f64::INFINITY) {
+		    return None;
+		}
+		Some(calc_light(dir, 1. / (pdf * self.samples as f64), Some(radiance), brdf))
+	    })
+	    .fold(Color::default(), |res, color| res.add(color));
+	Some(res)
+    }
+
+    fn sample(&self, _: Point3, rng: &mut Rng) -> Option<LightSample> {
+	let (dir, radiance, pdf) = self.pick(rng.next_f64(), rng.next_f64())?;
+	Some(LightSample {
+	    direction: dir,
+	    distance: f64::INFINITY,
+	    irradiance: radiance.div(pdf),
+	    pdf: Some(pdf),
+	})
+    }
+
+    fn background(&self, direction: Vec3) -> Color {
+	let (u, v) = self.coords(direction.norm());
+	self.radiance(u, v)
+    }
+
+    fn pdf(&self, direction: Vec3) -> f64 {
+	let (rows, (u, v)) = match &self.rows {
+	    Some(rows) => (rows, self.coords(direction.norm())),
+	    None => return 0.,
+	};
+	let row = ((v * self.image.height() as f64) as usize).min(self.image.height() - 1);
+	let sin = (PI * v).sin();
+	match &self.columns[row] {
+	    Some(columns) if sin > 0. => rows.pdf(v) * columns.pdf(u) / (2. * PI * PI * sin),
+	    _ => 0.,
+	}
+    }
+}
+
+/// Disk of the sun seen from the ground, parallel light from a small cone
+/// of directions casting slightly soft shadows.
+pub struct SunLight {
+    /// Unit vector towards the sun.
+    direction: Vec3,
+    cos_radius: f64,
+    radiance: Color,
+    samples: u32,
+}
+
+impl SunLight {
+    /// Sun of the given angular `radius` in radians towards `direction`.
+    pub fn new(direction: Vec3, radius: f64, radiance: Color) -> Self {
+	Self { direction: direction.norm(), cos_radius: radius.cos(), radiance, samples: AREA_LIGHT_SAMPLES }
+    }
+
+    /// Sets the number of directions Whitted shading takes.
+    pub fn set_samples(&mut self, samples: u32) {
+	self.samples = samples;
+    }
+
+    fn solid_angle(&self) -> f64 {
+	2. * PI * (1. - self.cos_radius)
+    }
+}
+
+impl Light for SunLight {
+    fn calc(&self, intersection: &Intersection, brdf: &Brdf, bodies: &Bodies) -> Option<Color> {
+	let p = intersection.point;
+	let share = self.solid_angle() / self.samples as f64;
+	let total = (0..self.samples)
+	    .filter_map(|i| {
+		let (u, v) = sampling::halton(i);
+		let dir = sampling::uniform_cone(self.direction, self.cos_radius, u, v);
+		if bodies.occluded(&Ray::new(p, dir), f64::INFINITY) {
+		    return None;
+		}
+		Some(calc_light(dir, share, Some(self.radiance), brdf))
+	    })
+	    .fold(Color::default(), |res, color| res.add(color));
+	Some(total)
+    }
+
+    fn sample(&self, _: Point3, rng: &mut Rng) -> Option<LightSample> {
+	Some(LightSample {
+	    direction: sampling::uniform_cone(self.direction, self.cos_radius, rng.next_f64(), rng.next_f64()),
+	    distance: f64::INFINITY,
+	    irradiance: self.radiance.mul_float(self.solid_angle()),
+	    pdf: Some(1. / self.solid_angle()),
+	})
+    }
+
+    fn background(&self, direction: Vec3) -> Color {
+	if direction.norm().dot(self.direction) >= self.cos_radius {
+	    self.radiance
+	} else {
+	    Color::default()
+	}
+    }
+
+    fn pdf(&self, direction: Vec3) -> f64 {
+	if direction.norm().dot(self.direction) >= self.cos_radius {
+	    1. / self.solid_angle()
+	} else {
+	    0.
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::figures::Polygon;
+    use super::super::material::Material;
+
+    #[test]
+    fn attenuation() {
+	let close = |a: f64, b: f64| (a - b).abs() < 1e-12;
+	let inverse_square = Attenuation::default();
+	assert!(close(inverse_square.factor(1.), 1.));
+	assert!(close(inverse_square.factor(2.), 0.25));
+	assert!(close(inverse_square.factor(10.), 0.01));
+
+	let terms = Attenuation::new(1., 0.5, 0.25);
+	assert!(close(terms.factor(0.), 1.));
+	assert!(close(terms.factor(2.), 1. / 3.));
+	assert!(close(terms.factor(4.), 1. / 7.));
+	assert!(close(Attenuation::new(2., 0., 0.).factor(5.), 0.5));
+	assert!(close(Attenuation::new(0., 1., 0.).factor(4.), 0.25));
+	assert_eq!(Attenuation::new(0., 0., 0.).factor(1.), 0.);
+
+	// Light fades out smoothly towards the range and is gone past it
+	let ranged = Attenuation { range: Some(4.), ..Attenuation::new(1., 0., 0.) };
+	assert!(close(ranged.factor(0.), 1.));
+	assert!(close(ranged.factor(2.), (1f64 - 1. / 16.).powi(2)));
+	assert!(ranged.factor(3.9) > 0. && ranged.factor(3.9) < 0.05);
+	assert_eq!(ranged.factor(4.), 0.);
+	assert_eq!(ranged.factor(6.), 0.);
+    }
+
+    #[test]
+    fn rect_light() {
+	let material = Material::new(Color::white(), 0.);
+	let (p, n) = (Vec3(0., 0., 0.), Vec3(0., 1., 0.));
+	let int = Intersection::new(p, n, Ray::new(p, n), &material, false);
+	let brdf = Brdf::new(&int, Vec3(0., -1., 0.));
+	let mut bodies = Bodies::new();
+	bodies.build();
+	// Light of a rectangle with edges `w` and `l` facing down onto a
+	// point `h` below its center
+	let calc = |w: f64, l: f64, h: f64, bodies: &Bodies| {
+	    let mut light = RectLight::new(Vec3(0., h, 0.), Vec3(w, 0., 0.), Vec3(0., 0., l), 1.);
+	    light.set_samples(1024);
+	    light.calc(&int, &brdf, bodies).unwrap().r
+	};
+
+	// Far away the light looks like a point of intensity given by its
+	// area, dimming with the squared distance
+	let far = calc(2., 2., 20., &bodies);
+	let expected = brdf.eval(n).r * 4. / 400.;
+	assert!((far - expected).abs() < 0.01 * expected, "{} {}", far, expected);
+	let farther = calc(2., 2., 40., &bodies);
+	assert!((far / farther - 4.).abs() < 0.04, "{} {}", far, farther);
+	let wider = calc(4., 2., 40., &bodies);
+	assert!((wider / farther - 2.).abs() < 0.02, "{} {}", wider, farther);
+
+	// Sampling finds the same light on average
+	let mut light = RectLight::new(Vec3(0., 1., 0.), Vec3(2., 0., 0.), Vec3(0., 0., 2.), 1.);
+	light.set_samples(1024);
+	let full = light.calc(&int, &brdf, &bodies).unwrap().r;
+	let mut rng = Rng::new(1);
+	let count = 20000;
+	let sampled = (0..count)
+	    .filter_map(|_| light.sample(p, &mut rng))
+	    .map(|sample| brdf.eval(sample.direction).r * sample.irradiance.r * sample.direction.dot(n))
+	    .sum::<f64>() / count as f64;
+	assert!((sampled - full).abs() < 0.02 * full, "{} {}", sampled, full);
+
+	// A wall halfway up hides the half of the square at negative x
+	bodies.push(Box::new(Polygon::new(Vec3(0., 0.5, -10.), Vec3(0., 0.5, 10.), Vec3(-10., 0.5, 0.), Material::new(Color::white(), 0.))));
+	bodies.build();
+	let penumbra = light.calc(&int, &brdf, &bodies).unwrap().r;
+	assert!((penumbra - 0.5 * full).abs() < 0.02 * full, "{} {}", penumbra, full);
     }
 }
@@ -1,61 +1,179 @@
 use super::super::raytracer::Pixel;
+use std::sync::Arc;
 
-#[derive(Clone, Copy)]
+use super::super::math::{Point3, Vec3};
+use super::super::medium::Medium;
+use super::super::texture::Texture;
+
+/// Linear RGB radiance, `1.` in a channel is the brightest value an 8-bit
+/// image can show but light is free to go above it.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Color {
-    r: u8,
-    g: u8,
-    b: u8
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
 }
 
 impl Default for Color {
     fn default() -> Self {
-	Color::new(0x00, 0x00, 0x00)
+	Color::rgb(0., 0., 0.)
     }
 }
 
 impl Color {
-    pub fn new(r: u8, g: u8, b: u8) -> Self { Self { r, g, b } }
-    pub fn white() -> Color { Color::new(0xff, 0xff, 0xff) }
+    /// Color from 8-bit channels.
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+	Self::rgb(r as f64 / 255., g as f64 / 255., b as f64 / 255.)
+    }
+    pub const fn rgb(r: f64, g: f64, b: f64) -> Self { Self { r, g, b } }
+    pub fn white() -> Color { Color::rgb(1., 1., 1.) }
+
+    /// Quantizes to 8 bits, clipping values out of `[0, 1]`.
     pub fn pixel(&self) -> Pixel {
-	Pixel(self.r, self.g, self.b)
+	let quantize = |c: f64| (c.clamp(0., 1.) * 255.).round() as u8;
+	Pixel(quantize(self.r), quantize(self.g), quantize(self.b))
     }
 
     pub fn add(&self, rhs: Color) -> Color {
-	Color::new(self.r.saturating_add(rhs.r),
-		   self.g.saturating_add(rhs.g),
-		   self.b.saturating_add(rhs.b))
+	Color::rgb(self.r + rhs.r, self.g + rhs.g, self.b + rhs.b)
     }
 
     pub fn sub(&self, rhs: Color) -> Color {
-	Color::new(self.r.saturating_sub(rhs.r),
-		   self.g.saturating_sub(rhs.g),
-		   self.b.saturating_sub(rhs.b))
+	Color::rgb(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b)
     }
 
-    pub fn mul(&self, fact: u8) -> Color {
-	Color::new(self.r.saturating_mul(fact),
-		   self.g.saturating_mul(fact),
-		   self.b.saturating_mul(fact))
+    /// Component-wise product, e.g. of light and surface colors.
+    pub fn mul(&self, rhs: Color) -> Color {
+	Color::rgb(self.r * rhs.r, self.g * rhs.g, self.b * rhs.b)
     }
 
     pub fn mul_float(&self, fact: f64) -> Color {
-	Color::new((self.r as f64 * fact) as u8,
-		   (self.g as f64 * fact) as u8,
-		   (self.b as f64 * fact) as u8)
+	Color::rgb(self.r * fact, self.g * fact, self.b * fact)
     }
 
-    pub fn div(&self, fact: u8) -> Color {
-	Color::new(self.r / fact,
-		   self.g / fact,
-		   self.b / fact)
+    pub fn div(&self, fact: f64) -> Color {
+	Color::rgb(self.r / fact, self.g / fact, self.b / fact)
     }
+
+    pub fn max(&self, rhs: Color) -> Color {
+	Color::rgb(self.r.max(rhs.r), self.g.max(rhs.g), self.b.max(rhs.b))
+    }
+
+    /// Relative luminance of linear sRGB.
+    pub fn luminance(&self) -> f64 {
+	0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+}
+
+/// Detail bending the shading normal of a surface without changing its
+/// shape.
+#[derive(Clone)]
+pub enum Relief {
+    /// Tangent space normal map with linear values, red goes along the
+    /// tangent, green along the bitangent and blue out of the surface.
+    Normal(Arc<dyn Texture + Send + Sync>),
+    /// Height map of the texture brightness, slopes scaled by the given
+    /// strength. Slopes are taken per unit of texture coordinates, not of
+    /// length on the surface, so bumps are as steep however far the
+    /// texture is stretched.
+    Bump(Arc<dyn Texture + Send + Sync>, f64),
 }
 
-#[derive(Clone, Copy)]
+impl Relief {
+    /// Shading normal at texture coordinates `uv` of `point`, where the
+    /// surface has the frame of normal `n`, tangent `t` and bitangent `b`.
+    pub fn normal(&self, uv: (f64, f64), point: Point3, n: Vec3, t: Vec3, b: Vec3) -> Vec3 {
+	match self {
+	    Relief::Normal(texture) => {
+		let c = texture.color(uv, point);
+		(t * (2. * c.r - 1.) + b * (2. * c.g - 1.) + n * (2. * c.b - 1.)).norm()
+	    },
+	    Relief::Bump(texture, strength) => {
+		// Image textures vary with the coordinates, solid ones with the
+		// point, stepping both covers either
+		const STEP: f64 = 1e-3;
+		let height = |du: f64, dv: f64| texture.color((uv.0 + du, uv.1 + dv), point + t * du + b * dv).luminance();
+		let h = height(0., 0.);
+		let (slope_u, slope_v) = ((height(STEP, 0.) - h) / STEP, (height(0., STEP) - h) / STEP);
+		(n - (t * slope_u + b * slope_v) * *strength).norm()
+	    },
+	}
+    }
+}
+
+/// Light entering the surface and scattering inside the object before it
+/// leaves again, as in skin, wax, marble or milk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Subsurface {
+    /// Average distance light travels inside between collisions, per
+    /// channel.
+    pub mean_free_path: Color,
+    /// Asymmetry of the phase function inside, as for media.
+    pub anisotropy: f64,
+}
+
+impl Subsurface {
+    pub fn new(mean_free_path: Color) -> Self {
+	Self { mean_free_path, anisotropy: 0. }
+    }
+
+    /// Extinction and scattering coefficients per channel under a surface
+    /// of `color`. The single scattering albedo is picked so that light
+    /// scattering many times leaves about `color` of itself, by the fit
+    /// of Chiang et al. 2016.
+    pub fn coefficients(&self, color: Color) -> (Color, Color) {
+	let channel = |path: f64, color: f64| {
+	    let a = color.clamp(0., 1.);
+	    let albedo = 1. - (4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt()).powi(2);
+	    let extinction = 1. / path.max(1e-6);
+	    (extinction, extinction * albedo.clamp(0., 1.))
+	};
+	let (r, g, b) = (channel(self.mean_free_path.r, color.r), channel(self.mean_free_path.g, color.g), channel(self.mean_free_path.b, color.b));
+	(Color::rgb(r.0, g.0, b.0), Color::rgb(r.1, g.1, b.1))
+    }
+}
+
+#[derive(Clone)]
 pub struct Material {
+    /// Color of the surface, multiplies the texture if there is one. The
+    /// diffuse color of dielectrics and the reflectance of metals.
     pub color: Color,
-    pub shine: Option<i32>,
+    pub texture: Option<Arc<dyn Texture + Send + Sync>>,
+    /// Spread of the highlights from `0.` for a polished surface to `1.`
+    /// for a matte one.
+    pub roughness: f64,
+    /// Varies the roughness, which is multiplied by the luminance of the
+    /// texture.
+    pub roughness_texture: Option<Arc<dyn Texture + Send + Sync>>,
+    /// `1.` for metals, which reflect in their own color and have no
+    /// diffuse part, `0.` for dielectrics.
+    pub metallic: f64,
+    /// Varies the metallic share like `roughness_texture`.
+    pub metallic_texture: Option<Arc<dyn Texture + Send + Sync>>,
+    /// Share of light mirrored on top of the surface reflection.
     pub reflection: f64,
+    /// Varies the reflection like `roughness_texture`.
+    pub reflection_texture: Option<Arc<dyn Texture + Send + Sync>>,
+    /// Blur of the mirror image from `0.` for a perfect mirror to `1.`,
+    /// like `roughness`.
+    pub reflection_roughness: f64,
+    /// Rays Whitted shading averages over the blurred mirror image.
+    pub reflection_samples: u32,
+    pub relief: Option<Relief>,
+    /// Share of light passing through the surface.
+    pub transparency: f64,
+    /// Index of refraction of the medium behind the surface.
+    pub ior: f64,
+    /// Radiance given off by the front of the surface, which makes the
+    /// object a light.
+    pub emission: Color,
+    /// Matter filling the object. Its surface is then only the boundary
+    /// of the medium, which rays pass unchanged.
+    pub medium: Option<Arc<Medium>>,
+    /// Scattering under the surface, which takes the place of the diffuse
+    /// part of the surface in path tracing. Whitted shading keeps it
+    /// diffuse.
+    pub subsurface: Option<Subsurface>,
 }
 
 impl Default for Material {
@@ -64,14 +182,86 @@ impl Default for Material {
     }
 }
 
+/// Rays averaged over blurred reflections by default.
+pub const REFLECTION_SAMPLES: u32 = 16;
+
+/// Roughness whose highlights match those of a Phong exponent.
+fn shine_roughness(shine: i32) -> f64 {
+    // The exponent of the half vector is about four times that of the
+    // mirror direction, GGX alpha is the square of the roughness
+    (2. / (4. * shine.max(0) as f64 + 2.)).powf(0.25)
+}
+
 impl Material {
-    pub fn new(color: Color, reflection: f64) -> Self { Self { color, shine: None, reflection } }
-    pub fn new_shine(color: Color, shine: i32, reflection: f64) -> Self {
+    /// Matte dielectric.
+    pub const fn new(color: Color, reflection: f64) -> Self {
 	Self {
 	    color,
-	    shine: Some(shine),
-	    reflection
+	    texture: None,
+	    roughness: 1.,
+	    roughness_texture: None,
+	    metallic: 0.,
+	    metallic_texture: None,
+	    reflection,
+	    reflection_texture: None,
+	    reflection_roughness: 0.,
+	    reflection_samples: REFLECTION_SAMPLES,
+	    relief: None,
+	    transparency: 0.,
+	    ior: 1.,
+	    emission: Color::rgb(0., 0., 0.),
+	    medium: None,
+	    subsurface: None,
+	}
+    }
+    /// Dielectric with highlights as sharp as those of the Phong exponent
+    /// `shine`.
+    pub fn new_shine(color: Color, shine: i32, reflection: f64) -> Self {
+	Self { roughness: shine_roughness(shine), ..Self::new(color, reflection) }
+    }
+    pub fn new_metal(color: Color, roughness: f64) -> Self {
+	Self { roughness, metallic: 1., ..Self::new(color, 0.) }
+    }
+    /// Black surface glowing with the radiance `emission`.
+    pub fn new_emissive(emission: Color) -> Self {
+	Self { emission, ..Self::new(Color::default(), 0.) }
+    }
+    pub fn new_transparent(color: Color, transparency: f64, ior: f64) -> Self {
+	Self { transparency, ior, ..Self::new(color, 0.) }
+    }
+    /// Translucent dielectric light travels `mean_free_path` into between
+    /// collisions, per channel.
+    pub fn new_subsurface(color: Color, mean_free_path: Color, roughness: f64) -> Self {
+	Self { roughness, subsurface: Some(Subsurface::new(mean_free_path)), ..Self::new(color, 0.) }
+    }
+
+    /// Color of the surface at texture coordinates `uv` of `point`.
+    pub fn color_at(&self, uv: (f64, f64), point: Point3) -> Color {
+	match &self.texture {
+	    Some(texture) => self.color.mul(texture.color(uv, point)),
+	    None => self.color,
 	}
     }
 
+    /// Share of light mirrored at texture coordinates `uv` of `point`.
+    pub fn reflection_at(&self, uv: (f64, f64), point: Point3) -> f64 {
+	scaled(self.reflection, &self.reflection_texture, uv, point)
+    }
+
+    pub fn roughness_at(&self, uv: (f64, f64), point: Point3) -> f64 {
+	scaled(self.roughness, &self.roughness_texture, uv, point)
+    }
+
+    pub fn metallic_at(&self, uv: (f64, f64), point: Point3) -> f64 {
+	scaled(self.metallic, &self.metallic_texture, uv, point)
+    }
+}
+
+/// `value` times the luminance of `texture` at texture coordinates `uv`
+/// of `point`.
+fn scaled(value: f64, texture: &Option<Arc<dyn Texture + Send + Sync>>, uv: (f64, f64), point: Point3) -> f64 {
+    match texture {
+	Some(texture) => value * texture.color(uv, point).luminance().clamp(0., 1.),
+	None => value,
+    }
 }
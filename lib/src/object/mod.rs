@@ -1,18 +1,36 @@
+pub mod bodies;
+pub mod brdf;
 pub mod figures;
 pub mod light;
 pub mod material;
 
+pub use bodies::Bodies;
+pub use brdf::Brdf;
+
 pub use figures::Object;
 pub use figures::Sphere;
 pub use figures::Plane;
 pub use figures::Polygon;
+pub use figures::Mesh;
+pub use figures::Triangle;
 pub use figures::Intersection;
 
 pub use light::Light;
 pub use light::AmbientLight;
 pub use light::PointLight;
+pub use light::Attenuation;
 pub use light::DirectLight;
-pub use light::LightColor;
+pub use light::SpotLight;
+pub use light::RectLight;
+pub use light::DiskLight;
+pub use light::SphereLight;
+pub use light::EnvironmentLight;
+pub use light::SunLight;
+pub use light::MeshLight;
+pub use light::Patch;
+pub use light::LightSample;
 
 pub use material::Color;
 pub use material::Material;
+pub use material::Relief;
+pub use material::Subsurface;
@@ -1,11 +1,95 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use super::object::*;
 use super::math::*;
+use super::medium::Medium;
+use super::sampling::{self, Rng, Sampler, Sampling};
+use super::tonemap::ToneMap;
 use rayon::prelude::*;
 
+/// Deepest recursion level of reflected and refracted rays.
+const MAX_DEPTH: i32 = 2;
+
+/// Bounces every path survives before Russian roulette may end it.
+const ROULETTE_DEPTH: u32 = 3;
+
+/// Steps Whitted shading takes through a medium to gather the light it
+/// scatters.
+const MARCH_STEPS: u32 = 64;
+
+/// Optical depth past which media are treated as opaque when marching,
+/// less than a thousandth of the light gets through.
+const MARCH_DEPTH: f64 = 6.9;
+
+/// Collisions a random walk under a surface takes before it is given up.
+const MAX_WALK: u32 = 1024;
+
+/// Index of refraction of the specular layer on top of subsurface
+/// materials, matching the reflectance of dielectrics in the BRDF.
+const SUBSURFACE_IOR: f64 = 1.5;
+
+/// Where a path tracer ray next meets matter.
+enum Event<'a> {
+    /// Collision with the medium at a point.
+    Medium(Point3, &'a Medium),
+    Surface(usize, Intersection<'a>),
+    Escape,
+}
+
+/// Algorithm computing the light carried by camera rays.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Integrator {
+    /// Direct lighting with mirror reflection and refraction.
+    Whitted,
+    /// Monte Carlo path tracing with light sampling at every diffuse
+    /// bounce, paths end after `max_depth` bounces.
+    Path { max_depth: u32 },
+}
+
+impl Integrator {
+    pub const DEFAULT_MAX_DEPTH: u32 = 8;
+}
+
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	match s {
+	    "whitted" => Ok(Integrator::Whitted),
+	    "path" => Ok(Integrator::Path { max_depth: Integrator::DEFAULT_MAX_DEPTH }),
+	    _ => Err(format!("unknown integrator `{}`, expected one of `whitted`, `path`", s)),
+	}
+    }
+}
+
+impl fmt::Display for Integrator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	f.write_str(match self {
+	    Integrator::Whitted => "whitted",
+	    Integrator::Path { .. } => "path",
+	})
+    }
+}
+
+/// Scene state a rendered image depends on.
+#[derive(Clone, Copy, PartialEq)]
+struct RenderState {
+    scene: u64,
+    camera: u64,
+    integrator: Integrator,
+    sampling: Sampling,
+}
+
 pub struct Raytracer {
     canvas: Option<Canvas>,
+    /// Passes averaged in the canvas.
+    passes: u32,
+    /// State the passes were traced in.
+    state: Option<RenderState>,
     pub scene: Scene
 }
 
@@ -13,39 +97,306 @@ impl Raytracer {
     pub fn new(scene: Scene) -> Raytracer {
 	Raytracer {
 	    canvas: Some(Canvas::new(scene.width, scene.height)),
+	    passes: 0,
+	    state: None,
 	    scene
 	}
     }
 
+    /// Renders the scene from scratch.
     pub fn render(&mut self) -> &Canvas {
+	self.state = None;
+	self.render_progressive()
+    }
+
+    /// Traces one more pass and averages it with the earlier ones, which
+    /// are dropped when the scene or the camera changed since.
+    pub fn render_progressive(&mut self) -> &Canvas {
+	self.scene.build();
+	let state = Some(self.render_state());
+	if self.state != state {
+	    self.state = state;
+	    self.passes = 0;
+	}
+
 	let mut canvas = self.canvas.take().unwrap();
 	let rt = Arc::new(&self);
-	canvas.update(|r| rt.trace(r, 0).pixel(), &rt.scene.camera);
+	let pass = rt.passes;
+	match rt.scene.integrator {
+	    Integrator::Whitted => canvas.update(|r, rng| rt.trace(r, 0, rt.scene.fog.as_ref(), rng), &rt.scene.camera, &rt.scene.sampling, pass),
+	    Integrator::Path { max_depth } => canvas.update(|r, rng| rt.trace_path(r, max_depth, rng), &rt.scene.camera, &rt.scene.sampling, pass),
+	}
+	canvas.tone_map = rt.scene.tone_map;
+	self.passes += 1;
 	self.canvas.replace(canvas);
-	return self.canvas.as_ref().unwrap();
+	self.canvas.as_ref().unwrap()
+    }
+
+    /// Passes averaged in the current image.
+    pub fn passes(&self) -> u32 {
+	self.passes
+    }
+
+    /// Whether more passes can still improve the image, which is not the
+    /// case when every pass traces the very same rays.
+    pub fn converged(&self) -> bool {
+	let deterministic = self.scene.integrator == Integrator::Whitted && self.scene.sampling.sampler == Sampler::Grid && self.scene.camera.aperture() <= 0.;
+	self.passes > 0 && deterministic && self.state == Some(self.render_state())
+    }
+
+    fn render_state(&self) -> RenderState {
+	RenderState {
+	    scene: self.scene.generation,
+	    camera: self.scene.camera.generation(),
+	    integrator: self.scene.integrator,
+	    sampling: self.scene.sampling,
+	}
     }
 
-    fn trace(&self, ray: Ray, depth: i32) -> Color {
-	if let Some(int) = self.scene.nearest_intersection(&ray) {
-	    let color = self.scene.calc_light(&ray, &int).calc_color(int.material);
-	    if int.material.reflection < f64::EPSILON || depth > 2 {
-		color
+    /// Light reaching the start of `ray`, which travels through `medium`.
+    /// `rng` only rotates the rays splitting rough reflections.
+    fn trace(&self, ray: Ray, depth: i32, medium: Option<&Medium>, rng: &mut Rng) -> Color {
+	let int = self.scene.nearest_intersection(&ray);
+	let dist = int.as_ref().map_or_else(|| self.scene.fog_reach(&ray), |int| ray.distance(int.point));
+	let behind = match int {
+	    Some(int) => self.shade(&ray, int, depth, medium, rng),
+	    None => self.scene.background(ray.direction, None),
+	};
+	match medium {
+	    Some(medium) => self.scene.march(&ray, dist, medium, behind),
+	    None => behind,
+	}
+    }
+
+    /// Light leaving the surface at `int` towards the start of `ray`.
+    fn shade(&self, ray: &Ray, int: Intersection, depth: i32, medium: Option<&Medium>, rng: &mut Rng) -> Color {
+	if let Some(inner) = &int.material.medium {
+	    // Boundaries of media only switch the medium, nested media
+	    // are not supported
+	    let next = if int.inside { self.scene.fog.as_ref() } else { Some(&**inner) };
+	    return self.trace(Ray::new(int.point - int.geometric_n * (200. * f64::EPSILON), ray.direction), depth, next, rng);
+	}
+	let material = int.material;
+	let color = self.scene.calc_light(&int, &Brdf::new(&int, ray.direction), medium).add(int.emission());
+	let reflection = int.reflection();
+	let reflective = reflection >= f64::EPSILON;
+	let transparent = material.transparency >= f64::EPSILON;
+	if !(reflective || transparent) || depth > MAX_DEPTH {
+	    return color;
+	}
+
+	let reflected_color = self.trace_reflection(ray, &int, depth, medium, rng);
+	let surface_color = color.mul_float(1. - reflection).add(reflected_color.mul_float(reflection));
+	if !transparent {
+	    return surface_color;
+	}
+
+	// Light passing through is split between reflection and
+	// refraction by the Fresnel term
+	let (n1, n2) = if int.inside { (material.ior, 1.) } else { (1., material.ior) };
+	let fresnel = schlick(ray.direction, int.n, n1, n2);
+	let refracted_color = match refract(ray.direction, int.n, n1 / n2) {
+	    Some(dir) => {
+		// Start on the other side of the surface
+		let p = int.point - int.geometric_n * (200. * f64::EPSILON);
+		self.trace(Ray::new(p, dir), depth + 1, medium, rng)
+	    },
+	    None => Color::default(),
+	};
+	let passed_color = reflected_color.mul_float(fresnel).add(refracted_color.mul_float(1. - fresnel));
+	surface_color.mul_float(1. - material.transparency).add(passed_color.mul_float(material.transparency))
+    }
+
+    /// Light mirrored at `int`, averaged over several rays for rough
+    /// reflections. Only the first reflection of a camera ray is split,
+    /// deeper ones follow the perfect mirror. The rays follow a Halton
+    /// sequence shifted at random, so that neighbouring pixels and passes
+    /// do not repeat the same pattern.
+    fn trace_reflection(&self, ray: &Ray, int: &Intersection, depth: i32, medium: Option<&Medium>, rng: &mut Rng) -> Color {
+	let material = int.material;
+	if material.reflection_roughness <= 0. || depth > 0 {
+	    return self.trace(Ray::new(int.reflect.point, int.reflect.direction), depth + 1, medium, rng);
+	}
+	let shift = (rng.next_f64(), rng.next_f64());
+	let (total, count) = (0..material.reflection_samples.max(1))
+	    .filter_map(|i| {
+		let (u, v) = sampling::halton(i);
+		int.glossy_reflect(ray.direction, (u + shift.0).fract(), (v + shift.1).fract())
+	    })
+	    .fold((Color::default(), 0), |(total, count), reflected| (total.add(self.trace(reflected, depth + 1, medium, rng)), count + 1));
+	if count == 0 {
+	    return self.trace(Ray::new(int.reflect.point, int.reflect.direction), depth + 1, medium, rng);
+	}
+	total.div(count as f64)
+    }
+
+    fn trace_path(&self, mut ray: Ray, max_depth: u32, rng: &mut Rng) -> Color {
+	let mut radiance = Color::default();
+	let mut throughput = Color::white();
+	// Density the last diffuse bounce picked the ray with
+	let mut bounce_pdf = None;
+	let mut medium = self.scene.fog.as_ref();
+	for depth in 0..=max_depth {
+	    if depth > ROULETTE_DEPTH {
+		let survival = throughput.r.max(throughput.g).max(throughput.b).min(0.95);
+		if rng.next_f64() >= survival {
+		    break;
+		}
+		throughput = throughput.div(survival);
+	    }
+	    let origin = ray.point;
+	    let (object, int) = match self.scene.next_event(&mut ray, &mut medium, rng) {
+		Event::Surface(object, int) => (object, int),
+		Event::Medium(point, scattering) => {
+		    // Absorption ends the path, scattering picks a new
+		    // direction by the phase function
+		    if rng.next_f64() >= scattering.albedo() {
+			break;
+		    }
+		    throughput = throughput.mul(scattering.color);
+		    let direction = ray.direction;
+		    let phase = |wi: Vec3| {
+			let value = scattering.phase(wi.dot(direction));
+			(Color::white().mul_float(value), value)
+		    };
+		    radiance = radiance.add(throughput.mul(self.scene.sample_lights(point, medium, phase, rng)));
+		    if depth == max_depth {
+			break;
+		    }
+		    let (dir, pdf) = scattering.sample_phase(direction, rng);
+		    bounce_pdf = Some(pdf);
+		    ray = Ray::new(point, dir);
+		    continue;
+		},
+		Event::Escape => {
+		    radiance = radiance.add(throughput.mul(self.scene.background(ray.direction, bounce_pdf)));
+		    break;
+		},
+	    };
+	    let emission = int.emission();
+	    if emission != Color::default() {
+		let weight = self.scene.emission_weight(object, origin, &int, bounce_pdf);
+		radiance = radiance.add(throughput.mul(emission).mul_float(weight));
+	    }
+	    bounce_pdf = None;
+	    let material = int.material;
+
+	    // Mirror and refraction lobes are picked with the probability
+	    // of their share so the throughput stays the same
+	    if rng.next_f64() < material.transparency {
+		let (n1, n2) = if int.inside { (material.ior, 1.) } else { (1., material.ior) };
+		let fresnel = schlick(ray.direction, int.n, n1, n2);
+		ray = match refract(ray.direction, int.n, n1 / n2) {
+		    Some(dir) if rng.next_f64() >= fresnel => Ray::new(int.point - int.geometric_n * (200. * f64::EPSILON), dir),
+		    _ => int.reflect,
+		};
+	    } else if rng.next_f64() < int.reflection() {
+		ray = match int.glossy_reflect(ray.direction, rng.next_f64(), rng.next_f64()) {
+		    Some(reflected) => reflected,
+		    None => break,
+		};
+	    } else if let Some(subsurface) = &material.subsurface {
+		// A specular layer on top reflects part of the light, the
+		// rest enters and leaves again somewhere nearby
+		let coat = Brdf::from_parts(int.n, -1. * ray.direction, Color::default(), int.roughness(), 0.);
+		let fresnel = schlick(ray.direction, int.n, 1., SUBSURFACE_IOR);
+		let reflect = |wi: Vec3| {
+		    let cos = wi.dot(int.n);
+		    if cos <= 0. {
+			return (Color::default(), 0.);
+		    }
+		    (coat.eval(wi).mul_float(cos), fresnel * coat.pdf(wi))
+		};
+		radiance = radiance.add(throughput.mul(self.scene.sample_lights(int.point, medium, reflect, rng)));
+		if depth == max_depth {
+		    break;
+		}
+		if rng.next_f64() < fresnel {
+		    match coat.sample(rng) {
+			Some((dir, weight, pdf)) => {
+			    throughput = throughput.mul(weight).div(fresnel);
+			    bounce_pdf = Some(fresnel * pdf);
+			    ray = Ray::new(int.point, dir);
+			},
+			None => break,
+		    }
+		    continue;
+		}
+		let (exit, weight) = match self.scene.subsurface_walk(&int, subsurface, rng) {
+		    Some(walk) => walk,
+		    None => break,
+		};
+		// Light leaves evenly in all directions
+		throughput = throughput.mul(weight);
+		let n = -1. * exit.n;
+		let point = exit.point - exit.geometric_n * (200. * f64::EPSILON);
+		let diffuse = |wi: Vec3| {
+		    let cos = wi.dot(n);
+		    if cos <= 0. {
+			return (Color::default(), 0.);
+		    }
+		    (Color::white().mul_float(cos / PI), cos / PI)
+		};
+		radiance = radiance.add(throughput.mul(self.scene.sample_lights(point, medium, diffuse, rng)));
+		let dir = sampling::cosine_hemisphere(n, rng);
+		bounce_pdf = Some(dir.dot(n) / PI);
+		ray = Ray::new(point, dir);
 	    } else {
-		let reflected_color = self.trace(int.reflect, depth + 1);
-		color.mul_float(1. - int.material.reflection).add(reflected_color.mul_float(int.material.reflection))
+		let brdf = Brdf::new(&int, ray.direction);
+		let reflect = |wi: Vec3| {
+		    let cos = wi.dot(int.n);
+		    if cos <= 0. {
+			return (Color::default(), 0.);
+		    }
+		    (brdf.eval(wi).mul_float(cos), brdf.pdf(wi))
+		};
+		radiance = radiance.add(throughput.mul(self.scene.sample_lights(int.point, medium, reflect, rng)));
+		if depth == max_depth {
+		    break;
+		}
+		match brdf.sample(rng) {
+		    Some((dir, weight, pdf)) => {
+			throughput = throughput.mul(weight);
+			bounce_pdf = Some(pdf);
+			ray = Ray::new(int.point, dir);
+		    },
+		    None => break,
+		}
 	    }
-	} else {
-	    Color::default()
 	}
+	radiance
+    }
+}
+
+/// Weight of a sample taken with density `pdf` when `other` could have
+/// picked it as well, by the power heuristic.
+fn mis_weight(pdf: f64, other: f64) -> f64 {
+    if other <= 0. {
+	return 1.;
     }
+    pdf * pdf / (pdf * pdf + other * other)
 }
 
 pub struct Scene {
     width: u32,
     height: u32,
-    bodies: Vec<Box<dyn Object + Sync + Send>>,
+    bodies: Bodies,
     lights: Vec<Box<dyn Light + Sync + Send>>,
+    /// Lights of glowing objects by object index, also found in `lights`.
+    emitters: HashMap<usize, Arc<MeshLight>>,
+    /// Whether any object bounds a medium.
+    media: bool,
+    /// Bumped whenever objects or lights are added.
+    generation: u64,
     pub camera: Camera,
+    pub integrator: Integrator,
+    pub sampling: Sampling,
+    /// Applied to the rendered canvas when turning it into pixels.
+    pub tone_map: ToneMap,
+    /// Medium filling all space outside of objects bounding their own.
+    /// Rays leaving the scene only pass through it up to the box around
+    /// the bounded objects, so lights at infinite distance shine in.
+    pub fog: Option<Medium>,
 }
 
 impl Scene {
@@ -53,71 +404,315 @@ impl Scene {
 	Scene {
 	    width,
 	    height,
-	    bodies: vec![],
+	    bodies: Bodies::new(),
 	    lights: vec![],
+	    emitters: HashMap::new(),
+	    media: false,
+	    generation: 0,
 	    camera: Camera::new(width, height),
+	    integrator: Integrator::Whitted,
+	    sampling: Sampling::default(),
+	    tone_map: ToneMap::default(),
+	    fog: None,
 	}
     }
 
     pub fn add<T: Object + Send + Sync + 'static>(&mut self, obj: T) {
-	self.bodies.push(Box::new(obj));
+	self.add_boxed(Box::new(obj));
+    }
+    /// Objects with glowing materials also become lights.
+    pub fn add_boxed(&mut self, obj: Box<dyn Object + Send + Sync>) {
+	if let Some(emitter) = obj.emitter() {
+	    let emitter = Arc::new(emitter);
+	    self.emitters.insert(self.bodies.len(), emitter.clone());
+	    self.lights.push(Box::new(emitter));
+	}
+	self.media |= obj.has_medium();
+	self.bodies.push(obj);
+	self.generation += 1;
     }
     pub fn add_light<T: Light + Send + Sync + 'static>(&mut self, light: T) {
 	self.lights.push(Box::new(light));
+	self.generation += 1;
     }
 
-    fn nearest_intersection(&self, ray: &Ray) -> Option<Intersection> {
-	let mut res = None;
-	let mut res_dist = f64::INFINITY;
+    /// Builds acceleration structures over the objects added so far.
+    /// Done automatically before rendering.
+    pub fn build(&mut self) {
+	self.bodies.build();
+    }
 
-	for obj in self.bodies.iter() {
-	    if let Some(int) = obj.intersect(ray) {
-		let dist = ray.distance(int.point);
-		if dist < res_dist {
-		    res_dist = dist;
-		    res = Some(int);
-		}
+    fn nearest_intersection(&self, ray: &Ray) -> Option<Intersection<'_>> {
+	self.bodies.nearest(ray).map(Intersection::with_relief)
+    }
+
+    fn nearest_object(&self, ray: &Ray) -> Option<(usize, Intersection<'_>)> {
+	self.bodies.nearest_object(ray).map(|(i, int)| (i, int.with_relief()))
+    }
+
+    /// Light from all lights reflected towards the viewer, dimmed by
+    /// `medium` and the media on the way to the lights.
+    fn calc_light(&self, intersection: &Intersection, brdf: &Brdf, medium: Option<&Medium>) -> Color {
+	let mut res = Color::default();
+	// Media dim the light as seen from one direction towards the light,
+	// sampled the same way in every pass
+	let mut rng = Rng::new(0);
+
+	for light in self.lights.iter() {
+	    if let Some(color) = light.calc(intersection, brdf, &self.bodies) {
+		let passed = match light.sample(intersection.point, &mut rng) {
+		    Some(sample) if self.fog.is_some() || self.media => {
+			let ray = Ray::new(intersection.point, sample.direction);
+			self.pass_media(&ray, sample.distance, medium, false, &mut rng)
+		    },
+		    _ => 1.,
+		};
+		res = res.add(color.mul_float(passed));
 	    }
 	}
 
-	return res;
+	res
     }
 
-    fn calc_light(&self, ray: &Ray, intersection: &Intersection) -> LightColor {
-	let mut res = LightColor::new(None, 0.);
-
+    /// Light from all lights scattered at `point` towards the viewer,
+    /// `medium` fills the space around it. `scatter` gives the share of
+    /// the light arriving from a direction that is scattered, with the
+    /// density of a bounce picking it. Directions a bounce might pick as
+    /// well are shared with `background`.
+    fn sample_lights<F>(&self, point: Point3, medium: Option<&Medium>, scatter: F, rng: &mut Rng) -> Color
+    where F: Fn(Vec3) -> (Color, f64) {
+	let mut res = Color::default();
 	for light in self.lights.iter() {
-	    if let Some(color) = light.calc(ray, intersection, &self.bodies) {
-		res = res.add(color);
+	    let sample = match light.sample(point, rng) {
+		Some(sample) => sample,
+		None => continue,
+	    };
+	    let (value, pdf) = scatter(sample.direction);
+	    if value == Color::default() {
+		continue;
+	    }
+	    let transmittance = self.transmittance(&Ray::new(point, sample.direction), sample.distance, medium, rng);
+	    if transmittance <= 0. {
+		continue;
+	    }
+	    let weight = match sample.pdf {
+		Some(light_pdf) => mis_weight(light_pdf, pdf),
+		None => 1.,
+	    };
+	    res = res.add(value.mul(sample.irradiance).mul_float(transmittance * weight));
+	}
+	res
+    }
+
+    /// Share of the light getting `dist` along the ray, which starts in
+    /// `medium`, zero if a surface blocks it. Boundaries of media on the
+    /// way switch the medium.
+    fn transmittance<'a>(&'a self, ray: &Ray, dist: f64, medium: Option<&'a Medium>, rng: &mut Rng) -> f64 {
+	if self.fog.is_none() && !self.media {
+	    return if self.bodies.occluded(ray, dist) { 0. } else { 1. };
+	}
+	self.pass_media(ray, dist, medium, true, rng)
+    }
+
+    /// Share of the light getting `dist` along the ray through the media
+    /// on the way. Other surfaces block it if `opaque`, otherwise it passes
+    /// through them.
+    fn pass_media<'a>(&'a self, ray: &Ray, dist: f64, medium: Option<&'a Medium>, opaque: bool, rng: &mut Rng) -> f64 {
+	let (mut ray, mut dist, mut medium, mut res) = (Ray::new(ray.point, ray.direction), dist, medium, 1.);
+	loop {
+	    let hit = self.bodies.nearest(&ray).map(|int| (ray.distance(int.point), int));
+	    let reach = hit.as_ref().map_or_else(|| dist.min(self.fog_reach(&ray)), |(t, _)| t.min(dist));
+	    if let Some(medium) = medium {
+		res *= medium.transmittance(&ray, reach, rng);
+	    }
+	    match hit {
+		Some((t, int)) if t < dist && res > 0. => {
+		    match &int.material.medium {
+			Some(inner) => medium = if int.inside { self.fog.as_ref() } else { Some(&**inner) },
+			None if opaque => return 0.,
+			None => (),
+		    }
+		    ray = Ray::new(int.point - int.geometric_n * (200. * f64::EPSILON), ray.direction);
+		    dist -= t;
+		},
+		_ => return res,
+	    }
+	}
+    }
+
+    /// Distance along a ray leaving the scene to where it leaves the box
+    /// around the bounded objects, past which there is no fog. Light of
+    /// lights at infinite distance and the background enters the fog
+    /// there. Without bounded objects fog fills all space.
+    fn fog_reach(&self, ray: &Ray) -> f64 {
+	let bounds = match self.bodies.bounds() {
+	    Some(bounds) => bounds,
+	    None => return f64::INFINITY,
+	};
+	let inv_dir = Vec3(1. / ray.direction.0, 1. / ray.direction.1, 1. / ray.direction.2);
+	bounds.span(ray, inv_dir, f64::INFINITY).map_or(0., |(_, end)| end)
+    }
+
+    /// Follows light entering the object at `int` on a random walk under
+    /// its surface until it gets out. Returns where it leaves with the
+    /// share of light left per channel, `None` if the walk was cut short.
+    fn subsurface_walk<'a>(&'a self, int: &Intersection, subsurface: &Subsurface, rng: &mut Rng) -> Option<(Intersection<'a>, Color)> {
+	let (extinction, scattering) = subsurface.coefficients(int.color());
+	let transmittance = |t: f64| Color::rgb((-extinction.r * t).exp(), (-extinction.g * t).exp(), (-extinction.b * t).exp());
+	let mut ray = Ray::new(int.point - int.geometric_n * (200. * f64::EPSILON), sampling::cosine_hemisphere(-1. * int.n, rng));
+	let mut weight = Color::white();
+	for step in 0..MAX_WALK {
+	    let hit = self.bodies.nearest(&ray)?;
+	    let dist = ray.distance(hit.point);
+	    // Distances are picked by a channel chosen by its share of the
+	    // light left, weights divide by the density over all three
+	    let total = weight.r + weight.g + weight.b;
+	    if total <= 0. {
+		return None;
+	    }
+	    let chance = weight.div(total);
+	    let mean = |c: Color| chance.r * c.r + chance.g * c.g + chance.b * c.b;
+	    let u = rng.next_f64();
+	    let sigma = if u < chance.r { extinction.r } else if u < chance.r + chance.g { extinction.g } else { extinction.b };
+	    let t = -(1. - rng.next_f64()).ln() / sigma;
+	    if t >= dist {
+		let passed = transmittance(dist);
+		return Some((hit, weight.mul(passed).div(mean(passed))));
+	    }
+	    let passed = transmittance(t);
+	    weight = weight.mul(scattering.mul(passed)).div(mean(extinction.mul(passed)));
+	    // Only dim walks may end early, bright ones would otherwise grow
+	    // into a few heavy ones the collision limit cuts off
+	    let survival = weight.r.max(weight.g).max(weight.b);
+	    if step >= ROULETTE_DEPTH && survival < 1. {
+		if rng.next_f64() >= survival {
+		    return None;
+		}
+		weight = weight.div(survival);
+	    }
+	    let direction = sampling::henyey_greenstein(ray.direction, subsurface.anisotropy, rng.next_f64(), rng.next_f64());
+	    ray = Ray::new(ray.point + ray.direction * t, direction);
+	}
+	None
+    }
+
+    /// Next place the ray meets matter, either colliding with `medium` or
+    /// hitting a surface. Boundaries of media on the way are crossed,
+    /// which moves the start of the ray and switches the medium.
+    fn next_event<'a>(&'a self, ray: &mut Ray, medium: &mut Option<&'a Medium>, rng: &mut Rng) -> Event<'a> {
+	loop {
+	    let hit = self.nearest_object(ray);
+	    let dist = hit.as_ref().map_or_else(|| self.fog_reach(ray), |(_, int)| ray.distance(int.point));
+	    if let Some(current) = *medium {
+		if let Some(t) = current.sample_distance(ray, dist, rng) {
+		    return Event::Medium(ray.point + ray.direction * t, current);
+		}
+	    }
+	    match hit {
+		Some((_, int)) if int.material.medium.is_some() => {
+		    *medium = if int.inside { self.fog.as_ref() } else { int.material.medium.as_deref() };
+		    *ray = Ray::new(int.point - int.geometric_n * (200. * f64::EPSILON), ray.direction);
+		},
+		Some((object, int)) => return Event::Surface(object, int),
+		None => return Event::Escape,
+	    }
+	}
+    }
+
+    /// Light reaching the start of the ray through `medium` from `behind`,
+    /// `dist` along it, with the light of the lights scattered towards it
+    /// on the way. The medium is marched in even steps and only scatters
+    /// once.
+    fn march(&self, ray: &Ray, dist: f64, medium: &Medium, behind: Color) -> Color {
+	let (start, end, majorant) = match medium.extent(ray, dist) {
+	    Some(extent) if extent.2 > 0. => extent,
+	    _ => return behind,
+	};
+	let end = end.min(start + MARCH_DEPTH / majorant);
+	let step = (end - start) / MARCH_STEPS as f64;
+	// Lights sampled at random pick the same directions in every pass
+	let mut rng = Rng::new(0);
+	let (mut res, mut transmittance) = (Color::default(), 1.);
+	for i in 0..MARCH_STEPS {
+	    let point = ray.point + ray.direction * (start + (i as f64 + 0.5) * step);
+	    let density = medium.density(point);
+	    if density <= 0. {
+		continue;
+	    }
+	    let mut scattered = Color::default();
+	    for light in self.lights.iter() {
+		if let Some(sample) = light.sample(point, &mut rng) {
+		    let reach = self.transmittance(&Ray::new(point, sample.direction), sample.distance, Some(medium), &mut rng);
+		    let phase = medium.phase(sample.direction.dot(ray.direction));
+		    scattered = scattered.add(sample.irradiance.mul_float(phase * reach));
+		}
 	    }
+	    // Light scattered within the step is dimmed by the part of the
+	    // step in front of it
+	    let extinction = medium.extinction() * density;
+	    let passed = (-extinction * step).exp();
+	    let share = medium.scattering * density * (1. - passed) / extinction;
+	    res = res.add(scattered.mul(medium.color).mul_float(transmittance * share));
+	    transmittance *= passed;
 	}
-	
-	return res;
+	res.add(behind.mul_float(transmittance))
+    }
+
+    /// Weight of the glow of `object` at `int` seen from `origin`.
+    /// `bounce_pdf` is the density a diffuse bounce picked the ray with,
+    /// light sampling covers the rest.
+    fn emission_weight(&self, object: usize, origin: Point3, int: &Intersection, bounce_pdf: Option<f64>) -> f64 {
+	match (bounce_pdf, self.emitters.get(&object)) {
+	    (Some(pdf), Some(light)) => mis_weight(pdf, light.pdf_at(origin, int)),
+	    _ => 1.,
+	}
+    }
+
+    /// Light of rays leaving the scene. `bounce_pdf` is the density a
+    /// diffuse bounce picked the ray with, light sampling covers the rest.
+    fn background(&self, direction: Vec3, bounce_pdf: Option<f64>) -> Color {
+	self.lights.iter().fold(Color::default(), |res, light| {
+	    let weight = match bounce_pdf {
+		Some(pdf) => mis_weight(pdf, light.pdf(direction)),
+		None => 1.,
+	    };
+	    res.add(light.background(direction).mul_float(weight))
+	})
     }
 }
 
+/// Traced radiance of every pixel, row by row.
 pub struct Canvas {
-    matrix: Vec<Vec<Pixel>>,
+    matrix: Vec<Vec<Color>>,
+    pub tone_map: ToneMap,
 }
 
 impl Canvas {
     pub fn new(width: u32, height: u32) -> Canvas {
 	Canvas {
-	    matrix: vec![vec![Pixel::default(); width as usize]; height as usize],
+	    matrix: vec![vec![Color::default(); width as usize]; height as usize],
+	    tone_map: ToneMap::default(),
 	}
     }
 
-    pub fn iter(&self) -> std::iter::Flatten<std::slice::Iter<Vec<Pixel>>> {
+    pub fn iter(&self) -> std::iter::Flatten<std::slice::Iter<'_, Vec<Color>>> {
 	self.matrix.iter().flatten()
     }
 
+    /// Pixels tone mapped and quantized to 8 bits.
+    pub fn pixels(&self) -> impl Iterator<Item = Pixel> + '_ {
+	self.iter().map(move |c| self.tone_map.pixel(*c))
+    }
+
     pub fn size(&self) -> (u32, u32) {
 	assert_ne!(self.matrix.len(), 0, "Canvas cannot be empty");
 	(self.matrix[0].len() as u32, self.matrix.len() as u32)
     }
 
-    fn update<T>(&mut self, f: T, camera: &Camera)
-    where T: Fn(Ray) -> Pixel + Send + Sync {
+    /// Traces pass number `pass` of every pixel and averages it with the
+    /// earlier ones.
+    fn update<T>(&mut self, f: T, camera: &Camera, sampling: &Sampling, pass: u32)
+    where T: Fn(Ray, &mut Rng) -> Color + Send + Sync {
 	// self.matrix
 	//     .iter_mut()
 	//     .zip(0..)
@@ -136,8 +731,25 @@ impl Canvas {
 	    .flat_map(|(row, y)| row.par_iter_mut()
 		      .zip((0..width).into_par_iter())
 			   .map(move |(pixel, x)| ((x as u32, y as u32), pixel)))
-	    .for_each(|(coords, pixel)| {
-		*pixel = f(camera.get_ray(coords));
+	    .for_each(|((x, y), pixel)| {
+		let mut rng = Rng::for_pixel(x, y, pass);
+		let (sum, weight) = sampling.pixel_samples(&mut rng).into_iter()
+		    .fold((Color::default(), 0.), |(sum, weight), ((dx, dy), w)| {
+			let coords = (x as f64 + dx, y as f64 + dy);
+			let ray = if camera.aperture() > 0. {
+			    camera.get_lens_ray(coords, (rng.next_f64(), rng.next_f64()))
+			} else {
+			    camera.get_ray(coords)
+			};
+			let color = match ray {
+			    Some(ray) => f(ray, &mut rng),
+			    None => Color::default(),
+			};
+			(sum.add(color.mul_float(w)), weight + w)
+		    });
+		// Negative lobes of a filter may cancel out all the weight
+		let color = if weight.abs() > f64::EPSILON { sum.div(weight) } else { Color::default() };
+		*pixel = pixel.add(color.sub(*pixel).div(pass as f64 + 1.));
 	    });
     }
 }
@@ -150,6 +762,185 @@ impl Pixel {
 
 impl Default for Pixel {
     fn default() -> Self {
-        Color::default().pixel()
+	Color::default().pixel()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::object;
+
+    fn scene() -> Scene {
+	let mut scene = Scene::new(8, 6);
+	scene.add(object::Sphere::new(Vec3(0., 0.5, 1.), 0.5, Material::new(Color::new(200, 100, 50), 0.)));
+	scene.add_light(AmbientLight::new(1.));
+	scene
+    }
+
+    #[test]
+    fn progressive_passes() {
+	let mut rt = Raytracer::new(scene());
+	rt.render_progressive();
+	assert!(rt.converged());
+
+	rt.scene.integrator = Integrator::Path { max_depth: 2 };
+	assert!(!rt.converged());
+	rt.render_progressive();
+	rt.render_progressive();
+	assert_eq!(rt.passes(), 2);
+
+	rt.scene.camera.transform(CameraTransform::Move(0.1));
+	rt.render_progressive();
+	assert_eq!(rt.passes(), 1);
+	rt.render();
+	assert_eq!(rt.passes(), 1);
+    }
+
+    #[test]
+    fn point_light_paths() {
+	let mut scene = Scene::new(8, 6);
+	scene.add(object::Plane::new(Vec3(0., 1., 0.), 0., Material::new(Color::white(), 0.)));
+	scene.add_light(PointLight::new(Vec3(1., 2., 0.), 1.));
+	let rt = Raytracer::new(scene);
+
+	let down = || Ray::new(Vec3(0., 1., 0.), Vec3(0., -1., 0.));
+	let whitted = rt.trace(down(), 0, None, &mut Rng::new(0));
+	assert!(whitted.r > 0.05, "{:?}", whitted);
+	let path = rt.trace_path(down(), 1, &mut Rng::new(5));
+	assert!((path.r - whitted.r).abs() < 1e-9, "{:?} {:?}", path, whitted);
+    }
+
+    #[test]
+    fn glossy_reflections() {
+	// A mirror floor reflecting a glowing ball
+	let scene = |roughness| {
+	    let mut scene = Scene::new(8, 6);
+	    let mut mirror = Material::new(Color::white(), 1.);
+	    mirror.reflection_roughness = roughness;
+	    scene.add(object::Plane::new(Vec3(0., 1., 0.), 0., mirror));
+	    scene.add(object::Sphere::new(Vec3(0., 2., 2.), 0.3, Material::new_emissive(Color::white())));
+	    Raytracer::new(scene)
+	};
+	let ray = || Ray::new(Vec3(0., 1., -1.), Vec3(0., -1., 1.).norm());
+
+	let rt = scene(0.);
+	let int = rt.scene.nearest_intersection(&ray()).unwrap();
+	for (u, v) in [(0., 0.), (0.3, 0.7), (0.9, 0.2)] {
+	    let reflected = int.glossy_reflect(ray().direction, u, v).unwrap();
+	    assert_eq!((reflected.point, reflected.direction), (int.reflect.point, int.reflect.direction));
+	}
+	let sharp = rt.trace(ray(), 0, None, &mut Rng::new(0));
+	assert_eq!(sharp, rt.trace(Ray::new(int.reflect.point, int.reflect.direction), 1, None, &mut Rng::new(0)));
+	assert_eq!(sharp, Color::white());
+
+	// Rough reflections spread around the mirrored ray and blur the ball
+	let rt = scene(0.4);
+	let int = rt.scene.nearest_intersection(&ray()).unwrap();
+	let spread = (0..64)
+	    .filter_map(|i| {
+		let (u, v) = sampling::halton(i);
+		int.glossy_reflect(ray().direction, u, v)
+	    })
+	    .map(|reflected| reflected.direction.dot(int.reflect.direction))
+	    .fold(1., f64::min);
+	assert!(spread < 0.95, "{}", spread);
+	let blurred = rt.trace(ray(), 0, None, &mut Rng::new(0));
+	assert!(blurred.r > 0.05 && blurred.r < 0.9, "{:?}", blurred);
+	// Pixels shift the rays differently
+	assert_ne!(blurred, rt.trace(ray(), 0, None, &mut Rng::new(1)));
+    }
+
+    #[test]
+    fn emissive_objects() {
+	let mut scene = Scene::new(8, 6);
+	// Glowing square facing down onto a white floor
+	let glow = Material::new_emissive(Color::white());
+	let corners = [Vec3(-1., 2., -1.), Vec3(1., 2., -1.), Vec3(1., 2., 1.), Vec3(-1., 2., 1.)];
+	scene.add(object::Polygon::new(corners[0], corners[1], corners[2], glow.clone()));
+	scene.add(object::Polygon::new(corners[0], corners[2], corners[3], glow));
+	scene.add(object::Plane::new(Vec3(0., 1., 0.), 0., Material::new(Color::white(), 0.)));
+	assert_eq!(scene.lights.len(), 2);
+	let rt = Raytracer::new(scene);
+
+	assert_eq!(rt.trace(Ray::new(Vec3(0., 1., 0.), Vec3(0., 1., 0.)), 0, None, &mut Rng::new(0)), Color::white());
+	// Radiance of the floor below the center is the form factor of the
+	// square, about 0.24
+	let down = || Ray::new(Vec3(0., 1., 0.), Vec3(0., -1., 0.));
+	let whitted = rt.trace(down(), 0, None, &mut Rng::new(0)).r;
+	assert!((whitted - 0.24).abs() < 0.02, "{}", whitted);
+	let mut rng = Rng::new(5);
+	let count = 4000;
+	let path = (0..count).map(|_| rt.trace_path(down(), 1, &mut rng).r).sum::<f64>() / count as f64;
+	assert!((path - 0.24).abs() < 0.02, "{}", path);
+    }
+
+    #[test]
+    fn media() {
+	// A ball of pure absorber in front of a white background
+	let mut scene = Scene::new(8, 6);
+	let mut ink = Material::new(Color::white(), 0.);
+	ink.medium = Some(Arc::new(Medium::new(1., 0.)));
+	scene.add(object::Sphere::new(Vec3(0., 0., 0.), 1., ink));
+	let sky = super::super::image::Image::new(1, 1, vec![Color::white()]);
+	scene.add_light(EnvironmentLight::new(Arc::new(sky), 1.));
+	let rt = Raytracer::new(scene);
+	let through = || Ray::new(Vec3(0., 0., -3.), Vec3(0., 0., 1.));
+	let expected = (-2f64).exp();
+	let whitted = rt.trace(through(), 0, None, &mut Rng::new(0)).r;
+	assert!((whitted - expected).abs() < 1e-6, "{}", whitted);
+	let mut rng = Rng::new(2);
+	let count = 4000;
+	let path = (0..count).map(|_| rt.trace_path(through(), 0, &mut rng).r).sum::<f64>() / count as f64;
+	assert!((path - expected).abs() < 0.02, "{}", path);
+	let mut rng = Rng::new(3);
+	assert!((rt.scene.transmittance(&through(), 10., None, &mut rng) - expected).abs() < 1e-6);
+
+	// Fog lit by a point light scatters some of it towards the camera
+	let mut scene = Scene::new(8, 6);
+	scene.fog = Some(Medium::new(0., 0.1));
+	scene.add_light(PointLight::new(Vec3(0., 1., 2.), 10.));
+	let rt = Raytracer::new(scene);
+	let glow = rt.trace(through(), 0, rt.scene.fog.as_ref(), &mut Rng::new(0)).r;
+	assert!(glow > 0.01, "{}", glow);
+
+	// Sunlight falls through absorbing fog onto a floor, which ends at
+	// the top of the ball 3 above it
+	let mut scene = Scene::new(8, 6);
+	scene.fog = Some(Medium::new(0.1, 0.));
+	scene.add(object::Plane::new(Vec3(0., 1., 0.), 0., Material::new(Color::white(), 0.)));
+	scene.add(object::Sphere::new(Vec3(3., 1., 0.), 2., Material::new(Color::white(), 0.)));
+	scene.add_light(DirectLight::new(Vec3(0., -1., 0.), 1.));
+	let rt = Raytracer::new(scene);
+	let down = || Ray::new(Vec3(1.2, 1., 1.8), Vec3(0., -1., 0.));
+	let expected = rt.trace(down(), 0, None, &mut Rng::new(0)).r * (-0.4f64).exp();
+	let whitted = rt.trace(down(), 0, rt.scene.fog.as_ref(), &mut Rng::new(0)).r;
+	assert!((whitted - expected).abs() < 1e-6, "{}", whitted);
+	let mut rng = Rng::new(4);
+	let path = (0..count).map(|_| rt.trace_path(down(), 0, &mut rng).r).sum::<f64>() / count as f64;
+	assert!((path - expected).abs() < 0.02, "{}", path);
+    }
+
+    #[test]
+    fn subsurface() {
+	// Under even white light a translucent ball shows about its color,
+	// whatever the light does inside
+	let furnace = |color: Color| {
+	    let mut scene = Scene::new(8, 6);
+	    scene.add(object::Sphere::new(Vec3(0., 0., 0.), 1., Material::new_subsurface(color, Color::rgb(0.2, 0.1, 0.05), 0.5)));
+	    let sky = super::super::image::Image::new(1, 1, vec![Color::white()]);
+	    scene.add_light(EnvironmentLight::new(Arc::new(sky), 1.));
+	    let rt = Raytracer::new(scene);
+	    let mut rng = Rng::new(7);
+	    let count = 4000;
+	    (0..count).fold(Color::default(), |res, _| res.add(rt.trace_path(Ray::new(Vec3(0., 0., -3.), Vec3(0., 0., 1.)), 8, &mut rng))).div(count as f64)
+	};
+	let white = furnace(Color::white());
+	for c in [white.r, white.g, white.b] {
+	    assert!((c - 1.).abs() < 0.05, "{:?}", white);
+	}
+	let tinted = furnace(Color::rgb(0.8, 0.4, 0.2));
+	assert!(tinted.r > tinted.g && tinted.g > tinted.b, "{:?}", tinted);
+	assert!((tinted.g - 0.4).abs() < 0.15, "{:?}", tinted);
     }
 }
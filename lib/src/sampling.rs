@@ -0,0 +1,429 @@
+//! Random and low-discrepancy sampling: placement and weighting of
+//! several camera rays inside a pixel, directions picked around normals,
+//! facets, phase functions and cones, and discrete distributions for
+//! importance sampling lights.
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+use super::math::Vec3;
+
+/// Small deterministic generator (SplitMix64), every pixel seeds its own
+/// so renders repeat exactly regardless of thread scheduling.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+	Rng(seed)
+    }
+
+    /// Generator for the pixel at `(x, y)` in the given rendering pass.
+    pub fn for_pixel(x: u32, y: u32, pass: u32) -> Self {
+	let mut rng = Rng::new(((y as u64) << 32) | x as u64);
+	rng.0 ^= Rng::new(pass as u64).next_u64();
+	rng.next_u64();
+	rng
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+	self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+	let mut z = self.0;
+	z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+	z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+	z ^ (z >> 31)
+    }
+
+    /// Uniform value in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+	(self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Two unit vectors perpendicular to `n` and each other.
+pub fn basis(n: Vec3) -> (Vec3, Vec3) {
+    let a = if n.0.abs() > 0.9 { Vec3(0., 1., 0.) } else { Vec3(1., 0., 0.) };
+    let t = n.cross(a).norm();
+    (t, n.cross(t))
+}
+
+/// Direction around the unit vector `axis` with the given cosine to it.
+fn around(axis: Vec3, cos: f64, phi: f64) -> Vec3 {
+    let (t, b) = basis(axis);
+    let sin = (1. - cos * cos).max(0.).sqrt();
+    t * (sin * phi.cos()) + b * (sin * phi.sin()) + axis * cos
+}
+
+/// Direction in the hemisphere of the unit normal `n` with the density
+/// `cos / π` to it.
+pub fn cosine_hemisphere(n: Vec3, rng: &mut Rng) -> Vec3 {
+    let (u, v) = (rng.next_f64(), rng.next_f64());
+    around(n, (1. - u).sqrt(), 2. * PI * v)
+}
+
+/// Facet normal around the unit normal `n` for a point `(u, v)` in
+/// `[0, 1)²`, with the density `D · cos` to it where `D` is the GGX
+/// distribution of roughness `alpha`.
+pub fn ggx_normal(n: Vec3, alpha: f64, u: f64, v: f64) -> Vec3 {
+    let cos = ((1. - u) / (1. + (alpha * alpha - 1.) * u)).sqrt();
+    around(n, cos, 2. * PI * v)
+}
+
+/// Direction of light scattered from travelling along the unit vector
+/// `axis` for a point `(u, v)` in `[0, 1)²`, following the
+/// Henyey-Greenstein phase function of asymmetry `g`.
+pub fn henyey_greenstein(axis: Vec3, g: f64, u: f64, v: f64) -> Vec3 {
+    let cos = if g.abs() < 1e-3 {
+	1. - 2. * u
+    } else {
+	let s = (1. - g * g) / (1. - g + 2. * g * u);
+	(1. + g * g - s * s) / (2. * g)
+    };
+    around(axis, cos.clamp(-1., 1.), 2. * PI * v)
+}
+
+/// Direction within the angle of cosine `cos_max` around the unit vector
+/// `axis` for a point `(u, v)` in `[0, 1)²`, uniform over the solid
+/// angle.
+pub fn uniform_cone(axis: Vec3, cos_max: f64, u: f64, v: f64) -> Vec3 {
+    around(axis, 1. - u * (1. - cos_max), 2. * PI * v)
+}
+
+/// Piecewise constant density over `[0, 1)` with one step per weight.
+#[derive(Clone, Debug)]
+pub struct Distribution {
+    /// Running sums of the weights, starting with `0.`.
+    cdf: Vec<f64>,
+}
+
+impl Distribution {
+    /// `None` when no weight is positive.
+    pub fn new(weights: &[f64]) -> Option<Self> {
+	let mut cdf = Vec::with_capacity(weights.len() + 1);
+	cdf.push(0.);
+	for w in weights {
+	    cdf.push(cdf.last().unwrap() + w.max(0.));
+	}
+	if *cdf.last().unwrap() <= 0. {
+	    return None;
+	}
+	Some(Self { cdf })
+    }
+
+    /// Sum of the weights.
+    pub fn total(&self) -> f64 {
+	*self.cdf.last().unwrap()
+    }
+
+    /// Maps a uniform `u` in `[0, 1)` to a point following the density,
+    /// returns it with the index of its step.
+    pub fn sample(&self, u: f64) -> (f64, usize) {
+	let target = u * self.total();
+	let i = (self.cdf.partition_point(|&c| c <= target) - 1).min(self.cdf.len() - 2);
+	let (a, b) = (self.cdf[i], self.cdf[i + 1]);
+	let t = if b > a { (target - a) / (b - a) } else { 0. };
+	((i as f64 + t) / (self.cdf.len() - 1) as f64, i)
+    }
+
+    /// Density at the point `x` in `[0, 1)`.
+    pub fn pdf(&self, x: f64) -> f64 {
+	let n = self.cdf.len() - 1;
+	let i = ((x * n as f64) as usize).min(n - 1);
+	(self.cdf[i + 1] - self.cdf[i]) * n as f64 / self.total()
+    }
+}
+
+/// Pattern of sample positions inside a pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sampler {
+    /// Centers of a regular grid of cells.
+    Grid,
+    /// A random point in every grid cell.
+    Jittered,
+    /// Halton sequence in bases 2 and 3.
+    Halton,
+    /// Sobol sequence.
+    Sobol,
+}
+
+impl Sampler {
+    /// Positions of `count` samples in `[0, 1)²`.
+    pub fn samples(self, count: u32, rng: &mut Rng) -> Vec<(f64, f64)> {
+	match self {
+	    Sampler::Grid | Sampler::Jittered => {
+		// Cells of the last row are left out when the count is not
+		// a product of the grid sides
+		let cols = (count as f64).sqrt().ceil().max(1.) as u32;
+		let rows = count.div_ceil(cols);
+		(0..count).map(|i| {
+		    let (jx, jy) = match self {
+			Sampler::Jittered => (rng.next_f64(), rng.next_f64()),
+			_ => (0.5, 0.5),
+		    };
+		    (((i % cols) as f64 + jx) / cols as f64, ((i / cols) as f64 + jy) / rows as f64)
+		}).collect()
+	    },
+	    Sampler::Halton => {
+		// Random shift decorrelates neighbouring pixels
+		let (sx, sy) = (rng.next_f64(), rng.next_f64());
+		(0..count).map(|i| {
+		    let (u, v) = halton(i);
+		    ((u + sx).fract(), (v + sy).fract())
+		}).collect()
+	    },
+	    Sampler::Sobol => {
+		// Random digit scrambling keeps the stratification
+		let (sx, sy) = (rng.next_u64() as u32, rng.next_u64() as u32);
+		(0..count).map(|i| {
+		    (unit(i.reverse_bits() ^ sx), unit(sobol_second(i) ^ sy))
+		}).collect()
+	    },
+	}
+    }
+}
+
+/// Point `i` of the Halton sequence in bases 2 and 3, spreads a fixed
+/// number of points evenly over `[0, 1)²`.
+pub fn halton(i: u32) -> (f64, f64) {
+    (radical_inverse(i + 1, 2), radical_inverse(i + 1, 3))
+}
+
+fn radical_inverse(mut i: u32, base: u32) -> f64 {
+    let inv = 1. / base as f64;
+    let mut f = inv;
+    let mut res = 0.;
+    while i > 0 {
+	res += (i % base) as f64 * f;
+	i /= base;
+	f *= inv;
+    }
+    res
+}
+
+/// Second dimension of the Sobol sequence, direction numbers come from
+/// the polynomial `x + 1`.
+fn sobol_second(mut i: u32) -> u32 {
+    let mut v = 1 << 31;
+    let mut res = 0;
+    while i > 0 {
+	if i & 1 == 1 {
+	    res ^= v;
+	}
+	i >>= 1;
+	v ^= v >> 1;
+    }
+    res
+}
+
+fn unit(bits: u32) -> f64 {
+    bits as f64 / (1u64 << 32) as f64
+}
+
+impl FromStr for Sampler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	match s {
+	    "grid" => Ok(Sampler::Grid),
+	    "jittered" => Ok(Sampler::Jittered),
+	    "halton" => Ok(Sampler::Halton),
+	    "sobol" => Ok(Sampler::Sobol),
+	    _ => Err(format!("unknown sampler `{}`, expected one of `grid`, `jittered`, `halton`, `sobol`", s)),
+	}
+    }
+}
+
+impl fmt::Display for Sampler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	f.write_str(match self {
+	    Sampler::Grid => "grid",
+	    Sampler::Jittered => "jittered",
+	    Sampler::Halton => "halton",
+	    Sampler::Sobol => "sobol",
+	})
+    }
+}
+
+/// Reconstruction filter weighting samples by their distance to the
+/// pixel center.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    /// Mitchell-Netravali with `B = C = 1/3`.
+    Mitchell,
+}
+
+impl Filter {
+    /// Half width of the filter footprint in pixels.
+    pub fn radius(self) -> f64 {
+	match self {
+	    Filter::Box => 0.5,
+	    Filter::Tent => 1.,
+	    Filter::Gaussian => 1.5,
+	    Filter::Mitchell => 2.,
+	}
+    }
+
+    /// Weight of a sample `(dx, dy)` pixels away from the center.
+    pub fn weight(self, dx: f64, dy: f64) -> f64 {
+	self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(self, x: f64) -> f64 {
+	let x = x.abs();
+	let r = self.radius();
+	if x > r {
+	    return 0.;
+	}
+	match self {
+	    Filter::Box => 1.,
+	    Filter::Tent => r - x,
+	    Filter::Gaussian => {
+		const ALPHA: f64 = 2.;
+		((-ALPHA * x * x).exp() - (-ALPHA * r * r).exp()).max(0.)
+	    },
+	    Filter::Mitchell => {
+		const B: f64 = 1. / 3.;
+		const C: f64 = 1. / 3.;
+		let x = x * 2. / r;
+		if x < 1. {
+		    ((12. - 9. * B - 6. * C) * x * x * x + (-18. + 12. * B + 6. * C) * x * x + (6. - 2. * B)) / 6.
+		} else {
+		    ((-B - 6. * C) * x * x * x + (6. * B + 30. * C) * x * x + (-12. * B - 48. * C) * x + (8. * B + 24. * C)) / 6.
+		}
+	    },
+	}
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	match s {
+	    "box" => Ok(Filter::Box),
+	    "tent" => Ok(Filter::Tent),
+	    "gaussian" => Ok(Filter::Gaussian),
+	    "mitchell" => Ok(Filter::Mitchell),
+	    _ => Err(format!("unknown filter `{}`, expected one of `box`, `tent`, `gaussian`, `mitchell`", s)),
+	}
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	f.write_str(match self {
+	    Filter::Box => "box",
+	    Filter::Tent => "tent",
+	    Filter::Gaussian => "gaussian",
+	    Filter::Mitchell => "mitchell",
+	})
+    }
+}
+
+/// How many rays are traced per pixel and how they are combined.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sampling {
+    pub samples: u32,
+    pub sampler: Sampler,
+    pub filter: Filter,
+}
+
+impl Default for Sampling {
+    /// One ray through the pixel center.
+    fn default() -> Self {
+	Sampling::new(1, Sampler::Grid, Filter::Box)
+    }
+}
+
+impl Sampling {
+    pub fn new(samples: u32, sampler: Sampler, filter: Filter) -> Self {
+	Sampling { samples, sampler, filter }
+    }
+
+    /// Positions of the samples of a pixel relative to its top left
+    /// corner together with their filter weights.
+    pub fn pixel_samples(&self, rng: &mut Rng) -> Vec<((f64, f64), f64)> {
+	let r = self.filter.radius();
+	self.sampler.samples(self.samples, rng).into_iter()
+	    .map(|(u, v)| {
+		let (dx, dy) = ((u * 2. - 1.) * r, (v * 2. - 1.) * r);
+		((0.5 + dx, 0.5 + dy), self.filter.weight(dx, dy))
+	    })
+	    .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samplers_stratify() {
+	let mut rng = Rng::new(1);
+	for sampler in [Sampler::Grid, Sampler::Jittered, Sampler::Halton, Sampler::Sobol] {
+	    assert_eq!(sampler.to_string().parse(), Ok(sampler));
+	    let samples = sampler.samples(16, &mut rng);
+	    assert_eq!(samples.len(), 16);
+	    // Every quarter of the pixel gets the same share
+	    let mut quarters = [0; 4];
+	    for (u, v) in samples {
+		assert!((0. ..1.).contains(&u) && (0. ..1.).contains(&v), "{} out of the pixel", sampler);
+		quarters[(u * 2.) as usize + 2 * (v * 2.) as usize] += 1;
+	    }
+	    if sampler != Sampler::Halton {
+		assert_eq!(quarters, [4; 4], "{}", sampler);
+	    }
+	}
+	assert_eq!(Sampler::Grid.samples(1, &mut rng), vec![(0.5, 0.5)]);
+    }
+
+    #[test]
+    fn filters() {
+	for filter in [Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell] {
+	    assert_eq!(filter.to_string().parse(), Ok(filter));
+	    let r = filter.radius();
+	    assert!(filter.weight(0., 0.) > 0.);
+	    assert!(filter.weight(r / 4., 0.) <= filter.weight(0., 0.));
+	    assert!(filter.weight(r * 1.01, 0.).abs() < 1e-12);
+	    assert!(filter.weight(r, r).abs() < 1e-12 || filter == Filter::Box);
+	}
+    }
+
+    #[test]
+    fn hemisphere() {
+	let mut rng = Rng::new(7);
+	let n = Vec3(0., 0.6, 0.8);
+	let mut cos_sum = 0.;
+	for _ in 0..10000 {
+	    let d = cosine_hemisphere(n, &mut rng);
+	    assert!((d.len() - 1.).abs() < 1e-9);
+	    assert!(d.dot(n) >= 0.);
+	    cos_sum += d.dot(n);
+	}
+	// Mean cosine of the density is 2/3
+	assert!((cos_sum / 10000. - 2. / 3.).abs() < 0.01);
+
+	for _ in 0..100 {
+	    assert!(ggx_normal(n, 0.01, rng.next_f64(), rng.next_f64()).dot(n) > 0.99);
+	}
+    }
+
+    #[test]
+    fn distribution() {
+	assert!(Distribution::new(&[0., 0.]).is_none());
+	let d = Distribution::new(&[1., 0., 3.]).unwrap();
+	assert_eq!(d.total(), 4.);
+	assert_eq!(d.sample(0.), (0., 0));
+	assert_eq!(d.sample(0.125), (1. / 6., 0));
+	let (x, i) = d.sample(0.5);
+	assert_eq!(i, 2);
+	assert!((x - 2. / 3. - 1. / 9.).abs() < 1e-12);
+	assert!((d.pdf(0.1) - 0.75).abs() < 1e-12);
+	assert_eq!(d.pdf(0.5), 0.);
+	assert!((d.pdf(x) - 2.25).abs() < 1e-12);
+    }
+}
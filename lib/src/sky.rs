@@ -0,0 +1,164 @@
+//! Clear sky after Preetham, Shirley and Smits, "A Practical Analytic
+//! Model for Daylight".
+//!
+//! Radiance is given in units of 10 kcd/m², which puts a clear noon sky
+//! around `1.`. The sky is baked into an environment map that lights
+//! the scene, the sun is a separate light so its small disk stays sharp.
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::image::Image;
+use super::math::Vec3;
+use super::object::{Color, EnvironmentLight, SunLight};
+
+/// Angular diameter of the sun seen from earth in degrees.
+pub const SUN_SIZE: f64 = 0.53;
+
+/// Irradiance of sunlight outside of the atmosphere in the same units as
+/// the sky radiance.
+const SUN_IRRADIANCE: f64 = 12.;
+
+/// Size of the environment map the sky is baked into.
+const MAP_WIDTH: usize = 256;
+const MAP_HEIGHT: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sky {
+    /// Unit vector towards the sun, above the horizon.
+    sun: Vec3,
+    /// Haziness of the air, `2.` for a very clear sky up to `10.` for a
+    /// hazy one.
+    pub turbidity: f64,
+    /// Reflectance of the ground lit by the sky and the sun, shown below
+    /// the horizon.
+    pub ground_albedo: f64,
+    /// Angular diameter of the sun disk in degrees.
+    pub sun_size: f64,
+}
+
+impl Sky {
+    /// Sky with the sun at `elevation` above the horizon and `azimuth`
+    /// from `+z` towards `+x`, both in radians.
+    pub fn new(elevation: f64, azimuth: f64) -> Self {
+	let elevation = elevation.clamp(0., PI / 2.);
+	Self {
+	    sun: Vec3(elevation.cos() * azimuth.sin(), elevation.sin(), elevation.cos() * azimuth.cos()),
+	    turbidity: 3.,
+	    ground_albedo: 0.3,
+	    sun_size: SUN_SIZE,
+	}
+    }
+
+    /// Radiance of the sky towards the unit vector `dir` above the
+    /// horizon, without the sun.
+    pub fn radiance(&self, dir: Vec3) -> Color {
+	let t = self.turbidity;
+	let theta_sun = self.sun.1.clamp(-1., 1.).acos();
+	// The model breaks down right at the horizon
+	let cos_theta = dir.1.max(0.01);
+	let gamma = dir.dot(self.sun).clamp(-1., 1.).acos();
+	let perez = |[a, b, c, d, e]: [f64; 5], cos_theta: f64, gamma: f64| {
+	    (1. + a * (b / cos_theta).exp()) * (1. + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+	};
+	let relative = |coefs: [f64; 5]| perez(coefs, cos_theta, gamma) / perez(coefs, 1., theta_sun);
+
+	let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_sun);
+	let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.);
+	let cubic = |[a, b, c, d]: [f64; 4]| ((a * theta_sun + b) * theta_sun + c) * theta_sun + d;
+	let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.])
+	    + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+	    + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+	let zenith_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.])
+	    + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+	    + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+	let luminance = zenith_luminance * relative([
+	    0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703,
+	]) / 10.;
+	let x = zenith_x * relative([
+	    -0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452,
+	]);
+	let y = zenith_y * relative([
+	    -0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529,
+	]);
+	xyy_to_rgb(x, y, luminance)
+    }
+
+    /// Radiance of the sun disk after passing through the atmosphere.
+    pub fn sun_radiance(&self) -> Color {
+	let elevation = self.sun.1.clamp(0., 1.).asin().to_degrees();
+	// Relative length of the path through the air after Kasten and
+	// Young
+	let air_mass = 1. / (self.sun.1.max(0.) + 0.50572 * (elevation + 6.07995).powf(-1.6364));
+	let beta = 0.04608 * self.turbidity - 0.04586;
+	// Rayleigh and aerosol optical depth at wavelengths in µm
+	let transmittance = |lambda: f64| (-air_mass * (0.008735 * lambda.powf(-4.08) + beta * lambda.powf(-1.3))).exp();
+	let solid_angle = 2. * PI * (1. - (self.sun_size.to_radians() / 2.).cos());
+	Color::rgb(transmittance(0.65), transmittance(0.55), transmittance(0.45)).mul_float(SUN_IRRADIANCE / solid_angle)
+    }
+
+    /// Sky baked into an environment map, the ground below the horizon
+    /// reflects the light it gets from above.
+    pub fn environment(&self, intensity: f64) -> EnvironmentLight {
+	let direction = |x: usize, y: usize| {
+	    let (theta, phi) = (PI * (y as f64 + 0.5) / MAP_HEIGHT as f64, 2. * PI * ((x as f64 + 0.5) / MAP_WIDTH as f64 - 0.5));
+	    Vec3(theta.sin() * phi.sin(), theta.cos(), theta.sin() * phi.cos())
+	};
+	let mut pixels = vec![Color::default(); MAP_WIDTH * MAP_HEIGHT];
+	let mut irradiance = self.sun_radiance().mul_float(2. * PI * (1. - (self.sun_size.to_radians() / 2.).cos()) * self.sun.1);
+	for y in 0..MAP_HEIGHT / 2 {
+	    for x in 0..MAP_WIDTH {
+		let dir = direction(x, y);
+		let radiance = self.radiance(dir);
+		let solid_angle = 2. * PI * PI * (1. - dir.1 * dir.1).sqrt() / (MAP_WIDTH * MAP_HEIGHT) as f64;
+		irradiance = irradiance.add(radiance.mul_float(dir.1 * solid_angle));
+		pixels[y * MAP_WIDTH + x] = radiance;
+	    }
+	}
+	let ground = irradiance.mul_float(self.ground_albedo / PI);
+	pixels[MAP_HEIGHT / 2 * MAP_WIDTH..].iter_mut().for_each(|p| *p = ground);
+	EnvironmentLight::new(Arc::new(Image::new(MAP_WIDTH, MAP_HEIGHT, pixels)), intensity)
+    }
+
+    pub fn sun(&self, intensity: f64) -> SunLight {
+	SunLight::new(self.sun, self.sun_size.to_radians() / 2., self.sun_radiance().mul_float(intensity))
+    }
+}
+
+/// Linear sRGB of a CIE xyY color.
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0. {
+	return Color::default();
+    }
+    let (cx, cz) = (x / y * luminance, (1. - x - y) / y * luminance);
+    let cy = luminance;
+    Color::rgb(
+	3.2406 * cx - 1.5372 * cy - 0.4986 * cz,
+	-0.9689 * cx + 1.8758 * cy + 0.0415 * cz,
+	0.0557 * cx - 0.2040 * cy + 1.0570 * cz,
+    ).max(Color::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clear_sky() {
+	let sky = Sky::new(30f64.to_radians(), 0.);
+	let zenith = sky.radiance(Vec3(0., 1., 0.));
+	assert!((0.3..0.8).contains(&zenith.luminance()), "zenith {:?}", zenith);
+	// Blue overhead, brighter towards the sun
+	assert!(zenith.b > zenith.r);
+	let near_sun = sky.radiance(Vec3(0., 0.6, 0.8).norm());
+	let away = sky.radiance(Vec3(0., 0.6, -0.8).norm());
+	assert!(near_sun.luminance() > 2. * away.luminance());
+
+	// Low sun gets redder
+	let noon = sky.sun_radiance();
+	let evening = Sky::new(3f64.to_radians(), 0.).sun_radiance();
+	assert!(evening.r / evening.b > noon.r / noon.b);
+	assert!(evening.luminance() < noon.luminance());
+    }
+}
@@ -0,0 +1,318 @@
+//! Colors varying over surfaces: images mapped by texture coordinates
+//! and procedural patterns.
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::image::Image;
+use super::math::{Point3, Vec3};
+use super::noise;
+use super::object::Color;
+
+pub trait Texture {
+    /// Color at texture coordinates `uv` of the surface point `point`.
+    fn color(&self, uv: (f64, f64), point: Point3) -> Color;
+}
+
+/// How coordinates outside of `[0, 1]` map onto the image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wrap {
+    /// Tiles the image.
+    Repeat,
+    /// Tiles the image flipping every other copy, so edges meet.
+    Mirror,
+    /// Extends the edge pixels.
+    Clamp,
+}
+
+impl Wrap {
+    /// Pixel `i` of a row or column of `n` pixels.
+    fn index(self, i: i64, n: usize) -> usize {
+	let n = n as i64;
+	(match self {
+	    Wrap::Repeat => i.rem_euclid(n),
+	    Wrap::Mirror => {
+		let i = i.rem_euclid(2 * n);
+		if i < n { i } else { 2 * n - 1 - i }
+	    },
+	    Wrap::Clamp => i.clamp(0, n - 1),
+	}) as usize
+    }
+}
+
+impl FromStr for Wrap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	match s {
+	    "repeat" => Ok(Wrap::Repeat),
+	    "mirror" => Ok(Wrap::Mirror),
+	    "clamp" => Ok(Wrap::Clamp),
+	    _ => Err(format!("unknown wrap mode `{}`, expected one of `repeat`, `mirror`, `clamp`", s)),
+	}
+    }
+}
+
+impl fmt::Display for Wrap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	f.write_str(match self {
+	    Wrap::Repeat => "repeat",
+	    Wrap::Mirror => "mirror",
+	    Wrap::Clamp => "clamp",
+	})
+    }
+}
+
+/// Lookup of colors between pixel centers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    /// Color of the closest pixel.
+    Nearest,
+    /// Blend of the four closest pixels.
+    Bilinear,
+}
+
+impl FromStr for Interpolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	match s {
+	    "nearest" => Ok(Interpolation::Nearest),
+	    "bilinear" => Ok(Interpolation::Bilinear),
+	    _ => Err(format!("unknown interpolation `{}`, expected one of `nearest`, `bilinear`", s)),
+	}
+    }
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	f.write_str(match self {
+	    Interpolation::Nearest => "nearest",
+	    Interpolation::Bilinear => "bilinear",
+	})
+    }
+}
+
+pub struct ImageTexture {
+    image: Arc<Image>,
+    pub wrap: Wrap,
+    pub interpolation: Interpolation,
+    /// Copies of the image per unit of texture coordinates along `u` and
+    /// `v`.
+    pub scale: (f64, f64),
+}
+
+impl ImageTexture {
+    /// Repeated bilinearly filtered image.
+    pub fn new(image: Arc<Image>) -> Self {
+	Self { image, wrap: Wrap::Repeat, interpolation: Interpolation::Bilinear, scale: (1., 1.) }
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> Color {
+	self.image.get(self.wrap.index(x, self.image.width()), self.wrap.index(y, self.image.height()))
+    }
+}
+
+impl Texture for ImageTexture {
+    /// `(0, 0)` is the bottom left corner of the image.
+    fn color(&self, (u, v): (f64, f64), _point: Point3) -> Color {
+	// Pixel centers lie at half coordinates
+	let x = u * self.scale.0 * self.image.width() as f64 - 0.5;
+	let y = (1. - v * self.scale.1) * self.image.height() as f64 - 0.5;
+	match self.interpolation {
+	    Interpolation::Nearest => self.pixel(x.round() as i64, y.round() as i64),
+	    Interpolation::Bilinear => {
+		let (x0, y0) = (x.floor(), y.floor());
+		let (tx, ty) = (x - x0, y - y0);
+		let (x0, y0) = (x0 as i64, y0 as i64);
+		let top = self.pixel(x0, y0).mul_float(1. - tx).add(self.pixel(x0 + 1, y0).mul_float(tx));
+		let bottom = self.pixel(x0, y0 + 1).mul_float(1. - tx).add(self.pixel(x0 + 1, y0 + 1).mul_float(tx));
+		top.mul_float(1. - ty).add(bottom.mul_float(ty))
+	    },
+	}
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    /// Alternating unit cubes, or squares of texture coordinates.
+    Checker,
+    /// Smooth Perlin noise.
+    Noise,
+    /// Layered noise with finer detail.
+    Fbm,
+    /// Layered absolute noise, billowy with sharp creases.
+    Turbulence,
+    /// Veins along `x` bent by turbulence.
+    Marble,
+    /// Rings around the `y` axis disturbed by noise.
+    Wood,
+    /// Distance to points scattered one per unit cube, giving cells.
+    Voronoi,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	match s {
+	    "checker" => Ok(Pattern::Checker),
+	    "noise" => Ok(Pattern::Noise),
+	    "fbm" => Ok(Pattern::Fbm),
+	    "turbulence" => Ok(Pattern::Turbulence),
+	    "marble" => Ok(Pattern::Marble),
+	    "wood" => Ok(Pattern::Wood),
+	    "voronoi" => Ok(Pattern::Voronoi),
+	    _ => Err(format!("unknown pattern `{}`, expected one of `checker`, `noise`, `fbm`, `turbulence`, `marble`, `wood`, `voronoi`", s)),
+	}
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	f.write_str(match self {
+	    Pattern::Checker => "checker",
+	    Pattern::Noise => "noise",
+	    Pattern::Fbm => "fbm",
+	    Pattern::Turbulence => "turbulence",
+	    Pattern::Marble => "marble",
+	    Pattern::Wood => "wood",
+	    Pattern::Voronoi => "voronoi",
+	})
+    }
+}
+
+/// Where a procedural pattern is evaluated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mapping {
+    /// At the point in space, as if carved from a solid block.
+    Solid,
+    /// At the texture coordinates `(u, v, 0)`.
+    Uv,
+}
+
+impl FromStr for Mapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	match s {
+	    "solid" => Ok(Mapping::Solid),
+	    "uv" => Ok(Mapping::Uv),
+	    _ => Err(format!("unknown mapping `{}`, expected one of `solid`, `uv`", s)),
+	}
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	f.write_str(match self {
+	    Mapping::Solid => "solid",
+	    Mapping::Uv => "uv",
+	})
+    }
+}
+
+/// Pattern blending between two colors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Procedural {
+    pub pattern: Pattern,
+    pub colors: [Color; 2],
+    /// Features of the pattern per unit of length or texture coordinates.
+    pub scale: f64,
+    /// Layers of noise of the `fbm`, `turbulence`, `marble` and `wood`
+    /// patterns.
+    pub octaves: u32,
+    pub mapping: Mapping,
+}
+
+impl Procedural {
+    pub fn new(pattern: Pattern, colors: [Color; 2]) -> Self {
+	Self { pattern, colors, scale: 1., octaves: 4, mapping: Mapping::Solid }
+    }
+
+    /// Share of the second color at `p`, in `[0, 1]`.
+    pub fn value(&self, p: Vec3) -> f64 {
+	let p = p * self.scale;
+	let octaves = self.octaves;
+	match self.pattern {
+	    Pattern::Checker => ((p.0.floor() + p.1.floor() + p.2.floor()) as i64).rem_euclid(2) as f64,
+	    Pattern::Noise => 0.5 * (noise::perlin(p) + 1.),
+	    Pattern::Fbm => 0.5 * (noise::fbm(p, octaves) + 1.),
+	    Pattern::Turbulence => noise::turbulence(p, octaves),
+	    Pattern::Marble => 0.5 * (1. + (PI * (p.0 + 4. * noise::turbulence(p, octaves))).sin()),
+	    Pattern::Wood => {
+		let ring = (p.0 * p.0 + p.2 * p.2).sqrt() + 0.4 * noise::fbm(p * 0.5, octaves);
+		ring.rem_euclid(1.)
+	    },
+	    Pattern::Voronoi => noise::voronoi(p).min(1.),
+	}
+    }
+}
+
+impl Texture for Procedural {
+    fn color(&self, uv: (f64, f64), point: Point3) -> Color {
+	let p = match self.mapping {
+	    Mapping::Solid => point,
+	    Mapping::Uv => Vec3(uv.0, uv.1, 0.),
+	};
+	let t = self.value(p);
+	self.colors[0].mul_float(1. - t).add(self.colors[1].mul_float(t))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+	let (black, white) = (Color::default(), Color::white());
+	let image = Arc::new(Image::new(2, 1, vec![black, white]));
+	let mut texture = ImageTexture::new(image);
+	let o = Vec3(0., 0., 0.);
+	assert_eq!(texture.color((0.25, 0.5), o), black);
+	assert_eq!(texture.color((0.5, 0.5), o), Color::rgb(0.5, 0.5, 0.5));
+	// Repeating blends the last pixel with the first one
+	assert_eq!(texture.color((1., 0.5), o), Color::rgb(0.5, 0.5, 0.5));
+	texture.wrap = Wrap::Clamp;
+	assert_eq!(texture.color((1., 0.5), o), white);
+	assert_eq!(texture.color((-3., 0.5), o), black);
+	texture.wrap = Wrap::Mirror;
+	assert_eq!(texture.color((1.25, 0.5), o), white);
+	texture.interpolation = Interpolation::Nearest;
+	assert_eq!(texture.color((0.45, 0.5), o), black);
+	texture.scale = (2., 1.);
+	assert_eq!(texture.color((0.45, 0.5), o), white);
+
+	for wrap in [Wrap::Repeat, Wrap::Mirror, Wrap::Clamp] {
+	    assert_eq!(wrap.to_string().parse(), Ok(wrap));
+	}
+    }
+
+    #[test]
+    fn procedural() {
+	let (black, white) = (Color::default(), Color::white());
+	let mut checker = Procedural::new(Pattern::Checker, [black, white]);
+	assert_eq!(checker.color((0., 0.), Vec3(0.5, 0.5, 0.5)), black);
+	assert_eq!(checker.color((0., 0.), Vec3(1.5, 0.5, -0.5)), black);
+	assert_eq!(checker.color((0., 0.), Vec3(1.5, 0.5, 0.5)), white);
+	checker.scale = 2.;
+	assert_eq!(checker.color((0., 0.), Vec3(0.75, 0.25, 0.25)), white);
+	checker.mapping = Mapping::Uv;
+	assert_eq!(checker.color((0.75, 0.75), Vec3(0.75, 0.25, 0.25)), black);
+
+	let points = (0..200).map(|i| Vec3(i as f64 * 0.13, (i % 7) as f64 * 0.29, i as f64 * -0.07));
+	for p in points {
+	    for pattern in [Pattern::Noise, Pattern::Fbm, Pattern::Turbulence, Pattern::Marble, Pattern::Wood, Pattern::Voronoi] {
+		let value = Procedural::new(pattern, [black, white]).value(p);
+		assert!((0. ..=1.).contains(&value), "{} at {:?}: {}", pattern, p, value);
+	    }
+	}
+
+	for pattern in [Pattern::Checker, Pattern::Marble, Pattern::Voronoi] {
+	    assert_eq!(pattern.to_string().parse(), Ok(pattern));
+	}
+    }
+}
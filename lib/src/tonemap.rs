@@ -0,0 +1,180 @@
+//! Conversion of traced radiance into displayable 8-bit pixels.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::object::Color;
+use super::raytracer::Pixel;
+
+/// Curve compressing radiance into `[0, 1]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    /// Values above `1.` are cut off.
+    Clip,
+    /// `x / (1 + x)` per channel.
+    Reinhard,
+    /// Narkowicz's fit of the ACES reference rendering transform.
+    Aces,
+    /// Hable's filmic curve from Uncharted 2.
+    Filmic,
+}
+
+impl Operator {
+    fn apply(self, x: f64) -> f64 {
+	match self {
+	    Operator::Clip => x,
+	    Operator::Reinhard => x / (1. + x),
+	    Operator::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+	    Operator::Filmic => {
+		const WHITE: f64 = 11.2;
+		const BIAS: f64 = 2.;
+		hable(x * BIAS) / hable(WHITE)
+	    },
+	}
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    const A: f64 = 0.15;
+    const B: f64 = 0.50;
+    const C: f64 = 0.10;
+    const D: f64 = 0.20;
+    const E: f64 = 0.02;
+    const F: f64 = 0.30;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+impl FromStr for Operator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	match s {
+	    "clip" => Ok(Operator::Clip),
+	    "reinhard" => Ok(Operator::Reinhard),
+	    "aces" => Ok(Operator::Aces),
+	    "filmic" => Ok(Operator::Filmic),
+	    _ => Err(format!("unknown tone mapping operator `{}`, expected one of `clip`, `reinhard`, `aces`, `filmic`", s)),
+	}
+    }
+}
+
+/// Transfer function from linear values to the stored ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Linear,
+    Srgb,
+}
+
+impl Encoding {
+    fn apply(self, x: f64) -> f64 {
+	match self {
+	    Encoding::Linear => x,
+	    Encoding::Srgb if x <= 0.0031308 => 12.92 * x,
+	    Encoding::Srgb => 1.055 * x.powf(1. / 2.4) - 0.055,
+	}
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+	match s {
+	    "linear" => Ok(Encoding::Linear),
+	    "srgb" => Ok(Encoding::Srgb),
+	    _ => Err(format!("unknown encoding `{}`, expected one of `linear`, `srgb`", s)),
+	}
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	f.write_str(match self {
+	    Operator::Clip => "clip",
+	    Operator::Reinhard => "reinhard",
+	    Operator::Aces => "aces",
+	    Operator::Filmic => "filmic",
+	})
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	f.write_str(match self {
+	    Encoding::Linear => "linear",
+	    Encoding::Srgb => "srgb",
+	})
+    }
+}
+
+/// Post-process stage between the traced buffer and pixels. The default
+/// clips values as images were shown before tone mapping existed and
+/// encodes them as sRGB, like the images read as textures and lights.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMap {
+    /// Exposure adjustment in stops, each one doubles the radiance.
+    pub exposure: f64,
+    pub operator: Operator,
+    pub encoding: Encoding,
+}
+
+impl Default for ToneMap {
+    fn default() -> Self {
+	ToneMap::new(Operator::Clip, Encoding::Srgb)
+    }
+}
+
+impl ToneMap {
+    pub fn new(operator: Operator, encoding: Encoding) -> Self {
+	ToneMap { exposure: 0., operator, encoding }
+    }
+
+    /// Display value of every channel in `[0, 1]`.
+    pub fn map(&self, color: Color) -> Color {
+	let scale = self.exposure.exp2();
+	let channel = |c: f64| {
+	    let c = self.operator.apply((c * scale).max(0.)).clamp(0., 1.);
+	    self.encoding.apply(c)
+	};
+	Color::rgb(channel(color.r), channel(color.g), channel(color.b))
+    }
+
+    pub fn pixel(&self, color: Color) -> Pixel {
+	self.map(color).pixel()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators() {
+	for op in [Operator::Clip, Operator::Reinhard, Operator::Aces, Operator::Filmic] {
+	    assert_eq!(op.to_string().parse(), Ok(op));
+	    assert!(op.apply(0.).abs() < 1e-9);
+	    let mut prev = 0.;
+	    for i in 1..100 {
+		let y = op.apply(i as f64 * 0.1).min(1.);
+		assert!(y >= prev, "{} is not monotonic", op);
+		prev = y;
+	    }
+	}
+	assert!((Operator::Filmic.apply(11.2 / 2.) - 1.).abs() < 1e-9);
+	assert!(Operator::Reinhard.apply(1e9) < 1.);
+    }
+
+    #[test]
+    fn srgb() {
+	let tm = ToneMap::new(Operator::Clip, Encoding::Srgb);
+	assert_eq!(tm.pixel(Color::rgb(0., 1., 2.)).1, 255);
+	assert_eq!(tm.pixel(Color::rgb(0.5, 0., 0.)).0, 188);
+	assert_eq!(tm.pixel(Color::rgb(0.0031308, 0., 0.)).0, 10);
+
+	assert_eq!(ToneMap::default().pixel(Color::rgb(0.5, 0., 0.)).0, 188);
+	let mut tm = ToneMap::new(Operator::Clip, Encoding::Linear);
+	assert_eq!(tm.pixel(Color::rgb(0.25, 0., 0.)).0, 64);
+	tm.exposure = 1.;
+	assert_eq!(tm.pixel(Color::rgb(0.25, 0., 0.)).0, 128);
+    }
+}
@@ -6,8 +6,8 @@ use winit_input_helper::WinitInputHelper;
 use pixels::{SurfaceTexture, Pixels};
 
 use lib::raytracer::*;
-use lib::object::*;
-use lib::math::{Vec3, CameraTransform};
+use lib::loader;
+use lib::math::CameraTransform;
 
 const SCREEN_WIDTH: u32 = 800;
 const SCREEN_HEIGHT: u32 = 600;
@@ -19,38 +19,10 @@ fn main() {
     let surface_texture = SurfaceTexture::new(width, height, &window);
     let mut pixels = Pixels::new(SCREEN_WIDTH, SCREEN_HEIGHT, surface_texture).unwrap();
 
-    let mut scene = Scene::new(SCREEN_WIDTH, SCREEN_HEIGHT);
-    scene.add(Polygon::new(
-	Vec3(-1., 0., 0.),
-	Vec3(1., 0., 0.),
-	Vec3(-1., 0., 1.),
-	Material::new(Color::new(0x00, 0xff, 0x00), 0.8)
-    ));
-    scene.add(Polygon::new(
-	Vec3(1., 0., 0.),
-	Vec3(-1., 0., 1.),
-	Vec3(1., 0., 1.),
-	Material::new(Color::new(0xff, 0x00, 0x00), 0.3)
-    ));
-    scene.add(Plane::new(
-	Vec3(0., -1., 0.), 0.,
-	Material::new(Color::new(0x50, 0x50, 0x50), 0.5)
-    ));
-    scene.add(Sphere::new(
-	Vec3(0., 0., 0.5), 0.5,
-	Material::new_shine(Color::new(0x00, 0x00, 0xff), 100, 0.6)
-    ));
-    scene.add(Sphere::new(
-	Vec3(-1.5, 0.2, 0.5), 0.2,
-	Material::new(Color::new(0xff, 0xff, 0x00), 0.1)
-    ));
-    scene.add(Sphere::new(
-	Vec3(1.50, 0.2, 0.5), 0.2,
-	Material::new_shine(Color::new(0xff, 0xff, 0x00), 10, 0.8)
-    ));
-    scene.add_light(PointLight::new(Vec3(0., 0.2, 2.), 1.));
-    scene.add_light(DirectLight::new(Vec3(0., -1., 1.), 0.2));
-    scene.add_light(AmbientLight::new(0.05));
+    let scene = match std::env::args().nth(1) {
+	Some(path) => loader::load_file(&path, SCREEN_WIDTH, SCREEN_HEIGHT),
+	None => loader::load(include_str!("../scenes/demo.toml"), SCREEN_WIDTH, SCREEN_HEIGHT),
+    }.unwrap_or_else(|e| panic!("Failed to load scene: {}", e));
     let mut raytracer = Raytracer::new(scene);
 
     let movement_keymap: Vec<(VirtualKeyCode, CameraTransform)> = vec![
@@ -70,8 +42,8 @@ fn main() {
 	}
 	if let Event::RedrawRequested(_) = event {
 	    let frame = pixels.get_frame();
-	    let canvas: &Canvas = raytracer.render();
-	    for (pixel, Pixel(r, g, b)) in frame.chunks_exact_mut(4).zip(canvas.iter().cloned()) {
+	    let canvas: &Canvas = raytracer.render_progressive();
+	    for (pixel, Pixel(r, g, b)) in frame.chunks_exact_mut(4).zip(canvas.pixels()) {
 		    pixel[0] = r;
 		    pixel[1] = g;
 		    pixel[2] = b;
@@ -79,6 +51,12 @@ fn main() {
 	    }
 	    pixels.render().unwrap();
 	}
+	// Keep refining the image while the camera stands still
+	if let Event::MainEventsCleared = event {
+	    if !raytracer.converged() {
+		window.request_redraw();
+	    }
+	}
 
 	if input.update(&event) {
 	    if let Some(size) = input.window_resized() {
@@ -3,8 +3,8 @@ use wasm_bindgen::JsCast;
 use wasm_bindgen::Clamped;
 
 use lib::raytracer::*;
-use lib::object::*;
-use lib::math::{Vec3, CameraTransform};
+use lib::loader;
+use lib::math::CameraTransform;
 
 use winit::window::{WindowBuilder};
 use winit::platform::web::{WindowExtWebSys, WindowBuilderExtWebSys};
@@ -39,38 +39,7 @@ pub fn main(canvas: web_sys::HtmlCanvasElement) {
         .build(&event_loop)
         .unwrap();
 
-    let mut scene = Scene::new(SCREEN_WIDTH, SCREEN_HEIGHT);
-    scene.add(Polygon::new(
-	Vec3(-1., 0., 0.),
-	Vec3(1., 0., 0.),
-	Vec3(-1., 0., 1.),
-	Material::new(Color::new(0x00, 0xff, 0x00), 0.8)
-    ));
-    scene.add(Polygon::new(
-	Vec3(1., 0., 0.),
-	Vec3(-1., 0., 1.),
-	Vec3(1., 0., 1.),
-	Material::new(Color::new(0xff, 0x00, 0x00), 0.3)
-    ));
-    scene.add(Plane::new(
-	Vec3(0., -1., 0.), 0.,
-	Material::new(Color::new(0x50, 0x50, 0x50), 0.5)
-    ));
-    scene.add(Sphere::new(
-	Vec3(0., 0., 0.5), 0.5,
-	Material::new_shine(Color::new(0x00, 0x00, 0xff), 8, 0.6)
-    ));
-    scene.add(Sphere::new(
-	Vec3(-1.5, 0.2, 0.5), 0.2,
-	Material::new(Color::new(0xff, 0xff, 0x00), 0.1)
-    ));
-    scene.add(Sphere::new(
-	Vec3(1.50, 0.2, 0.5), 0.2,
-	Material::new_shine(Color::new(0xff, 0xff, 0x00), 10, 0.8)
-    ));
-    scene.add_light(PointLight::new(Vec3(0., 0.5, 2.), 0.75));
-    scene.add_light(DirectLight::new(Vec3(0., -1., 1.), 0.2));
-    scene.add_light(AmbientLight::new(0.05));
+    let scene = loader::load(include_str!("../../scenes/web.toml"), SCREEN_WIDTH, SCREEN_HEIGHT).unwrap();
     let mut raytracer = Raytracer::new(scene);
 
     let movement_keymap: Vec<(VirtualKeyCode, CameraTransform)> = vec![
@@ -86,11 +55,17 @@ pub fn main(canvas: web_sys::HtmlCanvasElement) {
 
     event_loop.run(move |event, _, flow_control| {
 	if let Event::RedrawRequested(_) = event {
-	    let rt_canvas: &Canvas = raytracer.render();
-	    let mut rt_data: Vec<u8> = rt_canvas.iter().cloned().flat_map(|Pixel(r, g, b)| vec![r, g, b, 0xff].into_iter()).collect();
+	    let rt_canvas: &Canvas = raytracer.render_progressive();
+	    let mut rt_data: Vec<u8> = rt_canvas.pixels().flat_map(|Pixel(r, g, b)| vec![r, g, b, 0xff].into_iter()).collect();
 	    let data = web_sys::ImageData::new_with_u8_clamped_array_and_sh(Clamped(&mut rt_data), SCREEN_WIDTH, SCREEN_HEIGHT).unwrap();
 	    context.put_image_data(&data, 0.0, 0.0);
 	}
+	// Keep refining the image while the camera stands still
+	if let Event::MainEventsCleared = event {
+	    if !raytracer.converged() {
+		window.request_redraw();
+	    }
+	}
 
 	if input.update(&event) {
 	    for (key, mv) in movement_keymap.iter() {